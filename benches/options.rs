@@ -0,0 +1,92 @@
+//! Benchmarks for decoding, encoding and option access.
+//!
+//! Run with `cargo bench --bench options`. Decoding into the former
+//! `BTreeMap<u16, LinkedList<Vec<u8>>>` option storage is included as a
+//! baseline to compare against.
+
+use std::{
+    collections::{BTreeMap, LinkedList},
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use coap_lite::{
+    CoapOption, ContentFormat, MessageClass, Packet, PacketRef, RequestType,
+};
+
+/// Runs `f` repeatedly for about a second and prints the mean time per call.
+fn bench<F: FnMut()>(name: &str, mut f: F) {
+    for _ in 0..1000 {
+        f();
+    }
+
+    let start = Instant::now();
+    let mut iterations = 0u32;
+    while start.elapsed() < Duration::from_secs(1) {
+        for _ in 0..100 {
+            f();
+        }
+        iterations += 100;
+    }
+
+    let per_iteration = start.elapsed() / iterations;
+    println!("{:<32} {:>8} ns/iter", name, per_iteration.as_nanos());
+}
+
+/// Builds a request with the kind of options a gateway typically sees.
+fn sample_request() -> Packet {
+    let mut packet = Packet::new();
+    packet.header.code = MessageClass::Request(RequestType::Post);
+    packet.header.message_id = 4711;
+    packet.set_token(vec![0xCA, 0xFE, 0xBA, 0xBE]);
+    packet.add_option(CoapOption::UriHost, b"gateway.example".to_vec());
+    packet.add_option(CoapOption::ETag, vec![0x01, 0x02, 0x03, 0x04]);
+    packet.set_observe_value(0);
+    for segment in ["api", "v1", "devices", "3f2a", "telemetry"] {
+        packet.add_option(CoapOption::UriPath, segment.as_bytes().to_vec());
+    }
+    packet.set_content_format(ContentFormat::ApplicationCBOR);
+    packet.add_option(CoapOption::UriQuery, b"since=1700000000".to_vec());
+    packet.add_option(CoapOption::UriQuery, b"limit=100".to_vec());
+    packet.add_option(CoapOption::Accept, vec![60]);
+    packet.payload = vec![0xA5; 64];
+    packet
+}
+
+/// Decodes the options the way `Packet::from_bytes` used to store them.
+fn decode_linked_list(
+    bytes: &[u8],
+) -> (PacketRef<'_>, BTreeMap<u16, LinkedList<Vec<u8>>>) {
+    let packet = PacketRef::from_bytes(bytes).unwrap();
+    let mut options: BTreeMap<u16, LinkedList<Vec<u8>>> = BTreeMap::new();
+    for (number, value) in packet.options() {
+        options.entry(number).or_default().push_back(value.to_vec());
+    }
+    let _payload = packet.payload.to_vec();
+    (packet, options)
+}
+
+fn main() {
+    let packet = sample_request();
+    let bytes = packet.to_bytes().unwrap();
+
+    bench("decode", || {
+        black_box(Packet::from_bytes(black_box(&bytes)).unwrap());
+    });
+    bench("decode (LinkedList baseline)", || {
+        black_box(decode_linked_list(black_box(&bytes)));
+    });
+    bench("decode PacketRef", || {
+        black_box(PacketRef::from_bytes(black_box(&bytes)).unwrap());
+    });
+    bench("encode", || {
+        black_box(black_box(&packet).to_bytes().unwrap());
+    });
+    bench("get_option", || {
+        let values = black_box(&packet).get_option(CoapOption::UriQuery);
+        black_box(values.map(|values| values.len()));
+    });
+    bench("clone", || {
+        black_box(black_box(&packet).clone());
+    });
+}
@@ -1,16 +1,12 @@
-use coap_lite::{CoapRequest, RequestType as Method};
-use std::net::{SocketAddr, UdpSocket};
+use coap_lite::{UdpClient, UdpClientConfig};
 
 fn main() {
-    let mut request: CoapRequest<SocketAddr> = CoapRequest::new();
+    let mut client =
+        UdpClient::bind("127.0.0.1:0", UdpClientConfig::default()).unwrap();
 
-    request.set_method(Method::Get);
-    request.set_path("/test");
+    let response = client
+        .get("coap://127.0.0.1:5683/test")
+        .expect("Could not get a response");
 
-    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
-
-    let packet = request.message.to_bytes().unwrap();
-    socket
-        .send_to(&packet[..], "127.0.0.1:5683")
-        .expect("Could not send the data");
+    println!("Received '{}'", String::from_utf8_lossy(&response.payload));
 }
@@ -0,0 +1,608 @@
+//! An async client for CoAP over UDP on top of tokio.
+//!
+//! [`AsyncClient`] spawns a task that owns the socket and drives
+//! [`crate::ReliabilityLayer`] and [`crate::ClientExchanges`], while requests
+//! are futures that wait for the task to hand them their response. Like
+//! [`crate::UdpClient`], it performs block-wise transfers transparently.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+    vec::Vec,
+};
+
+use futures_core::Stream;
+use rand_core::{OsRng, RngCore};
+use tokio::{
+    net::{lookup_host, ToSocketAddrs, UdpSocket},
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    time::{sleep_until, timeout, Instant},
+};
+
+use crate::{
+    error::ClientError,
+    generator::TOKEN_LENGTH,
+    udp_client::{
+        coap_authority, BlockwiseStep, BlockwiseTransfer, RECEIVE_BUFFER_SIZE,
+    },
+    ClientExchanges, CoapResponse, ExchangeMatch, MessageIdAllocator,
+    MessageType, ObserveOption, Packet, PacketBuilder, ReliabilityEvent,
+    ReliabilityLayer, RequestType, TokenGenerator, UdpClientConfig,
+};
+
+/// What the responses to a request are handed to.
+type Waiter = UnboundedSender<Result<Packet, ClientError>>;
+
+/// What the responses to a request are received from.
+type Responses = UnboundedReceiver<Result<Packet, ClientError>>;
+
+/// The instructions for the task that owns the socket.
+enum Command {
+    /// Sends a request and hands its responses to the waiter.
+    Send {
+        destination: SocketAddr,
+        request: Packet,
+        waiter: Waiter,
+    },
+    /// Forgets about the request to an endpoint with a token.
+    Cancel {
+        destination: SocketAddr,
+        token: Vec<u8>,
+    },
+}
+
+/// An async CoAP client that sends Confirmable requests over UDP.
+///
+/// The client can be shared between tasks, and requests can be sent
+/// concurrently. As with [`crate::UdpClient`], the code of the returned
+/// responses has to be checked by the caller.
+#[derive(Clone)]
+pub struct AsyncClient {
+    commands: UnboundedSender<Command>,
+    tokens: Arc<Mutex<TokenGenerator<OsRng>>>,
+    timeout: Duration,
+    block_size: usize,
+}
+
+impl AsyncClient {
+    /// Creates a client sending from a socket bound to the given local
+    /// address, e.g. `0.0.0.0:0` for an arbitrary port.
+    ///
+    /// This spawns the task that owns the socket, so it must be called
+    /// within a tokio runtime. The task ends when the client and all its
+    /// observations have been dropped.
+    pub async fn bind<A: ToSocketAddrs>(
+        address: A,
+        config: UdpClientConfig,
+    ) -> Result<Self, ClientError> {
+        let socket = UdpSocket::bind(address).await?;
+        let (commands, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(Driver::new(socket, &config).run(receiver));
+        Ok(Self {
+            commands,
+            tokens: Arc::new(Mutex::new(TokenGenerator::random(
+                OsRng,
+                TOKEN_LENGTH,
+            ))),
+            timeout: config.timeout,
+            block_size: config.block_size,
+        })
+    }
+
+    /// Sends a GET request and returns the response.
+    pub async fn get(&self, uri: &str) -> Result<Packet, ClientError> {
+        self.request(RequestType::Get, uri, &[]).await
+    }
+
+    /// Sends a PUT request with a payload and returns the response.
+    pub async fn put(
+        &self,
+        uri: &str,
+        payload: &[u8],
+    ) -> Result<Packet, ClientError> {
+        self.request(RequestType::Put, uri, payload).await
+    }
+
+    /// Sends a POST request with a payload and returns the response.
+    pub async fn post(
+        &self,
+        uri: &str,
+        payload: &[u8],
+    ) -> Result<Packet, ClientError> {
+        self.request(RequestType::Post, uri, payload).await
+    }
+
+    /// Sends a DELETE request and returns the response.
+    pub async fn delete(&self, uri: &str) -> Result<Packet, ClientError> {
+        self.request(RequestType::Delete, uri, &[]).await
+    }
+
+    /// Sends a FETCH request with a payload and returns the response.
+    pub async fn fetch(
+        &self,
+        uri: &str,
+        payload: &[u8],
+    ) -> Result<Packet, ClientError> {
+        self.request(RequestType::Fetch, uri, payload).await
+    }
+
+    /// Sends a request with a `coap` URI and a payload, and returns the
+    /// response.
+    pub async fn request(
+        &self,
+        method: RequestType,
+        uri: &str,
+        payload: &[u8],
+    ) -> Result<Packet, ClientError> {
+        let destination = resolve(uri).await?;
+        let request = PacketBuilder::request(method).uri(uri).build()?;
+        let mut transfer =
+            BlockwiseTransfer::new(request, payload, self.block_size);
+        let mut message = transfer.first_message()?;
+        loop {
+            let response = self.exchange(destination, message).await?;
+            match transfer.handle_response(response)? {
+                BlockwiseStep::Send(next) => message = next,
+                BlockwiseStep::Done(response) => return Ok(response),
+            }
+        }
+    }
+
+    /// Starts observing a resource (RFC 7641), returning a stream of the
+    /// response to the registration followed by the notifications.
+    ///
+    /// The stream ends when the server ends the observation or stops
+    /// responding. Dropping it cancels the observation: the next
+    /// notification is rejected with a Reset message, upon which the server
+    /// forgets about the observer.
+    pub async fn observe(
+        &self,
+        uri: &str,
+    ) -> Result<Observation, ClientError> {
+        let destination = resolve(uri).await?;
+        let mut request =
+            PacketBuilder::request(RequestType::Get).uri(uri).build()?;
+        request.set_observe_value(usize::from(ObserveOption::Register) as u32);
+
+        let (_cancel, receiver) = self.start(destination, request)?;
+        Ok(Observation {
+            receiver,
+            _cancel,
+            timeout: Some(self.timeout),
+        })
+    }
+
+    /// Sends a single message with a fresh token and waits for its response.
+    async fn exchange(
+        &self,
+        destination: SocketAddr,
+        request: Packet,
+    ) -> Result<Packet, ClientError> {
+        // The request is forgotten when this future completes or is dropped
+        let (_cancel, mut receiver) = self.start(destination, request)?;
+        match timeout(self.timeout, receiver.recv()).await {
+            Ok(Some(result)) => result,
+            Ok(None) => Err(closed()),
+            Err(_) => Err(ClientError::TimedOut),
+        }
+    }
+
+    /// Hands a request with a fresh token to the task, returning the guard
+    /// that cancels it and the receiver of its responses.
+    fn start(
+        &self,
+        destination: SocketAddr,
+        mut request: Packet,
+    ) -> Result<(Cancel, Responses), ClientError> {
+        let token = self.tokens.lock().unwrap().next_token();
+        request.set_token(token.clone());
+        request.header.set_type(MessageType::Confirmable);
+        request.to_bytes()?;
+
+        let (waiter, receiver) = mpsc::unbounded_channel();
+        self.commands
+            .send(Command::Send {
+                destination,
+                request,
+                waiter,
+            })
+            .map_err(|_| closed())?;
+        let cancel = Cancel {
+            commands: self.commands.clone(),
+            destination,
+            token,
+        };
+        Ok((cancel, receiver))
+    }
+}
+
+/// Makes the task forget about a request once it is dropped, so that
+/// abandoned requests aren't retransmitted and their responses are rejected.
+struct Cancel {
+    commands: UnboundedSender<Command>,
+    destination: SocketAddr,
+    token: Vec<u8>,
+}
+
+impl Drop for Cancel {
+    fn drop(&mut self) {
+        let _ = self.commands.send(Command::Cancel {
+            destination: self.destination,
+            token: core::mem::take(&mut self.token),
+        });
+    }
+}
+
+/// The responses to an observation request, see [`AsyncClient::observe`].
+pub struct Observation {
+    receiver: Responses,
+    _cancel: Cancel,
+    /// The time to wait for the first response, which is cleared once it
+    /// arrived.
+    timeout: Option<Duration>,
+}
+
+impl Observation {
+    /// Waits for the next response or notification, returning `None` once
+    /// the observation has ended.
+    pub async fn next(&mut self) -> Option<Result<Packet, ClientError>> {
+        let next = match self.timeout.take() {
+            Some(duration) => timeout(duration, self.receiver.recv())
+                .await
+                .unwrap_or(Some(Err(ClientError::TimedOut))),
+            None => self.receiver.recv().await,
+        };
+        if matches!(next, Some(Err(_))) {
+            self.receiver.close();
+        }
+        next
+    }
+}
+
+impl Stream for Observation {
+    type Item = Result<Packet, ClientError>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        // The first response is awaited without a timeout here, as there is
+        // no timer to poll
+        self.timeout = None;
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// The task that owns the socket.
+struct Driver {
+    socket: UdpSocket,
+    reliability: ReliabilityLayer<SocketAddr>,
+    exchanges: ClientExchanges<SocketAddr>,
+    message_ids: MessageIdAllocator<SocketAddr, OsRng>,
+    waiters: BTreeMap<(SocketAddr, Vec<u8>), (u16, Waiter)>,
+    /// The point in time all durations passed to the components refer to.
+    start: Instant,
+}
+
+impl Driver {
+    fn new(socket: UdpSocket, config: &UdpClientConfig) -> Self {
+        let exchange_lifetime = config.transmission.exchange_lifetime();
+        Self {
+            socket,
+            reliability: ReliabilityLayer::new(
+                config.transmission,
+                OsRng.next_u32(),
+            ),
+            exchanges: ClientExchanges::new(exchange_lifetime),
+            message_ids: MessageIdAllocator::new(OsRng, exchange_lifetime),
+            waiters: BTreeMap::new(),
+            start: Instant::now(),
+        }
+    }
+
+    async fn run(mut self, mut commands: UnboundedReceiver<Command>) {
+        let mut buf = [0; RECEIVE_BUFFER_SIZE];
+        loop {
+            self.transmit().await;
+            let wake = self
+                .reliability
+                .poll_timeout()
+                .map(|timeout| self.start + timeout);
+
+            tokio::select! {
+                command = commands.recv() => match command {
+                    Some(command) => self.handle_command(command),
+                    None => return,
+                },
+                received = self.socket.recv_from(&mut buf) => {
+                    let (size, source) = match received {
+                        Ok(received) => received,
+                        // A failed socket ends the task, which fails all
+                        // requests
+                        Err(_) => return,
+                    };
+                    if let Err(error) =
+                        self.handle_datagram(source, &buf[..size]).await
+                    {
+                        coap_debug!(
+                            "Failed to reply to {}: {}",
+                            source,
+                            error
+                        );
+                    }
+                }
+                _ = sleep_until(wake.unwrap_or_else(Instant::now)),
+                    if wake.is_some() => {}
+            }
+        }
+    }
+
+    /// Sends the messages that are due.
+    ///
+    /// Requests that can't be encoded fail right away, while failing to
+    /// send one is left to the retransmissions.
+    async fn transmit(&mut self) {
+        while let Some(event) = self.reliability.poll(self.start.elapsed()) {
+            match event {
+                ReliabilityEvent::Transmit(endpoint, packet) => {
+                    let datagram = match packet.to_bytes() {
+                        Ok(datagram) => datagram,
+                        Err(error) => {
+                            let message_id = packet.header.message_id;
+                            self.reliability.cancel(&endpoint, message_id);
+                            self.finish(
+                                endpoint,
+                                packet.get_token(),
+                                Err(ClientError::Message(error)),
+                            );
+                            continue;
+                        }
+                    };
+                    if let Err(error) =
+                        self.socket.send_to(&datagram, endpoint).await
+                    {
+                        coap_debug!(
+                            "Failed to send to {}: {}",
+                            endpoint,
+                            error
+                        );
+                    }
+                }
+                ReliabilityEvent::TimedOut(endpoint, packet) => {
+                    self.finish(
+                        endpoint,
+                        packet.get_token(),
+                        Err(ClientError::TimedOut),
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Send {
+                destination,
+                mut request,
+                waiter,
+            } => {
+                let now = self.start.elapsed();
+                let message_id =
+                    match self.message_ids.allocate(&destination, now) {
+                        Some(message_id) => message_id,
+                        None => {
+                            let _ = waiter.send(Err(ClientError::TimedOut));
+                            return;
+                        }
+                    };
+                request.header.message_id = message_id;
+                self.waiters.insert(
+                    (destination, request.get_token().to_vec()),
+                    (message_id, waiter),
+                );
+                self.exchanges.register(destination, request.clone());
+                self.reliability.send(destination, request, now);
+            }
+            Command::Cancel { destination, token } => {
+                self.exchanges.cancel(&destination, &token);
+                if let Some((message_id, _)) =
+                    self.waiters.remove(&(destination, token))
+                {
+                    self.reliability.cancel(&destination, message_id);
+                }
+            }
+        }
+    }
+
+    async fn handle_datagram(
+        &mut self,
+        source: SocketAddr,
+        datagram: &[u8],
+    ) -> io::Result<()> {
+        let packet = match Packet::from_bytes(datagram) {
+            Ok(packet) => packet,
+            Err(_) => return Ok(()),
+        };
+
+        // Resets are reported below as well
+        self.reliability.handle_incoming(&source, &packet);
+        let now = self.start.elapsed();
+        match self.exchanges.handle_incoming(&source, &packet, now) {
+            ExchangeMatch::Response(request) => {
+                self.acknowledge(source, &packet).await?;
+                let message_id = request.header.message_id;
+                self.reliability.cancel(&source, message_id);
+                self.finish(source, request.get_token(), Ok(packet));
+            }
+            ExchangeMatch::Notification(request) => {
+                self.acknowledge(source, &packet).await?;
+                let message_id = request.header.message_id;
+                self.reliability.cancel(&source, message_id);
+                let key = (source, request.get_token().to_vec());
+                if let Some((_, waiter)) = self.waiters.get(&key) {
+                    let _ = waiter.send(Ok(packet));
+                }
+            }
+            ExchangeMatch::Reset(request) => {
+                self.finish(
+                    source,
+                    request.get_token(),
+                    Err(ClientError::Reset),
+                );
+            }
+            ExchangeMatch::Duplicate => {
+                self.acknowledge(source, &packet).await?
+            }
+            ExchangeMatch::Unexpected(reset) => {
+                self.socket.send_to(&reset.to_bytes()?, source).await?;
+            }
+            // Multicast requests aren't sent by this client
+            ExchangeMatch::Acknowledged(_)
+            | ExchangeMatch::GroupResponse(_)
+            | ExchangeMatch::Ignored => {}
+        }
+        Ok(())
+    }
+
+    /// Hands the final result to the waiter of a request and forgets about
+    /// it.
+    fn finish(
+        &mut self,
+        endpoint: SocketAddr,
+        token: &[u8],
+        result: Result<Packet, ClientError>,
+    ) {
+        self.exchanges.cancel(&endpoint, token);
+        if let Some((_, waiter)) =
+            self.waiters.remove(&(endpoint, token.to_vec()))
+        {
+            let _ = waiter.send(result);
+        }
+    }
+
+    /// Acknowledges a packet if it is Confirmable.
+    async fn acknowledge(
+        &self,
+        source: SocketAddr,
+        packet: &Packet,
+    ) -> io::Result<()> {
+        if let Some(ack) = CoapResponse::empty_ack(packet) {
+            self.socket.send_to(&ack.to_bytes()?, source).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Resolves the host of a `coap` URI.
+async fn resolve(uri: &str) -> Result<SocketAddr, ClientError> {
+    lookup_host(coap_authority(uri)?)
+        .await
+        .map_err(|_| ClientError::InvalidUri)?
+        .next()
+        .ok_or(ClientError::InvalidUri)
+}
+
+/// Returns the error for requests that can't be completed because the task
+/// owning the socket has ended.
+fn closed() -> ClientError {
+    ClientError::Io(io::ErrorKind::NotConnected.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{CoapRequest, CoapServer, TransmissionParameters};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[tokio::test]
+    async fn observes_resources() {
+        let value = Arc::new(AtomicU32::new(1));
+        let server = CoapServer::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("coap://{}/counter", server.local_addr().unwrap());
+        let notifier = server.notifier();
+        let current = value.clone();
+        tokio::spawn(server.run(move |mut request: CoapRequest<_>| {
+            let payload = current.load(Ordering::SeqCst).to_string();
+            async move {
+                if let Some(response) = &mut request.response {
+                    response.message.payload = payload.into_bytes();
+                }
+                request
+            }
+        }));
+
+        let client = AsyncClient::bind("127.0.0.1:0", Default::default())
+            .await
+            .unwrap();
+        let mut observation = client.observe(&uri).await.unwrap();
+        let first = observation.next().await.unwrap().unwrap();
+        assert_eq!(first.payload, b"1");
+        let sequence = first.get_observe_value().unwrap().unwrap();
+
+        value.store(2, Ordering::SeqCst);
+        notifier.resource_changed("counter");
+        let second = observation.next().await.unwrap().unwrap();
+        assert_eq!(second.header.get_type(), MessageType::Confirmable);
+        assert_eq!(second.payload, b"2");
+        assert!(second.get_observe_value().unwrap().unwrap() > sequence);
+
+        // Other requests work alongside the observation
+        let (a, b) = tokio::join!(client.get(&uri), client.get(&uri));
+        assert_eq!(a.unwrap().payload, b"2");
+        assert_eq!(b.unwrap().payload, b"2");
+    }
+
+    #[tokio::test]
+    async fn failures() {
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("coap://{}/", silent.local_addr().unwrap());
+        let config = UdpClientConfig {
+            timeout: Duration::from_millis(500),
+            transmission: TransmissionParameters {
+                ack_timeout: Duration::from_millis(20),
+                max_retransmit: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let client = AsyncClient::bind("127.0.0.1:0", config).await.unwrap();
+        assert!(matches!(client.get(&uri).await, Err(ClientError::TimedOut)));
+
+        let mut buf = [0; RECEIVE_BUFFER_SIZE];
+        while silent.try_recv_from(&mut buf).is_ok() {}
+
+        let mut observation = client.observe(&uri).await.unwrap();
+        let (size, source) = silent.recv_from(&mut buf).await.unwrap();
+        let request = Packet::from_bytes(&buf[..size]).unwrap();
+        assert_eq!(request.get_observe_value(), Some(Ok(0)));
+        let reset = Packet::reset(request.header.message_id);
+        silent
+            .send_to(&reset.to_bytes().unwrap(), source)
+            .await
+            .unwrap();
+        assert!(matches!(
+            observation.next().await,
+            Some(Err(ClientError::Reset))
+        ));
+        assert!(observation.next().await.is_none());
+
+        // Abandoned requests are no longer retransmitted
+        let abandoned = timeout(Duration::from_millis(5), client.get(&uri));
+        assert!(abandoned.await.is_err());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        while silent.try_recv_from(&mut buf).is_ok() {}
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(silent.try_recv_from(&mut buf).is_err());
+
+        assert!(matches!(
+            client.get("coap+tcp://127.0.0.1/").await,
+            Err(ClientError::InvalidUri)
+        ));
+    }
+}
@@ -0,0 +1,580 @@
+//! An async server for CoAP over UDP on top of tokio.
+//!
+//! [`CoapServer`] decodes incoming datagrams, passes requests to an async
+//! handler and sends its responses, while taking care of duplicates
+//! ([`crate::DedupCache`]), block-wise transfers ([`crate::BlockHandler`])
+//! and observations ([`crate::Subject`]) on its own.
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    io,
+    net::SocketAddr,
+    string::{String, ToString},
+    sync::Arc,
+    time::Duration,
+    vec::Vec,
+};
+
+use rand_core::{OsRng, RngCore};
+use tokio::{
+    net::{ToSocketAddrs, UdpSocket},
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    time::{sleep_until, Instant},
+};
+
+use crate::{
+    error::InvalidObserve, udp_client::RECEIVE_BUFFER_SIZE, BlockHandler,
+    BlockHandlerConfig, CoapOption, CoapRequest, DedupCache, DedupCacheConfig,
+    MessageClass, MessageIdAllocator, MessageType, ObserveOption, Packet,
+    PacketBuilder, ReliabilityEvent, ReliabilityLayer, RequestType, Subject,
+    TransmissionParameters,
+};
+
+/// A handle for telling a [`CoapServer`] that a resource changed, so that
+/// its observers get notified.
+#[derive(Debug, Clone)]
+pub struct ResourceNotifier {
+    sender: UnboundedSender<String>,
+}
+
+impl ResourceNotifier {
+    /// Notifies the observers of the resource with the given path, e.g.
+    /// `sensors/temp`.
+    pub fn resource_changed(&self, path: &str) {
+        // The server may be gone, in which case nobody is observing
+        let _ = self.sender.send(path.trim_matches('/').to_string());
+    }
+}
+
+/// An async CoAP server.
+///
+/// Requests are handled one at a time by calling the handler, which fills
+/// in the response of the request (see [`CoapRequest::response`]). GET
+/// requests with an Observe option of 0 register an observer if the
+/// response is successful, and whenever a resource is reported as changed
+/// through a [`ResourceNotifier`], the handler is called once more per
+/// observer to produce the Confirmable notification, which is retransmitted
+/// until the observer acknowledges it. Observers that reject a notification
+/// or don't acknowledge it in time are removed.
+///
+/// A server whose socket has joined a multicast group can be told to treat
+/// requests as multicast requests with [`CoapServer::set_multicast`].
+pub struct CoapServer {
+    socket: Arc<UdpSocket>,
+    block_handler: BlockHandler<SocketAddr>,
+    dedup_cache: DedupCache<SocketAddr>,
+    subject: Subject<SocketAddr>,
+    notifier: ResourceNotifier,
+    changes: UnboundedReceiver<String>,
+    reliability: ReliabilityLayer<SocketAddr>,
+    message_ids: MessageIdAllocator<SocketAddr, OsRng>,
+    /// The paths of the resources of the outstanding notifications, by
+    /// observer and message ID.
+    notifications: BTreeMap<(SocketAddr, u16), String>,
+    /// The point in time all durations passed to the components refer to.
+    start: Instant,
+    multicast: bool,
+    leisure: Duration,
+}
+
+impl CoapServer {
+    /// Creates a server listening on the given address.
+    pub async fn bind<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        let socket = UdpSocket::bind(address).await?;
+        let (sender, changes) = mpsc::unbounded_channel();
+        let transmission = TransmissionParameters::default();
+        Ok(Self {
+            socket: Arc::new(socket),
+            block_handler: BlockHandler::new(BlockHandlerConfig::default()),
+            dedup_cache: DedupCache::new(DedupCacheConfig::default()),
+            subject: Subject::default(),
+            notifier: ResourceNotifier { sender },
+            changes,
+            reliability: ReliabilityLayer::new(transmission, OsRng.next_u32()),
+            message_ids: MessageIdAllocator::new(
+                OsRng,
+                transmission.exchange_lifetime(),
+            ),
+            notifications: BTreeMap::new(),
+            start: Instant::now(),
+            multicast: false,
+            leisure: transmission.default_leisure,
+        })
+    }
+
+    /// Returns the socket of the server, e.g. for joining a multicast group.
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Sets whether requests are treated as multicast requests (see
+    /// [`CoapRequest::set_multicast`]), whose responses are delayed by a
+    /// random time within the Leisure period and suppressed if they are
+    /// errors.
+    ///
+    /// The socket doesn't tell which address a datagram was sent to, so this
+    /// applies to every request and is meant for servers that only receive
+    /// requests through the group.
+    pub fn set_multicast(&mut self, multicast: bool) {
+        self.multicast = multicast;
+    }
+
+    /// Sets the Leisure period for responses to multicast requests, which
+    /// defaults to [`TransmissionParameters::default_leisure`].
+    pub fn set_leisure(&mut self, leisure: Duration) {
+        self.leisure = leisure;
+    }
+
+    /// Returns the address the server is listening on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Returns a handle for notifying observers about changed resources.
+    pub fn notifier(&self) -> ResourceNotifier {
+        self.notifier.clone()
+    }
+
+    /// Serves requests with the handler until receiving from the socket
+    /// fails.
+    ///
+    /// Failing to send a single response or notification doesn't stop the
+    /// server.
+    pub async fn run<F, Fut>(mut self, mut handler: F) -> io::Result<()>
+    where
+        F: FnMut(CoapRequest<SocketAddr>) -> Fut,
+        Fut: Future<Output = CoapRequest<SocketAddr>>,
+    {
+        let mut buf = [0; RECEIVE_BUFFER_SIZE];
+        loop {
+            self.transmit().await;
+            let wake = self
+                .reliability
+                .poll_timeout()
+                .map(|timeout| self.start + timeout);
+
+            tokio::select! {
+                received = self.socket.recv_from(&mut buf) => {
+                    let (size, source) = received?;
+                    let packet = match Packet::from_bytes(&buf[..size]) {
+                        Ok(packet) => packet,
+                        Err(_) => continue,
+                    };
+                    if let Err(error) =
+                        self.handle_packet(&mut handler, source, packet).await
+                    {
+                        coap_debug!(
+                            "Failed to respond to {}: {}",
+                            source,
+                            error
+                        );
+                    }
+                }
+                Some(path) = self.changes.recv() => {
+                    self.notify(&mut handler, &path).await;
+                }
+                _ = sleep_until(wake.unwrap_or_else(Instant::now)),
+                    if wake.is_some() => {}
+            }
+        }
+    }
+
+    /// Sends the notifications that are due.
+    async fn transmit(&mut self) {
+        while let Some(event) = self.reliability.poll(self.start.elapsed()) {
+            match event {
+                ReliabilityEvent::Transmit(endpoint, packet) => {
+                    if let Err(error) = self.send(&packet, endpoint).await {
+                        coap_debug!(
+                            "Failed to notify {}: {}",
+                            endpoint,
+                            error
+                        );
+                    }
+                }
+                event => self.complete_notification(event),
+            }
+        }
+    }
+
+    /// Forgets about a notification that was acknowledged, rejected or timed
+    /// out, removing the observer in the latter two cases.
+    fn complete_notification(&mut self, event: ReliabilityEvent<SocketAddr>) {
+        let (endpoint, packet, ends_observation) = match event {
+            ReliabilityEvent::Acknowledged(endpoint, packet) => {
+                (endpoint, packet, false)
+            }
+            ReliabilityEvent::Reset(endpoint, packet)
+            | ReliabilityEvent::TimedOut(endpoint, packet) => {
+                (endpoint, packet, true)
+            }
+            ReliabilityEvent::Transmit(..) => return,
+        };
+        let key = (endpoint, packet.header.message_id);
+        let path = match self.notifications.remove(&key) {
+            Some(path) => path,
+            None => return,
+        };
+        if !ends_observation {
+            return;
+        }
+
+        if let Ok(packet) = PacketBuilder::request(RequestType::Get)
+            .path(&path)
+            .token(packet.get_token().to_vec())
+            .build()
+        {
+            self.subject
+                .deregister(&CoapRequest::from_packet(packet, endpoint));
+        }
+    }
+
+    async fn handle_packet<F, Fut>(
+        &mut self,
+        handler: &mut F,
+        source: SocketAddr,
+        packet: Packet,
+    ) -> io::Result<()>
+    where
+        F: FnMut(CoapRequest<SocketAddr>) -> Fut,
+        Fut: Future<Output = CoapRequest<SocketAddr>>,
+    {
+        match packet.header.get_type() {
+            MessageType::Acknowledgement | MessageType::Reset => {
+                if let Some(event) =
+                    self.reliability.handle_incoming(&source, &packet)
+                {
+                    self.complete_notification(event);
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+        if let Some(reset) = packet
+            .reset_for_ping()
+            .or_else(|| packet.reset_for_unexpected_response())
+        {
+            return self.send(&reset, source).await;
+        }
+        if !matches!(packet.header.code, MessageClass::Request(_)) {
+            return Ok(());
+        }
+
+        let mut request = CoapRequest::from_packet(packet, source);
+        request.set_multicast(self.multicast);
+        if self.dedup_cache.intercept_request(&mut request) {
+            return self.respond(&request).await;
+        }
+        match self.block_handler.intercept_request(&mut request) {
+            Ok(true) => return self.respond(&request).await,
+            Ok(false) => {}
+            Err(error) => {
+                request.apply_from_error(error);
+                return self.respond(&request).await;
+            }
+        }
+
+        let observe = request.get_observe_flag();
+        let mut request = handler(request).await;
+        self.update_observation(&mut request, observe);
+        if let Err(error) = self.block_handler.intercept_response(&mut request)
+        {
+            request.apply_from_error(error);
+        }
+        self.dedup_cache.intercept_response(&request);
+        self.respond(&request).await
+    }
+
+    /// Registers or deregisters the observer of a handled request.
+    fn update_observation(
+        &mut self,
+        request: &mut CoapRequest<SocketAddr>,
+        observe: Option<Result<ObserveOption, InvalidObserve>>,
+    ) {
+        let successful = request
+            .response
+            .as_ref()
+            .is_some_and(|response| is_successful(&response.message));
+        let is_get = *request.get_method() == RequestType::Get;
+
+        match observe {
+            Some(Ok(ObserveOption::Register)) if is_get && successful => {
+                self.subject.register(request);
+                let path = request.get_path();
+                let sequence = self
+                    .subject
+                    .get_resource(&path)
+                    .map_or(0, |resource| resource.sequence);
+                if let Some(response) = &mut request.response {
+                    response.message.set_observe_value(sequence);
+                }
+            }
+            Some(Ok(_)) => self.subject.deregister(request),
+            _ => {}
+        }
+    }
+
+    /// Queues a notification to every observer of a resource, each with its
+    /// own message ID.
+    async fn notify<F, Fut>(&mut self, handler: &mut F, path: &str)
+    where
+        F: FnMut(CoapRequest<SocketAddr>) -> Fut,
+        Fut: Future<Output = CoapRequest<SocketAddr>>,
+    {
+        let (observers, sequence): (Vec<_>, _) =
+            match self.subject.get_resource(path) {
+                Some(resource) => (
+                    resource
+                        .observers
+                        .iter()
+                        .map(|observer| {
+                            (observer.endpoint, observer.token.clone())
+                        })
+                        .collect(),
+                    resource.sequence.wrapping_add(1),
+                ),
+                None => return,
+            };
+
+        for (endpoint, token) in observers {
+            // The path was taken from a request, so it can't be too long
+            let packet = match PacketBuilder::request(RequestType::Get)
+                .path(path)
+                .token(token)
+                .build()
+            {
+                Ok(packet) => packet,
+                Err(_) => return,
+            };
+            let mut request =
+                handler(CoapRequest::from_packet(packet, endpoint)).await;
+            let mut response = match request.response.take() {
+                Some(response) => response.message,
+                None => continue,
+            };
+            let now = self.start.elapsed();
+            let message_id = match self.message_ids.allocate(&endpoint, now) {
+                Some(message_id) => message_id,
+                None => continue,
+            };
+            response.header.set_type(MessageType::Confirmable);
+            response.header.message_id = message_id;
+            if is_successful(&response) {
+                response.set_observe_value(sequence);
+            } else {
+                // Error responses end the observation
+                response.remove_option(CoapOption::Observe);
+                self.subject.deregister(&request);
+            }
+            self.notifications
+                .insert((endpoint, message_id), path.to_string());
+            self.reliability.send(endpoint, response, now);
+        }
+
+        // The notifications have different message IDs, so their
+        // acknowledgements are tracked above rather than by the subject
+        self.subject.resource_changed(path, 0, false);
+    }
+
+    /// Sends the response of a request, if there is one, in the background
+    /// if it has to be delayed.
+    async fn respond(
+        &self,
+        request: &CoapRequest<SocketAddr>,
+    ) -> io::Result<()> {
+        let source = match request.source {
+            Some(source) => source,
+            None => return Ok(()),
+        };
+        match request.outgoing_response(self.leisure, &mut OsRng) {
+            Some((response, delay)) if delay.is_zero() => {
+                self.send(response, source).await
+            }
+            Some((response, delay)) => {
+                let socket = Arc::clone(&self.socket);
+                let datagram = response.to_bytes()?;
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    // There is nobody to report the error to anymore
+                    let _ = socket.send_to(&datagram, source).await;
+                });
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    async fn send(
+        &self,
+        packet: &Packet,
+        destination: SocketAddr,
+    ) -> io::Result<()> {
+        self.socket
+            .send_to(&packet.to_bytes()?, destination)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Returns whether a response has a success status.
+fn is_successful(response: &Packet) -> bool {
+    match response.header.code {
+        MessageClass::Response(status) => !status.is_error(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        error::HandlingError, AsyncClient, ResponseType, UdpClient,
+        UdpClientConfig,
+    };
+
+    async fn handle(
+        mut request: CoapRequest<SocketAddr>,
+    ) -> CoapRequest<SocketAddr> {
+        let payload = match request.get_path().as_str() {
+            "hello" => b"world".to_vec(),
+            "big" => (0..3000).map(|i| i as u8).collect(),
+            "echo" => request.message.payload.clone(),
+            _ => {
+                request.apply_from_error(HandlingError::not_found());
+                return request;
+            }
+        };
+        if let Some(response) = &mut request.response {
+            response.message.payload = payload;
+        }
+        request
+    }
+
+    async fn receive(socket: &UdpSocket) -> Packet {
+        let mut buf = [0; RECEIVE_BUFFER_SIZE];
+        let (size, _) = socket.recv_from(&mut buf).await.unwrap();
+        Packet::from_bytes(&buf[..size]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_requests() {
+        let server = CoapServer::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("coap://{}", server.local_addr().unwrap());
+        tokio::spawn(server.run(handle));
+
+        let config = UdpClientConfig {
+            block_size: 256,
+            ..Default::default()
+        };
+        let client = AsyncClient::bind("127.0.0.1:0", config).await.unwrap();
+
+        let response = client.get(&format!("{}/hello", base)).await.unwrap();
+        assert_eq!(response.payload, b"world");
+
+        let big: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        let response = client.get(&format!("{}/big", base)).await.unwrap();
+        assert_eq!(response.payload, big);
+
+        let response = client
+            .post(&format!("{}/echo", base), &big[..1000])
+            .await
+            .unwrap();
+        assert_eq!(response.payload, &big[..1000]);
+
+        let response = client.delete(&format!("{}/nothing", base)).await;
+        assert_eq!(
+            response.unwrap().header.code,
+            MessageClass::Response(ResponseType::NotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn answers_pings() {
+        let server = CoapServer::bind("127.0.0.1:0").await.unwrap();
+        let address = server.local_addr().unwrap();
+        tokio::spawn(server.run(handle));
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let ping = Packet::ping(0x1234).to_bytes().unwrap();
+        socket.send_to(&ping, address).await.unwrap();
+
+        let mut buf = [0; RECEIVE_BUFFER_SIZE];
+        let (size, _) = socket.recv_from(&mut buf).await.unwrap();
+        assert_eq!(
+            Packet::from_bytes(&buf[..size]).unwrap(),
+            Packet::reset(0x1234)
+        );
+    }
+
+    #[tokio::test]
+    async fn retransmits_notifications() {
+        let server = CoapServer::bind("127.0.0.1:0").await.unwrap();
+        let address = server.local_addr().unwrap();
+        let notifier = server.notifier();
+        tokio::spawn(server.run(handle));
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let register = PacketBuilder::request(RequestType::Get)
+            .confirmable()
+            .message_id(1)
+            .path("hello")
+            .token(vec![7])
+            .observe(0)
+            .build()
+            .unwrap();
+        socket
+            .send_to(&register.to_bytes().unwrap(), address)
+            .await
+            .unwrap();
+        assert!(receive(&socket).await.get_observe_value().is_some());
+
+        // Notifications are retransmitted until they are acknowledged
+        notifier.resource_changed("hello");
+        let notification = receive(&socket).await;
+        assert_eq!(notification.header.get_type(), MessageType::Confirmable);
+        assert_eq!(receive(&socket).await, notification);
+
+        // Rejecting a notification ends the observation
+        let reset = Packet::reset(notification.header.message_id);
+        socket
+            .send_to(&reset.to_bytes().unwrap(), address)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        notifier.resource_changed("hello");
+        let received = tokio::time::timeout(
+            Duration::from_millis(500),
+            socket.recv_from(&mut [0; RECEIVE_BUFFER_SIZE]),
+        )
+        .await;
+        assert!(received.is_err());
+    }
+
+    #[tokio::test]
+    async fn delays_multicast_responses() {
+        let mut server = CoapServer::bind("127.0.0.1:0").await.unwrap();
+        server.set_multicast(true);
+        server.set_leisure(Duration::from_millis(100));
+        let base = format!("coap://{}", server.local_addr().unwrap());
+        tokio::spawn(server.run(handle));
+
+        let responses = tokio::task::spawn_blocking(move || {
+            let mut client =
+                UdpClient::bind("127.0.0.1:0", UdpClientConfig::default())
+                    .unwrap();
+            let wait = Duration::from_millis(300);
+            [format!("{}/hello", base), format!("{}/nothing", base)]
+                .map(|uri| client.multicast(RequestType::Get, &uri, b"", wait))
+        })
+        .await
+        .unwrap();
+
+        let [found, missing] = responses.map(Result::unwrap);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].1.payload, b"world");
+        assert_eq!(found[0].1.header.get_type(), MessageType::NonConfirmable);
+        // Error responses to multicast requests are suppressed
+        assert!(missing.is_empty());
+    }
+}
@@ -134,7 +134,7 @@ impl<Endpoint: Ord + Clone> BlockHandler<Endpoint> {
             .and_then(|x| x.ok());
         let maybe_response_block1 = Self::negotiate_block_size_if_necessary(
             request_block1.as_ref(),
-            Self::compute_message_size_hack(&mut request.message),
+            request.message.encoded_len(),
             request.message.payload.len(),
             max_total_message_size,
         )?;
@@ -268,7 +268,7 @@ impl<Endpoint: Ord + Clone> BlockHandler<Endpoint> {
 
         response.message.set_options_as::<BlockValue>(
             CoapOption::Block2,
-            [response_block2].into(),
+            [response_block2],
         );
 
         Ok(has_more_chunks)
@@ -281,7 +281,7 @@ impl<Endpoint: Ord + Clone> BlockHandler<Endpoint> {
         dst.header.set_type(src.header.get_type());
         dst.header.code = src.header.code;
         for (&option, value) in src.options() {
-            dst.set_option(CoapOption::from(option), value.clone());
+            dst.set_option(CoapOption::from(option), value);
         }
     }
 
@@ -308,7 +308,7 @@ impl<Endpoint: Ord + Clone> BlockHandler<Endpoint> {
                 if let Some(request_block2) =
                     Self::negotiate_block_size_if_necessary(
                         state.last_request_block2.as_ref(),
-                        Self::compute_message_size_hack(&mut response.message),
+                        response.message.encoded_len(),
                         response.message.payload.len(),
                         self.config.max_total_message_size,
                     )?
@@ -330,19 +330,6 @@ impl<Endpoint: Ord + Clone> BlockHandler<Endpoint> {
         Ok(false)
     }
 
-    /// Hack to work around the lack of an API to compute the size of a message
-    /// before producing it.
-    fn compute_message_size_hack(packet: &mut Packet) -> usize {
-        let moved_payload = mem::take(&mut packet.payload);
-        let size_sans_payload = packet
-            .to_bytes()
-            .expect("Internal error encoding packet")
-            .len();
-        packet.payload = moved_payload;
-
-        size_sans_payload + packet.payload.len()
-    }
-
     fn negotiate_block_size_if_necessary(
         request_block: Option<&BlockValue>,
         message_size: usize,
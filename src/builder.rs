@@ -0,0 +1,429 @@
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+use crate::{
+    error::BuildError,
+    header::{MessageClass, MessageType, RequestType as Method, ResponseType},
+    option_registry::builtin_properties,
+    option_value::{OptionValueType, OptionValueU16, OptionValueU32},
+    packet::{extended_token_length, CoapOption, ContentFormat, Packet},
+    response::CoapResponse,
+    uri::{parse_path_and_query, CoapUri},
+};
+
+/// Builder for requests and responses.
+///
+/// Problems with the values passed in are reported by [`build`], which also
+/// checks the token length and rejects options that don't belong on
+/// requests or responses respectively.
+///
+/// ## Example
+///
+/// ```
+/// use coap_lite::{
+///     CoapOption, ContentFormat, MessageClass, Packet, RequestType,
+///     ResponseType,
+/// };
+///
+/// let request = Packet::request(RequestType::Get)
+///     .confirmable()
+///     .message_id(0x1234)
+///     .token([0x7d, 0x34])
+///     .uri("/sensors/temp?unit=C")
+///     .accept(ContentFormat::ApplicationCBOR)
+///     .build()
+///     .unwrap();
+/// assert_eq!(
+///     request.get_first_option(CoapOption::UriQuery),
+///     Some(&b"unit=C"[..])
+/// );
+///
+/// let response = Packet::response(&request)
+///     .status(ResponseType::Content)
+///     .content_format(ContentFormat::TextPlain)
+///     .payload("22.5 C")
+///     .build()
+///     .unwrap();
+/// assert_eq!(
+///     response.header.code,
+///     MessageClass::Response(ResponseType::Content)
+/// );
+/// assert_eq!(response.get_token(), request.get_token());
+/// ```
+///
+/// [`build`]: PacketBuilder::build
+#[derive(Debug, Clone)]
+pub struct PacketBuilder {
+    packet: Packet,
+    error: Option<BuildError>,
+}
+
+impl PacketBuilder {
+    /// Creates a builder for a Confirmable request.
+    pub fn request(method: Method) -> PacketBuilder {
+        let mut packet = Packet::new();
+        packet.header.code = MessageClass::Request(method);
+        PacketBuilder {
+            packet,
+            error: None,
+        }
+    }
+
+    /// Creates a builder for a 2.05 (Content) response to `request`.
+    ///
+    /// The response is piggybacked on the acknowledgement of a Confirmable
+    /// request and Non-confirmable otherwise, and carries the token of the
+    /// request.
+    pub fn response(request: &Packet) -> PacketBuilder {
+        match CoapResponse::new(request) {
+            Some(response) => PacketBuilder {
+                packet: response.message,
+                error: None,
+            },
+            None => PacketBuilder {
+                packet: Packet::new(),
+                error: Some(BuildError::InvalidRequestType),
+            },
+        }
+    }
+
+    /// Sets the message type to Confirmable.
+    pub fn confirmable(self) -> Self {
+        self.message_type(MessageType::Confirmable)
+    }
+
+    /// Sets the message type to Non-confirmable.
+    pub fn non_confirmable(self) -> Self {
+        self.message_type(MessageType::NonConfirmable)
+    }
+
+    /// Sets the message type.
+    pub fn message_type(mut self, message_type: MessageType) -> Self {
+        self.packet.header.set_type(message_type);
+        self
+    }
+
+    /// Sets the message ID.
+    pub fn message_id(mut self, message_id: u16) -> Self {
+        self.packet.header.message_id = message_id;
+        self
+    }
+
+    /// Sets the token.
+    pub fn token(mut self, token: impl Into<Vec<u8>>) -> Self {
+        self.packet.set_token(token.into());
+        self
+    }
+
+    /// Sets the method, turning the message into a request.
+    pub fn method(mut self, method: Method) -> Self {
+        self.packet.header.code = MessageClass::Request(method);
+        self
+    }
+
+    /// Sets the status, turning the message into a response.
+    pub fn status(mut self, status: ResponseType) -> Self {
+        self.packet.header.code = MessageClass::Response(status);
+        self
+    }
+
+    /// Sets the Uri-Path and Uri-Query options from a relative reference
+    /// like `/a/b?x=1&y=2`, or additionally Uri-Host from an absolute URI
+    /// like `coap://example.com/a/b?x=1`.
+    ///
+    /// See [`CoapUri`] for how the URI is decomposed into options.
+    pub fn uri(mut self, uri: &str) -> Self {
+        if uri.contains("://") {
+            return match CoapUri::parse(uri) {
+                Ok(uri) => {
+                    uri.apply_to(&mut self.packet);
+                    self
+                }
+                Err(_) => self.fail(BuildError::InvalidUri),
+            };
+        }
+
+        match parse_path_and_query(uri) {
+            Ok((path, query)) if !uri.contains('#') => {
+                for (option, values) in [
+                    (CoapOption::UriPath, path),
+                    (CoapOption::UriQuery, query),
+                ] {
+                    self.packet.remove_option(option);
+                    for value in values {
+                        self.packet.add_option(option, value.into());
+                    }
+                }
+                self
+            }
+            _ => self.fail(BuildError::InvalidUri),
+        }
+    }
+
+    /// Sets the Uri-Path options from a path like `/a/b`.
+    pub fn path(mut self, path: &str) -> Self {
+        self.packet.remove_option(CoapOption::UriPath);
+        let path = path.strip_prefix('/').unwrap_or(path);
+        if !path.is_empty() {
+            for segment in path.split('/') {
+                self.packet.add_option(
+                    CoapOption::UriPath,
+                    segment.as_bytes().to_vec(),
+                );
+            }
+        }
+        self
+    }
+
+    /// Adds a Uri-Query option.
+    pub fn query(self, parameter: &str) -> Self {
+        self.option(CoapOption::UriQuery, parameter.as_bytes())
+    }
+
+    /// Sets the Uri-Host option.
+    pub fn host(mut self, host: &str) -> Self {
+        self.packet
+            .set_option(CoapOption::UriHost, [host.as_bytes()]);
+        self
+    }
+
+    /// Sets the Uri-Port option.
+    pub fn port(mut self, port: u16) -> Self {
+        self.packet
+            .set_options_as(CoapOption::UriPort, [OptionValueU16(port)]);
+        self
+    }
+
+    /// Sets the Accept option.
+    pub fn accept(self, content_format: ContentFormat) -> Self {
+        self.content_format_option(CoapOption::Accept, content_format)
+    }
+
+    /// Sets the Content-Format option.
+    pub fn content_format(self, content_format: ContentFormat) -> Self {
+        self.content_format_option(CoapOption::ContentFormat, content_format)
+    }
+
+    fn content_format_option(
+        mut self,
+        option: CoapOption,
+        content_format: ContentFormat,
+    ) -> Self {
+        let value = u16::try_from(usize::from(content_format)).unwrap();
+        self.packet.set_options_as(option, [OptionValueU16(value)]);
+        self
+    }
+
+    /// Sets the Observe option.
+    pub fn observe(mut self, value: u32) -> Self {
+        self.packet
+            .set_options_as(CoapOption::Observe, [OptionValueU32(value)]);
+        self
+    }
+
+    /// Adds an option value.
+    pub fn option(
+        mut self,
+        tp: CoapOption,
+        value: impl Into<Vec<u8>>,
+    ) -> Self {
+        self.packet.add_option(tp, value.into());
+        self
+    }
+
+    /// Adds an option value using a structured option value format.
+    pub fn option_as<T: OptionValueType>(
+        mut self,
+        tp: CoapOption,
+        value: T,
+    ) -> Self {
+        self.packet.add_option_as(tp, value);
+        self
+    }
+
+    /// Sets the payload.
+    pub fn payload(mut self, payload: impl Into<Vec<u8>>) -> Self {
+        self.packet.payload = payload.into();
+        self
+    }
+
+    fn fail(mut self, error: BuildError) -> Self {
+        self.error.get_or_insert(error);
+        self
+    }
+
+    /// Checks the message and returns it.
+    pub fn build(self) -> Result<Packet, BuildError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+
+        let packet = self.packet;
+        let token_length = packet.get_token().len();
+        if extended_token_length(token_length).is_err() {
+            return Err(BuildError::InvalidTokenLength(token_length));
+        }
+
+        let is_request = match packet.header.code {
+            MessageClass::Request(_) => true,
+            MessageClass::Response(_) => false,
+            _ => return Err(BuildError::InvalidCode),
+        };
+        for (number, value) in packet.options.iter() {
+            let option = CoapOption::from(number);
+            if !belongs_on(option, is_request) {
+                return Err(BuildError::UnexpectedOption(number));
+            }
+            if builtin_properties(option)
+                .is_some_and(|properties| !properties.is_valid_value(value))
+            {
+                return Err(BuildError::InvalidOptionValue(number));
+            }
+        }
+
+        Ok(packet)
+    }
+}
+
+/// Returns whether an option may be included in a request or a response
+/// respectively.
+fn belongs_on(option: CoapOption, is_request: bool) -> bool {
+    match option {
+        CoapOption::IfMatch
+        | CoapOption::UriHost
+        | CoapOption::IfNoneMatch
+        | CoapOption::UriPort
+        | CoapOption::UriPath
+        | CoapOption::UriQuery
+        | CoapOption::Accept
+        | CoapOption::ProxyUri
+        | CoapOption::ProxyScheme
+        | CoapOption::NoResponse => is_request,
+        CoapOption::LocationPath
+        | CoapOption::LocationQuery
+        | CoapOption::MaxAge => !is_request,
+        _ => true,
+    }
+}
+
+impl Packet {
+    /// Returns a builder for a Confirmable request.
+    pub fn request(method: Method) -> PacketBuilder {
+        PacketBuilder::request(method)
+    }
+
+    /// Returns a builder for a response to `request`.
+    ///
+    /// See [`PacketBuilder::response`].
+    pub fn response(request: &Packet) -> PacketBuilder {
+        PacketBuilder::response(request)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::option_value::OptionValueString;
+
+    #[test]
+    fn request() {
+        let request = Packet::request(Method::Post)
+            .non_confirmable()
+            .message_id(7)
+            .token(vec![1, 2, 3])
+            .host("example.com")
+            .port(5684)
+            .uri("/a/b?x=1&y=2")
+            .accept(ContentFormat::ApplicationCBOR)
+            .content_format(ContentFormat::ApplicationJSON)
+            .payload("{}")
+            .build()
+            .unwrap();
+
+        let mut expected = Packet::new();
+        expected.header.set_type(MessageType::NonConfirmable);
+        expected.header.code = MessageClass::Request(Method::Post);
+        expected.header.message_id = 7;
+        expected.set_token(vec![1, 2, 3]);
+        expected.add_option(CoapOption::UriHost, b"example.com".to_vec());
+        expected.add_option(CoapOption::UriPort, vec![0x16, 0x34]);
+        expected.add_option(CoapOption::UriPath, b"a".to_vec());
+        expected.add_option(CoapOption::UriPath, b"b".to_vec());
+        expected.add_option(CoapOption::UriQuery, b"x=1".to_vec());
+        expected.add_option(CoapOption::UriQuery, b"y=2".to_vec());
+        expected.add_option(CoapOption::Accept, vec![60]);
+        expected.add_option(CoapOption::ContentFormat, vec![50]);
+        expected.payload = b"{}".to_vec();
+        assert_eq!(request, expected);
+
+        let root = Packet::request(Method::Get).uri("/").build().unwrap();
+        assert_eq!(root.get_option(CoapOption::UriPath), None);
+        let segments = Packet::request(Method::Get)
+            .path("a//b")
+            .build()
+            .unwrap()
+            .get_options_as::<OptionValueString>(CoapOption::UriPath)
+            .unwrap()
+            .into_iter()
+            .map(|segment| segment.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(segments, ["a", "", "b"]);
+    }
+
+    #[test]
+    fn response() {
+        let request = Packet::request(Method::Get)
+            .message_id(42)
+            .token(vec![0xAB])
+            .build()
+            .unwrap();
+        let response = Packet::response(&request)
+            .status(ResponseType::Created)
+            .option(CoapOption::LocationPath, "new")
+            .build()
+            .unwrap();
+        assert_eq!(response.header.get_type(), MessageType::Acknowledgement);
+        assert_eq!(response.header.message_id, 42);
+        assert_eq!(response.get_token(), [0xAB]);
+        assert_eq!(
+            response.header.code,
+            MessageClass::Response(ResponseType::Created)
+        );
+
+        let mut ack = Packet::new();
+        ack.header.set_type(MessageType::Acknowledgement);
+        assert_eq!(
+            Packet::response(&ack).build(),
+            Err(BuildError::InvalidRequestType)
+        );
+    }
+
+    #[test]
+    fn validation() {
+        assert_eq!(
+            Packet::request(Method::Get).token(vec![0; 9]).build(),
+            Err(BuildError::InvalidTokenLength(9))
+        );
+        assert_eq!(
+            Packet::request(Method::Get)
+                .option(CoapOption::LocationPath, "a")
+                .build(),
+            Err(BuildError::UnexpectedOption(8))
+        );
+        assert_eq!(
+            Packet::request(Method::Get)
+                .status(ResponseType::Content)
+                .accept(ContentFormat::TextPlain)
+                .build(),
+            Err(BuildError::UnexpectedOption(17))
+        );
+        assert_eq!(
+            Packet::request(Method::Get).host("").build(),
+            Err(BuildError::InvalidOptionValue(3))
+        );
+        assert_eq!(
+            Packet::request(Method::Get).uri("/a#b").build(),
+            Err(BuildError::InvalidUri)
+        );
+    }
+}
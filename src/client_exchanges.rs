@@ -0,0 +1,489 @@
+//! Matching of incoming messages to the requests of a client (RFC 7252,
+//! Section 5.3.2).
+//!
+//! Responses are matched to requests by the endpoint they came from and
+//! their token, and piggybacked responses additionally by the message ID of
+//! the Confirmable request they acknowledge. Like [`crate::ReliabilityLayer`]
+//! this doesn't perform any I/O, so it can be combined with any transport.
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
+use core::time::Duration;
+
+use crate::{
+    header::{MessageClass, MessageType},
+    packet::Packet,
+};
+
+/// How an incoming packet relates to the outstanding requests of a
+/// [`ClientExchanges`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExchangeMatch {
+    /// The packet is the response to the contained request, which is no
+    /// longer outstanding.
+    Response(Packet),
+    /// The packet is a notification for the observation started by the
+    /// contained request, which remains outstanding.
+    Notification(Packet),
+    /// The packet is one of the responses to the contained multicast request,
+    /// which remains outstanding until it is cancelled.
+    GroupResponse(Packet),
+    /// The packet is an Empty ACK for the contained request, whose response
+    /// will follow in a separate message.
+    Acknowledged(Packet),
+    /// The peer rejected the contained request with a Reset message, so it is
+    /// no longer outstanding.
+    Reset(Packet),
+    /// The packet is a duplicate of a response that was already matched and
+    /// should not be processed again. If it is Confirmable, it still needs
+    /// to be acknowledged (see [`crate::CoapResponse::empty_ack`]).
+    Duplicate,
+    /// The packet doesn't belong to any outstanding request, e.g. because it
+    /// came from the wrong peer, and the contained Reset should be sent back.
+    Unexpected(Packet),
+    /// The packet should be silently ignored, e.g. because it acknowledges
+    /// a request that is no longer outstanding or isn't a response at all.
+    Ignored,
+}
+
+/// A request awaiting its response.
+#[derive(Debug, Clone)]
+struct Exchange {
+    request: Packet,
+    /// Whether the request registered an observation.
+    observe: bool,
+}
+
+/// Keeps track of the outstanding requests of a client and classifies
+/// incoming packets accordingly.
+///
+/// Time is passed in by the caller as the duration since an arbitrary but
+/// fixed point, like for [`crate::ReliabilityLayer`], and is only used to
+/// recognize duplicates of separate responses and notifications.
+#[derive(Debug, Clone)]
+pub struct ClientExchanges<Endpoint: Ord + Clone> {
+    exchange_lifetime: Duration,
+    exchanges: BTreeMap<(Endpoint, Vec<u8>), Exchange>,
+    /// The multicast requests by their token, which may be answered by any
+    /// endpoint.
+    groups: BTreeMap<Vec<u8>, Packet>,
+    /// The endpoints and message IDs of recently matched Confirmable and
+    /// Non-confirmable messages, along with the time they expire, in the
+    /// order they were received.
+    recent: VecDeque<(Endpoint, u16, Duration)>,
+}
+
+impl<Endpoint: Ord + Clone> ClientExchanges<Endpoint> {
+    /// Creates a new table that remembers matched messages for
+    /// `exchange_lifetime`, which should be EXCHANGE_LIFETIME (see
+    /// [`crate::TransmissionParameters::exchange_lifetime`]).
+    pub fn new(exchange_lifetime: Duration) -> Self {
+        Self {
+            exchange_lifetime,
+            exchanges: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Registers a request sent to an endpoint, replacing any outstanding
+    /// request to the endpoint with the same token.
+    ///
+    /// Requests with an Observe option of 0 stay outstanding after their
+    /// first response for as long as notifications arrive.
+    pub fn register(&mut self, endpoint: Endpoint, request: Packet) {
+        let observe = matches!(request.get_observe_value(), Some(Ok(0)));
+        self.exchanges.insert(
+            (endpoint, request.get_token().to_vec()),
+            Exchange { request, observe },
+        );
+    }
+
+    /// Registers a request sent to a multicast address (RFC 7252, Section
+    /// 8), which collects the responses of any endpoint with its token until
+    /// it is cancelled, e.g. once the caller's deadline for responses has
+    /// passed.
+    pub fn register_multicast(&mut self, request: Packet) {
+        self.groups.insert(request.get_token().to_vec(), request);
+    }
+
+    /// Removes the outstanding multicast request with a token, returning the
+    /// request if there was one.
+    pub fn cancel_multicast(&mut self, token: &[u8]) -> Option<Packet> {
+        self.groups.remove(token)
+    }
+
+    /// Removes the outstanding request to an endpoint with a token, e.g.
+    /// because it timed out or the observation is no longer of interest,
+    /// returning the request if there was one.
+    pub fn cancel(
+        &mut self,
+        endpoint: &Endpoint,
+        token: &[u8],
+    ) -> Option<Packet> {
+        self.exchanges
+            .remove(&(endpoint.clone(), token.to_vec()))
+            .map(|exchange| exchange.request)
+    }
+
+    /// Returns whether there is an outstanding request to an endpoint with a
+    /// token.
+    pub fn is_outstanding(&self, endpoint: &Endpoint, token: &[u8]) -> bool {
+        self.exchanges
+            .contains_key(&(endpoint.clone(), token.to_vec()))
+    }
+
+    /// Returns the number of outstanding requests.
+    pub fn len(&self) -> usize {
+        self.exchanges.len() + self.groups.len()
+    }
+
+    /// Returns whether there are no outstanding requests.
+    pub fn is_empty(&self) -> bool {
+        self.exchanges.is_empty() && self.groups.is_empty()
+    }
+
+    /// Classifies a packet received from an endpoint, completing the request
+    /// it answers.
+    pub fn handle_incoming(
+        &mut self,
+        endpoint: &Endpoint,
+        packet: &Packet,
+        now: Duration,
+    ) -> ExchangeMatch {
+        while self
+            .recent
+            .front()
+            .is_some_and(|(_, _, until)| *until <= now)
+        {
+            self.recent.pop_front();
+        }
+
+        let message_id = packet.header.message_id;
+        match (packet.header.get_type(), packet.header.code) {
+            (MessageType::Acknowledgement, MessageClass::Empty) => {
+                match self.find_by_message_id(
+                    endpoint,
+                    message_id,
+                    MessageType::Acknowledgement,
+                ) {
+                    Some(key) => ExchangeMatch::Acknowledged(
+                        self.exchanges[&key].request.clone(),
+                    ),
+                    None => ExchangeMatch::Ignored,
+                }
+            }
+            (MessageType::Reset, MessageClass::Empty) => {
+                match self.find_by_message_id(
+                    endpoint,
+                    message_id,
+                    MessageType::Reset,
+                ) {
+                    Some(key) => ExchangeMatch::Reset(
+                        self.exchanges.remove(&key).unwrap().request,
+                    ),
+                    None => ExchangeMatch::Ignored,
+                }
+            }
+            (MessageType::Acknowledgement, MessageClass::Response(_)) => {
+                match self.find_by_message_id(
+                    endpoint,
+                    message_id,
+                    MessageType::Acknowledgement,
+                ) {
+                    // An ACK with the wrong token still acknowledges the
+                    // request, but doesn't carry its response
+                    Some(key) if key.1 != packet.get_token() => {
+                        ExchangeMatch::Acknowledged(
+                            self.exchanges[&key].request.clone(),
+                        )
+                    }
+                    Some(key) => self.complete(key, packet),
+                    None => ExchangeMatch::Ignored,
+                }
+            }
+            (
+                MessageType::Confirmable | MessageType::NonConfirmable,
+                MessageClass::Empty | MessageClass::Response(_),
+            ) => {
+                if self.recent.iter().any(|(candidate, id, _)| {
+                    candidate == endpoint && *id == message_id
+                }) {
+                    return ExchangeMatch::Duplicate;
+                }
+
+                let key = (endpoint.clone(), packet.get_token().to_vec());
+                let group = self.groups.get(&key.1);
+                if packet.header.code == MessageClass::Empty
+                    || !self.exchanges.contains_key(&key) && group.is_none()
+                {
+                    return ExchangeMatch::Unexpected(Packet::reset(
+                        message_id,
+                    ));
+                }
+
+                let group = group.cloned();
+                self.recent.push_back((
+                    endpoint.clone(),
+                    message_id,
+                    now.saturating_add(self.exchange_lifetime),
+                ));
+                match group {
+                    Some(request) if !self.exchanges.contains_key(&key) => {
+                        ExchangeMatch::GroupResponse(request)
+                    }
+                    _ => self.complete(key, packet),
+                }
+            }
+            _ => ExchangeMatch::Ignored,
+        }
+    }
+
+    /// Returns the key of the outstanding request to an endpoint with a
+    /// message ID, which an incoming message of the given type can refer to:
+    /// Acknowledgements only match Confirmable requests, while a Reset may
+    /// also reject a Non-confirmable one (RFC 7252, Section 4.3).
+    fn find_by_message_id(
+        &self,
+        endpoint: &Endpoint,
+        message_id: u16,
+        message_type: MessageType,
+    ) -> Option<(Endpoint, Vec<u8>)> {
+        self.exchanges
+            .iter()
+            .find(|((candidate, _), exchange)| {
+                let matches_type = match exchange.request.header.get_type() {
+                    MessageType::Confirmable => true,
+                    MessageType::NonConfirmable => {
+                        message_type == MessageType::Reset
+                    }
+                    _ => false,
+                };
+                candidate == endpoint
+                    && matches_type
+                    && exchange.request.header.message_id == message_id
+            })
+            .map(|(key, _)| key.clone())
+    }
+
+    /// Matches a response to the outstanding request with the given key,
+    /// which is kept if the response is a notification.
+    fn complete(
+        &mut self,
+        key: (Endpoint, Vec<u8>),
+        response: &Packet,
+    ) -> ExchangeMatch {
+        let exchange = &self.exchanges[&key];
+        if exchange.observe && response.get_observe_value().is_some() {
+            return ExchangeMatch::Notification(exchange.request.clone());
+        }
+        ExchangeMatch::Response(self.exchanges.remove(&key).unwrap().request)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{RequestType, ResponseType};
+
+    const LIFETIME: Duration = Duration::from_secs(247);
+
+    fn request(message_id: u16, token: &[u8]) -> Packet {
+        Packet::request(RequestType::Get)
+            .message_id(message_id)
+            .token(token)
+            .build()
+            .unwrap()
+    }
+
+    fn response(
+        message_type: MessageType,
+        message_id: u16,
+        token: &[u8],
+    ) -> Packet {
+        let mut packet = Packet::new();
+        packet.header.set_type(message_type);
+        packet.header.code = MessageClass::Response(ResponseType::Content);
+        packet.header.message_id = message_id;
+        packet.set_token(token.to_vec());
+        packet
+    }
+
+    #[test]
+    fn piggybacked_responses() {
+        let mut exchanges = ClientExchanges::new(LIFETIME);
+        exchanges.register("a", request(1, b"t1"));
+        exchanges.register("a", request(2, b"t2"));
+        assert_eq!(exchanges.len(), 2);
+
+        let ack = response(MessageType::Acknowledgement, 1, b"t1");
+        assert_eq!(
+            exchanges.handle_incoming(&"b", &ack, Duration::ZERO),
+            ExchangeMatch::Ignored,
+            "wrong peer"
+        );
+        assert_eq!(
+            exchanges.handle_incoming(&"a", &ack, Duration::ZERO),
+            ExchangeMatch::Response(request(1, b"t1"))
+        );
+        assert_eq!(
+            exchanges.handle_incoming(&"a", &ack, Duration::ZERO),
+            ExchangeMatch::Ignored
+        );
+
+        let wrong_token = response(MessageType::Acknowledgement, 2, b"xx");
+        assert_eq!(
+            exchanges.handle_incoming(&"a", &wrong_token, Duration::ZERO),
+            ExchangeMatch::Acknowledged(request(2, b"t2"))
+        );
+        assert!(exchanges.is_outstanding(&"a", b"t2"));
+
+        assert_eq!(
+            exchanges.handle_incoming(&"a", &Packet::reset(2), Duration::ZERO),
+            ExchangeMatch::Reset(request(2, b"t2"))
+        );
+        assert!(exchanges.is_empty());
+    }
+
+    #[test]
+    fn reset_non_confirmable_requests() {
+        let mut exchanges = ClientExchanges::new(LIFETIME);
+        let mut non = request(3, b"t3");
+        non.header.set_type(MessageType::NonConfirmable);
+        exchanges.register("a", non.clone());
+
+        assert_eq!(
+            exchanges.handle_incoming(
+                &"a",
+                &Packet::empty_ack(3),
+                Duration::ZERO
+            ),
+            ExchangeMatch::Ignored,
+            "only Confirmable requests are acknowledged"
+        );
+        assert_eq!(
+            exchanges.handle_incoming(&"a", &Packet::reset(3), Duration::ZERO),
+            ExchangeMatch::Reset(non)
+        );
+        assert!(exchanges.is_empty());
+    }
+
+    #[test]
+    fn separate_responses() {
+        let mut exchanges = ClientExchanges::new(LIFETIME);
+        exchanges.register("a", request(1, b"t1"));
+
+        assert_eq!(
+            exchanges.handle_incoming(
+                &"a",
+                &Packet::empty_ack(1),
+                Duration::ZERO
+            ),
+            ExchangeMatch::Acknowledged(request(1, b"t1"))
+        );
+
+        let separate = response(MessageType::Confirmable, 7, b"t1");
+        assert_eq!(
+            exchanges.handle_incoming(&"b", &separate, Duration::ZERO),
+            ExchangeMatch::Unexpected(Packet::reset(7)),
+            "wrong peer"
+        );
+        assert_eq!(
+            exchanges.handle_incoming(&"a", &separate, Duration::ZERO),
+            ExchangeMatch::Response(request(1, b"t1"))
+        );
+        assert_eq!(
+            exchanges.handle_incoming(&"a", &separate, Duration::ZERO),
+            ExchangeMatch::Duplicate
+        );
+        assert_eq!(
+            exchanges.handle_incoming(&"a", &separate, LIFETIME),
+            ExchangeMatch::Unexpected(Packet::reset(7))
+        );
+
+        assert_eq!(
+            exchanges.handle_incoming(&"a", &Packet::ping(8), Duration::ZERO),
+            ExchangeMatch::Unexpected(Packet::reset(8))
+        );
+        assert_eq!(
+            exchanges.handle_incoming(&"a", &request(9, b""), Duration::ZERO),
+            ExchangeMatch::Ignored
+        );
+    }
+
+    #[test]
+    fn notifications() {
+        let mut exchanges = ClientExchanges::new(LIFETIME);
+        let mut observe = request(1, b"o");
+        observe.set_observe_value(0);
+        exchanges.register("a", observe.clone());
+
+        let mut notification = response(MessageType::Acknowledgement, 1, b"o");
+        notification.set_observe_value(10);
+        assert_eq!(
+            exchanges.handle_incoming(&"a", &notification, Duration::ZERO),
+            ExchangeMatch::Notification(observe.clone())
+        );
+
+        notification.header.set_type(MessageType::NonConfirmable);
+        notification.header.message_id = 2;
+        notification.set_observe_value(11);
+        assert_eq!(
+            exchanges.handle_incoming(&"a", &notification, Duration::ZERO),
+            ExchangeMatch::Notification(observe.clone())
+        );
+
+        // A response without Observe option ends the observation
+        let last = response(MessageType::NonConfirmable, 3, b"o");
+        assert_eq!(
+            exchanges.handle_incoming(&"a", &last, Duration::ZERO),
+            ExchangeMatch::Response(observe.clone())
+        );
+        assert!(exchanges.is_empty());
+
+        exchanges.register("a", observe);
+        assert!(exchanges.cancel(&"a", b"o").is_some());
+        assert_eq!(exchanges.cancel(&"a", b"o"), None);
+    }
+
+    #[test]
+    fn multicast_responses() {
+        let mut exchanges = ClientExchanges::new(LIFETIME);
+        let mut discovery = request(1, b"g");
+        discovery.header.set_type(MessageType::NonConfirmable);
+        exchanges.register_multicast(discovery.clone());
+
+        for (endpoint, message_id) in [("a", 10), ("b", 10), ("a", 11)] {
+            let response =
+                response(MessageType::NonConfirmable, message_id, b"g");
+            assert_eq!(
+                exchanges.handle_incoming(
+                    &endpoint,
+                    &response,
+                    Duration::ZERO
+                ),
+                ExchangeMatch::GroupResponse(discovery.clone())
+            );
+        }
+        let duplicate = response(MessageType::Confirmable, 10, b"g");
+        assert_eq!(
+            exchanges.handle_incoming(&"b", &duplicate, Duration::ZERO),
+            ExchangeMatch::Duplicate
+        );
+        let other = response(MessageType::NonConfirmable, 12, b"x");
+        assert_eq!(
+            exchanges.handle_incoming(&"a", &other, Duration::ZERO),
+            ExchangeMatch::Unexpected(Packet::reset(12))
+        );
+
+        assert_eq!(exchanges.len(), 1);
+        assert_eq!(exchanges.cancel_multicast(b"g"), Some(discovery));
+        let late = response(MessageType::NonConfirmable, 13, b"g");
+        assert_eq!(
+            exchanges.handle_incoming(&"c", &late, Duration::ZERO),
+            ExchangeMatch::Unexpected(Packet::reset(13))
+        );
+    }
+}
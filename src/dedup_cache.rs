@@ -0,0 +1,207 @@
+//! Detection of duplicate messages for servers (RFC 7252, Section 4.5).
+//!
+//! Retransmitted requests are recognized by their source endpoint and message
+//! ID, and answered with the response produced for the original request
+//! instead of being processed again.
+
+use core::time::Duration;
+
+use lru_time_cache::LruCache;
+
+use crate::{CoapRequest, MessageType, Packet};
+
+/// EXCHANGE_LIFETIME from RFC 7252, the time after which a message ID may be
+/// reused by the sender, with the default transmission parameters.
+const DEFAULT_EXCHANGE_LIFETIME: Duration = Duration::from_secs(247);
+
+/// Default number of exchanges to remember.
+const DEFAULT_CAPACITY: usize = 1024;
+
+/// Remembers recent requests and the responses sent to them, so that
+/// duplicates can be answered without processing them again.
+pub struct DedupCache<Endpoint: Ord + Clone> {
+    /// Maps the source and message ID of a request to the response, which is
+    /// `None` until the response has been produced.
+    exchanges: LruCache<(Endpoint, u16), Option<Packet>>,
+}
+
+/// The configuration for [`DedupCache`].
+pub struct DedupCacheConfig {
+    /// Length of time for which a request is remembered.  Should be at least
+    /// EXCHANGE_LIFETIME (247 seconds with the default transmission
+    /// parameters), since a peer may retransmit for that long.
+    pub exchange_lifetime: Duration,
+
+    /// Maximum number of requests to remember, evicting the least recently
+    /// seen ones first.  This bounds the memory used when many requests come
+    /// in within the exchange lifetime.
+    pub capacity: usize,
+}
+
+impl Default for DedupCacheConfig {
+    fn default() -> Self {
+        Self {
+            exchange_lifetime: DEFAULT_EXCHANGE_LIFETIME,
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+}
+
+impl<Endpoint: Ord + Clone> DedupCache<Endpoint> {
+    /// Creates a new cache which is expected to be re-used across all
+    /// requests received by a server.
+    pub fn new(config: DedupCacheConfig) -> Self {
+        Self {
+            exchanges: LruCache::with_expiry_duration_and_capacity(
+                config.exchange_lifetime,
+                config.capacity,
+            ),
+        }
+    }
+
+    /// Intercepts a request before application processing has occurred.
+    ///
+    /// Returns true if the request is a duplicate and no further processing
+    /// should occur.  The response of a Confirmable request is then replaced
+    /// by the response sent for the original, which should be sent to the
+    /// peer, or removed if there is none (yet).  Duplicate Non-confirmable
+    /// requests are silently ignored, so their response is always removed.
+    /// Returns false if the request is new (or its source is unknown) and
+    /// handling should proceed to the application normally, followed by
+    /// [`DedupCache::intercept_response`].
+    pub fn intercept_request(
+        &mut self,
+        request: &mut CoapRequest<Endpoint>,
+    ) -> bool {
+        let key = match Self::key(request) {
+            Some(key) => key,
+            None => return false,
+        };
+
+        match self.exchanges.peek(&key) {
+            Some(cached) => {
+                let confirmable = request.message.header.get_type()
+                    == MessageType::Confirmable;
+                match (cached, &mut request.response) {
+                    (Some(cached), Some(response)) if confirmable => {
+                        response.message = cached.clone()
+                    }
+                    _ => request.response = None,
+                }
+                true
+            }
+            None => {
+                self.exchanges.insert(key, None);
+                false
+            }
+        }
+    }
+
+    /// Intercepts a prepared response before it is to be delivered over the
+    /// network, remembering it so that it can be re-sent for duplicates of
+    /// the request if it is Confirmable.
+    pub fn intercept_response(&mut self, request: &CoapRequest<Endpoint>) {
+        let key = match Self::key(request) {
+            Some(key) => key,
+            None => return,
+        };
+        if request.message.header.get_type() != MessageType::Confirmable {
+            return;
+        }
+
+        if self.exchanges.contains_key(&key) {
+            let response =
+                request.response.as_ref().map(|response| &response.message);
+            self.exchanges.insert(key, response.cloned());
+        }
+    }
+
+    /// Returns the key identifying the exchange of a request, unless it has
+    /// no source or isn't Confirmable or Non-confirmable.
+    fn key(request: &CoapRequest<Endpoint>) -> Option<(Endpoint, u16)> {
+        match request.message.header.get_type() {
+            MessageType::Confirmable | MessageType::NonConfirmable => request
+                .source
+                .clone()
+                .map(|source| (source, request.message.header.message_id)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{MessageClass, RequestType, ResponseType};
+
+    fn request(source: &str, message_id: u16) -> CoapRequest<String> {
+        let mut packet = Packet::new();
+        packet.header.code = MessageClass::Request(RequestType::Post);
+        packet.header.message_id = message_id;
+        CoapRequest::from_packet(packet, source.into())
+    }
+
+    #[test]
+    fn replays_responses() {
+        let mut cache = DedupCache::new(DedupCacheConfig::default());
+
+        let mut original = request("a", 1);
+        assert!(!cache.intercept_request(&mut original));
+        let response = original.response.as_mut().unwrap();
+        response.message.header.code =
+            MessageClass::Response(ResponseType::Created);
+        response.message.payload = b"created".to_vec();
+        cache.intercept_response(&original);
+
+        let mut duplicate = request("a", 1);
+        assert!(cache.intercept_request(&mut duplicate));
+        assert_eq!(duplicate.response, original.response);
+
+        // Duplicates of Non-confirmable requests are ignored
+        let mut non = request("a", 3);
+        non.message.header.set_type(MessageType::NonConfirmable);
+        assert!(!cache.intercept_request(&mut non.clone()));
+        cache.intercept_response(&non);
+        assert!(cache.intercept_request(&mut non));
+        assert!(non.response.is_none());
+
+        // Other sources and message IDs are separate exchanges
+        assert!(!cache.intercept_request(&mut request("b", 1)));
+        assert!(!cache.intercept_request(&mut request("a", 2)));
+    }
+
+    #[test]
+    fn drops_duplicates_in_progress() {
+        let mut cache = DedupCache::new(DedupCacheConfig::default());
+        assert!(!cache.intercept_request(&mut request("a", 1)));
+
+        let mut duplicate = request("a", 1);
+        assert!(cache.intercept_request(&mut duplicate));
+        assert!(duplicate.response.is_none());
+
+        let mut unknown_source = CoapRequest::<String>::new();
+        assert!(!cache.intercept_request(&mut unknown_source));
+        assert!(!cache.intercept_request(&mut unknown_source));
+    }
+
+    #[test]
+    fn bounded_and_expiring() {
+        let mut cache = DedupCache::new(DedupCacheConfig {
+            capacity: 2,
+            ..Default::default()
+        });
+        for message_id in 0..3 {
+            assert!(!cache.intercept_request(&mut request("a", message_id)));
+        }
+        assert!(!cache.intercept_request(&mut request("a", 0)));
+        assert!(cache.intercept_request(&mut request("a", 2)));
+
+        let mut cache = DedupCache::new(DedupCacheConfig {
+            exchange_lifetime: Duration::from_millis(10),
+            ..Default::default()
+        });
+        assert!(!cache.intercept_request(&mut request("a", 1)));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!cache.intercept_request(&mut request("a", 1)));
+    }
+}
@@ -0,0 +1,572 @@
+//! Human-readable rendering of messages for debugging, e.g.
+//! `CON GET mid=0x1234 token=7d34 Uri-Path: "sensors" "temp"`.
+
+use alloc::string::String;
+use core::{
+    convert::TryFrom,
+    fmt::{self, Write},
+};
+
+use crate::{
+    header::{
+        Header, MessageClass, MessageType, RequestType, ResponseType,
+        SignalingType,
+    },
+    option_registry::{builtin_properties, OptionFormat},
+    packet::{CoapOption, ContentFormat, Packet},
+    packet_ref::PacketRef,
+};
+
+/// How deeply nested CBOR data items may be before giving up on rendering
+/// them.
+const MAX_CBOR_DEPTH: usize = 16;
+
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_message(
+            f,
+            &self.header,
+            self.get_token(),
+            self.options.iter(),
+            &self.payload,
+        )
+    }
+}
+
+impl fmt::Display for PacketRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_message(
+            f,
+            &self.header,
+            self.get_token(),
+            self.options(),
+            self.payload,
+        )
+    }
+}
+
+fn fmt_message<'a>(
+    f: &mut fmt::Formatter,
+    header: &Header,
+    token: &[u8],
+    options: impl Iterator<Item = (u16, &'a [u8])> + Clone,
+    payload: &[u8],
+) -> fmt::Result {
+    // Signaling messages only exist over reliable transports (RFC 8323),
+    // which have neither message types nor message IDs
+    let is_signaling = matches!(header.code, MessageClass::Signaling(_));
+    if !is_signaling {
+        let message_type = match header.get_type() {
+            MessageType::Confirmable => "CON",
+            MessageType::NonConfirmable => "NON",
+            MessageType::Acknowledgement => "ACK",
+            MessageType::Reset => "RST",
+        };
+        write!(f, "{} ", message_type)?;
+    }
+    fmt_code(f, header.code)?;
+    if !is_signaling {
+        write!(f, " mid={:#06x}", header.message_id)?;
+    }
+    if !token.is_empty() {
+        f.write_str(" token=")?;
+        fmt_hex(f, token)?;
+    }
+
+    let mut previous = None;
+    for (number, value) in options.clone() {
+        let option = CoapOption::from(number);
+        let properties = builtin_properties(option);
+        if previous != Some(number) {
+            match properties {
+                Some(properties) => write!(f, " {}", properties.name)?,
+                None => write!(f, " Option {}", number)?,
+            }
+            if properties.map(|properties| properties.format)
+                == Some(OptionFormat::Empty)
+                && value.is_empty()
+            {
+                previous = Some(number);
+                continue;
+            }
+            f.write_str(":")?;
+        }
+        f.write_str(" ")?;
+        fmt_option_value(f, option, properties.map(|p| p.format), value)?;
+        previous = Some(number);
+    }
+
+    if !payload.is_empty() {
+        let content_format = options
+            .filter(|&(number, _)| {
+                number == u16::from(CoapOption::ContentFormat)
+            })
+            .find_map(|(_, value)| decode_uint(value))
+            .and_then(|value| usize::try_from(value).ok())
+            .map(ContentFormat::try_from);
+        f.write_str(" Payload: ")?;
+        fmt_payload(f, content_format, payload)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the code, using the method name for requests and the dotted form
+/// followed by the name for everything else.
+fn fmt_code(f: &mut fmt::Formatter, code: MessageClass) -> fmt::Result {
+    let name = match code {
+        MessageClass::Request(method) => {
+            return f.write_str(match method {
+                RequestType::Get => "GET",
+                RequestType::Post => "POST",
+                RequestType::Put => "PUT",
+                RequestType::Delete => "DELETE",
+                RequestType::Fetch => "FETCH",
+                RequestType::Patch => "PATCH",
+                RequestType::IPatch => "iPATCH",
+                RequestType::UnKnown => "Unknown",
+            })
+        }
+        MessageClass::Empty => "Empty",
+        MessageClass::Response(status) => response_name(status),
+        MessageClass::Signaling(signal) => match signal {
+            SignalingType::Csm => "CSM",
+            SignalingType::Ping => "Ping",
+            SignalingType::Pong => "Pong",
+            SignalingType::Release => "Release",
+            SignalingType::Abort => "Abort",
+            SignalingType::UnKnown => "Unknown",
+        },
+        MessageClass::Reserved(_) => return write!(f, "{}", code),
+    };
+    write!(f, "{} {}", code, name)
+}
+
+fn response_name(status: ResponseType) -> &'static str {
+    match status {
+        ResponseType::Created => "Created",
+        ResponseType::Deleted => "Deleted",
+        ResponseType::Valid => "Valid",
+        ResponseType::Changed => "Changed",
+        ResponseType::Content => "Content",
+        ResponseType::Continue => "Continue",
+        ResponseType::BadRequest => "Bad Request",
+        ResponseType::Unauthorized => "Unauthorized",
+        ResponseType::BadOption => "Bad Option",
+        ResponseType::Forbidden => "Forbidden",
+        ResponseType::NotFound => "Not Found",
+        ResponseType::MethodNotAllowed => "Method Not Allowed",
+        ResponseType::NotAcceptable => "Not Acceptable",
+        ResponseType::Conflict => "Conflict",
+        ResponseType::PreconditionFailed => "Precondition Failed",
+        ResponseType::RequestEntityTooLarge => "Request Entity Too Large",
+        ResponseType::UnsupportedContentFormat => "Unsupported Content-Format",
+        ResponseType::RequestEntityIncomplete => "Request Entity Incomplete",
+        ResponseType::UnprocessableEntity => "Unprocessable Entity",
+        ResponseType::TooManyRequests => "Too Many Requests",
+        ResponseType::InternalServerError => "Internal Server Error",
+        ResponseType::NotImplemented => "Not Implemented",
+        ResponseType::BadGateway => "Bad Gateway",
+        ResponseType::ServiceUnavailable => "Service Unavailable",
+        ResponseType::GatewayTimeout => "Gateway Timeout",
+        ResponseType::ProxyingNotSupported => "Proxying Not Supported",
+        ResponseType::HopLimitReached => "Hop Limit Reached",
+        ResponseType::UnKnown => "Unknown",
+    }
+}
+
+fn fmt_option_value(
+    f: &mut fmt::Formatter,
+    option: CoapOption,
+    format: Option<OptionFormat>,
+    value: &[u8],
+) -> fmt::Result {
+    match (option, format, decode_uint(value)) {
+        (CoapOption::ContentFormat | CoapOption::Accept, _, Some(number)) => {
+            match usize::try_from(number).map(ContentFormat::try_from) {
+                Ok(Ok(content_format)) => {
+                    f.write_str(content_format.media_type())
+                }
+                _ => write!(f, "{}", number),
+            }
+        }
+        (CoapOption::Block1 | CoapOption::Block2, _, Some(block)) => {
+            write!(f, "{}/{}/", block >> 4, (block >> 3) & 0x1)?;
+            match block & 0x7 {
+                7 => f.write_str("BERT"),
+                size_exponent => write!(f, "{}", 1 << (size_exponent + 4)),
+            }
+        }
+        (_, Some(OptionFormat::UInt), Some(number)) => {
+            write!(f, "{}", number)
+        }
+        (_, Some(OptionFormat::String), _) => {
+            match core::str::from_utf8(value) {
+                Ok(text) => write!(f, "{:?}", text),
+                Err(_) => fmt_hex(f, value),
+            }
+        }
+        _ => fmt_hex(f, value),
+    }
+}
+
+fn fmt_payload(
+    f: &mut fmt::Formatter,
+    content_format: Option<Result<ContentFormat, impl fmt::Debug>>,
+    payload: &[u8],
+) -> fmt::Result {
+    let media_type = match content_format {
+        Some(Ok(content_format)) => content_format.media_type(),
+        // Unregistered formats are opaque to us
+        Some(Err(_)) => return fmt_hex(f, payload),
+        None => {
+            return match core::str::from_utf8(payload) {
+                Ok(text) if !text.chars().any(is_binary_char) => {
+                    write!(f, "{:?}", text)
+                }
+                _ => fmt_hex(f, payload),
+            }
+        }
+    };
+
+    let is_text = media_type.starts_with("text/")
+        || media_type.contains("json")
+        || media_type.contains("xml")
+        || media_type.contains("link-format")
+        || media_type.contains("javascript");
+    let is_cbor = media_type.contains("cbor")
+        || media_type.contains("cose")
+        || media_type.contains("cwt");
+
+    if media_type.contains("deflate") {
+        fmt_hex(f, payload)
+    } else if is_text {
+        match core::str::from_utf8(payload) {
+            Ok(text) => write!(f, "{:?}", text),
+            Err(_) => fmt_hex(f, payload),
+        }
+    } else if is_cbor {
+        let sequence = media_type.contains("cbor-seq");
+        match render_cbor(payload, sequence) {
+            Some(diagnostic) => f.write_str(&diagnostic),
+            None => fmt_hex(f, payload),
+        }
+    } else {
+        fmt_hex(f, payload)
+    }
+}
+
+fn is_binary_char(c: char) -> bool {
+    c.is_control() && !matches!(c, '\n' | '\r' | '\t')
+}
+
+fn fmt_hex(f: &mut fmt::Formatter, bytes: &[u8]) -> fmt::Result {
+    for byte in bytes {
+        write!(f, "{:02x}", byte)?;
+    }
+    Ok(())
+}
+
+/// Decodes an unsigned integer option value.
+fn decode_uint(value: &[u8]) -> Option<u64> {
+    if value.len() > 8 {
+        return None;
+    }
+    Some(
+        value
+            .iter()
+            .fold(0, |number, &byte| (number << 8) | u64::from(byte)),
+    )
+}
+
+/// Renders CBOR data in diagnostic notation (RFC 8949), or a sequence of
+/// data items separated by commas (RFC 8742). Returns `None` if the data is
+/// not well-formed.
+fn render_cbor(buf: &[u8], sequence: bool) -> Option<String> {
+    let mut reader = CborReader { buf, idx: 0 };
+    let mut out = String::new();
+    loop {
+        reader.item(&mut out, 0)?;
+        if reader.idx == buf.len() {
+            return Some(out);
+        }
+        if !sequence {
+            return None;
+        }
+        out.push_str(", ");
+    }
+}
+
+struct CborReader<'a> {
+    buf: &'a [u8],
+    idx: usize,
+}
+
+impl<'a> CborReader<'a> {
+    fn take(&mut self, length: u64) -> Option<&'a [u8]> {
+        let length = usize::try_from(length).ok()?;
+        let end = self.idx.checked_add(length)?;
+        let bytes = self.buf.get(self.idx..end)?;
+        self.idx = end;
+        Some(bytes)
+    }
+
+    /// Reads the initial byte and argument of a data item, returning the
+    /// major type, the additional information and the argument.
+    fn head(&mut self) -> Option<(u8, u8, u64)> {
+        let initial = *self.take(1)?.first()?;
+        let info = initial & 0x1F;
+        let argument = match info {
+            0..=23 => info.into(),
+            24..=27 => self
+                .take(1 << (info - 24))?
+                .iter()
+                .fold(0, |argument, &byte| (argument << 8) | u64::from(byte)),
+            31 => 0,
+            _ => return None,
+        };
+        Some((initial >> 5, info, argument))
+    }
+
+    /// Consumes the break stop code if it is next.
+    fn at_break(&mut self) -> bool {
+        if self.buf.get(self.idx) == Some(&0xFF) {
+            self.idx += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn item(&mut self, out: &mut String, depth: usize) -> Option<()> {
+        if depth > MAX_CBOR_DEPTH {
+            return None;
+        }
+
+        let (major, info, argument) = self.head()?;
+        let indefinite = info == 31;
+        match major {
+            0 => write!(out, "{}", argument).ok()?,
+            1 => write!(out, "{}", -1 - i128::from(argument)).ok()?,
+            2 | 3 if indefinite => {
+                out.push_str("(_ ");
+                let mut first = true;
+                while !self.at_break() {
+                    if !first {
+                        out.push_str(", ");
+                    }
+                    first = false;
+                    let (chunk_major, chunk_info, length) = self.head()?;
+                    if chunk_major != major || chunk_info == 31 {
+                        return None;
+                    }
+                    self.string(out, major, length)?;
+                }
+                out.push(')');
+            }
+            2 | 3 => self.string(out, major, argument)?,
+            4 => {
+                out.push_str(if indefinite { "[_ " } else { "[" });
+                let mut idx = 0;
+                while if indefinite {
+                    !self.at_break()
+                } else {
+                    idx < argument
+                } {
+                    if idx > 0 {
+                        out.push_str(", ");
+                    }
+                    self.item(out, depth + 1)?;
+                    idx += 1;
+                }
+                out.push(']');
+            }
+            5 => {
+                out.push_str(if indefinite { "{_ " } else { "{" });
+                let mut idx = 0;
+                while if indefinite {
+                    !self.at_break()
+                } else {
+                    idx < argument
+                } {
+                    if idx > 0 {
+                        out.push_str(", ");
+                    }
+                    self.item(out, depth + 1)?;
+                    out.push_str(": ");
+                    self.item(out, depth + 1)?;
+                    idx += 1;
+                }
+                out.push('}');
+            }
+            6 => {
+                write!(out, "{}(", argument).ok()?;
+                self.item(out, depth + 1)?;
+                out.push(')');
+            }
+            _ => match info {
+                20 => out.push_str("false"),
+                21 => out.push_str("true"),
+                22 => out.push_str("null"),
+                23 => out.push_str("undefined"),
+                25 => write_float(out, half_to_f32(argument as u16))?,
+                26 => write_float(out, f32::from_bits(argument as u32))?,
+                27 => write_float(out, f64::from_bits(argument))?,
+                31 => return None,
+                _ => write!(out, "simple({})", argument).ok()?,
+            },
+        }
+        Some(())
+    }
+
+    fn string(
+        &mut self,
+        out: &mut String,
+        major: u8,
+        length: u64,
+    ) -> Option<()> {
+        let bytes = self.take(length)?;
+        if major == 3 {
+            write!(out, "{:?}", core::str::from_utf8(bytes).ok()?).ok()
+        } else {
+            out.push_str("h'");
+            for byte in bytes {
+                write!(out, "{:02x}", byte).ok()?;
+            }
+            out.push('\'');
+            Some(())
+        }
+    }
+}
+
+fn write_float<T: fmt::Debug + Into<f64> + Copy>(
+    out: &mut String,
+    value: T,
+) -> Option<()> {
+    let float: f64 = value.into();
+    if float.is_nan() {
+        out.push_str("NaN");
+    } else if float.is_infinite() {
+        out.push_str(if float > 0.0 { "Infinity" } else { "-Infinity" });
+    } else {
+        write!(out, "{:?}", value).ok()?;
+    }
+    Some(())
+}
+
+/// Converts a half-precision float to single precision.
+fn half_to_f32(half: u16) -> f32 {
+    let sign = u32::from(half >> 15) << 31;
+    let exponent = u32::from((half >> 10) & 0x1F);
+    let mantissa = u32::from(half & 0x3FF);
+    match exponent {
+        0 => {
+            let magnitude = mantissa as f32 / (1 << 24) as f32;
+            if sign != 0 {
+                -magnitude
+            } else {
+                magnitude
+            }
+        }
+        0x1F => f32::from_bits(sign | 0x7F80_0000 | (mantissa << 13)),
+        _ => f32::from_bits(
+            sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::header::MessageClass;
+    use alloc::string::ToString;
+
+    #[test]
+    fn request() {
+        let mut packet = Packet::new();
+        packet.header.message_id = 0x1234;
+        packet.set_token(vec![0x7d, 0x34]);
+        packet.add_option(CoapOption::UriPath, b"sensors".to_vec());
+        packet.add_option(CoapOption::UriPath, b"temp".to_vec());
+        packet.add_option(CoapOption::Accept, vec![60]);
+        packet.add_option(CoapOption::Block2, vec![0x2A]);
+        packet.add_option(CoapOption::IfNoneMatch, vec![]);
+
+        let expected = "CON GET mid=0x1234 token=7d34 If-None-Match \
+                        Uri-Path: \"sensors\" \"temp\" Accept: \
+                        application/cbor Block2: 2/1/64";
+        assert_eq!(packet.to_string(), expected);
+
+        let bytes = packet.to_bytes().unwrap();
+        assert_eq!(
+            PacketRef::from_bytes(&bytes).unwrap().to_string(),
+            expected
+        );
+    }
+
+    #[test]
+    fn response() {
+        let mut packet = Packet::new();
+        packet.header.set_type(MessageType::Acknowledgement);
+        packet.header.code = MessageClass::Response(ResponseType::Content);
+        packet.set_content_format(ContentFormat::TextPlain);
+        packet.add_option(CoapOption::ETag, vec![0xAB, 0xCD]);
+        packet.add_option(CoapOption::Unknown(65000), vec![0x01]);
+        packet.payload = b"22.5 \xC2\xB0C".to_vec();
+        assert_eq!(
+            packet.to_string(),
+            "ACK 2.05 Content mid=0x0000 ETag: abcd Content-Format: \
+             text/plain; charset=utf-8 Option 65000: 01 Payload: \"22.5 °C\""
+        );
+
+        packet.header.code = MessageClass::Response(ResponseType::NotFound);
+        packet.clear_all_options();
+        packet.payload = vec![0x00, 0xFF];
+        assert_eq!(
+            packet.to_string(),
+            "ACK 4.04 Not Found mid=0x0000 Payload: 00ff"
+        );
+    }
+
+    #[test]
+    fn cbor_payload() {
+        let mut packet = Packet::new();
+        packet.header.code = MessageClass::Response(ResponseType::Content);
+        packet.set_content_format(ContentFormat::ApplicationCBOR);
+        // {"temp": -1.5, "ids": [1, 24], "raw": h'00ff', 1: true}
+        packet.payload = vec![
+            0xA4, 0x64, 0x74, 0x65, 0x6D, 0x70, 0xF9, 0xBE, 0x00, 0x63, 0x69,
+            0x64, 0x73, 0x82, 0x01, 0x18, 0x18, 0x63, 0x72, 0x61, 0x77, 0x42,
+            0x00, 0xFF, 0x01, 0xF5,
+        ];
+        assert_eq!(
+            packet.to_string(),
+            "CON 2.05 Content mid=0x0000 Content-Format: application/cbor \
+             Payload: {\"temp\": -1.5, \"ids\": [1, 24], \"raw\": h'00ff', \
+             1: true}"
+        );
+
+        // Truncated data falls back to hex
+        packet.payload = vec![0x82, 0x01];
+        assert!(packet.to_string().ends_with("Payload: 8201"));
+
+        assert_eq!(
+            render_cbor(
+                &[0x9F, 0x20, 0xC1, 0x1A, 0x00, 0x01, 0x00, 0x00, 0xFF],
+                false
+            ),
+            Some("[_ -1, 1(65536)]".into())
+        );
+        assert_eq!(
+            render_cbor(&[0x01, 0xF6, 0xFA, 0x7F, 0x80, 0x00, 0x00], true),
+            Some("1, null, Infinity".into())
+        );
+        assert_eq!(render_cbor(&[0x01, 0x02], false), None);
+    }
+
+    #[test]
+    fn signaling() {
+        let csm = crate::SignalingMessage::csm(Some(1152), false);
+        assert_eq!(csm.message.to_string(), "7.01 CSM Option 2: 0480");
+    }
+}
@@ -11,13 +11,16 @@ use std::error;
 use crate::ResponseType;
 
 /// The errors that can occur when encoding/decoding packets.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
 pub enum MessageError {
     InvalidHeader,
     InvalidPacketLength,
     InvalidTokenLength,
     InvalidOptionDelta,
     InvalidOptionLength,
+    BufferTooSmall,
+    InvalidEmptyMessage,
 }
 
 impl fmt::Display for MessageError {
@@ -38,6 +41,15 @@ impl fmt::Display for MessageError {
             MessageError::InvalidOptionLength => {
                 write!(f, "CoAP error: invalid option length")
             }
+            MessageError::BufferTooSmall => {
+                write!(f, "CoAP error: buffer too small for packet")
+            }
+            MessageError::InvalidEmptyMessage => {
+                write!(
+                    f,
+                    "CoAP error: Empty message with token, options or payload"
+                )
+            }
         }
     }
 }
@@ -45,6 +57,158 @@ impl fmt::Display for MessageError {
 #[cfg(feature = "std")]
 impl error::Error for MessageError {}
 
+#[cfg(feature = "std")]
+impl From<MessageError> for std::io::Error {
+    fn from(error: MessageError) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, error)
+    }
+}
+
+/// The rules of RFC 7252 that [`crate::StrictDecoder`] checks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValidationRule {
+    /// The message can't be decoded at all.
+    Malformed(MessageError),
+    /// The version is not 1.
+    UnknownVersion,
+    /// The code is in one of the reserved classes 1, 6 or 7.
+    ReservedCode,
+    /// An Empty message (code 0.00) has a token, options or a payload.
+    NonEmptyEmptyMessage,
+    /// The payload marker is followed by a zero-length payload.
+    EmptyPayload,
+    /// A critical option that is not repeatable occurs more than once.
+    RepeatedOption(u16),
+    /// A critical option is not recognized.
+    UnrecognizedCriticalOption(u16),
+    /// A critical option has a value that doesn't match its format or length
+    /// bounds.
+    InvalidOptionValue(u16),
+}
+
+/// How a message that broke one of the rules should be handled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reaction {
+    /// Reject the message, which for a Confirmable message means answering
+    /// with a Reset and otherwise ignoring it.
+    Reject,
+    /// Silently ignore the message.
+    Ignore,
+    /// Answer with a 4.02 (Bad Option) response.
+    BadOption,
+}
+
+/// The error that occurs when a message breaks one of the rules checked by
+/// [`crate::StrictDecoder`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    /// The rule that was broken.
+    pub rule: ValidationRule,
+    /// The offset of the offending byte in the message.
+    pub offset: usize,
+    /// How the message should be handled.
+    pub reaction: Reaction,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.rule {
+            ValidationRule::Malformed(error) => {
+                write!(f, "{} at offset {}", error, self.offset)
+            }
+            ValidationRule::UnknownVersion => {
+                write!(f, "CoAP error: unknown version")
+            }
+            ValidationRule::ReservedCode => {
+                write!(f, "CoAP error: reserved code")
+            }
+            ValidationRule::NonEmptyEmptyMessage => {
+                write!(f, "CoAP error: Empty message is not empty")
+            }
+            ValidationRule::EmptyPayload => {
+                write!(
+                    f,
+                    "CoAP error: empty payload after payload marker at \
+                     offset {}",
+                    self.offset
+                )
+            }
+            ValidationRule::RepeatedOption(number) => {
+                write!(
+                    f,
+                    "CoAP error: repeated option {} at offset {}",
+                    number, self.offset
+                )
+            }
+            ValidationRule::UnrecognizedCriticalOption(number) => {
+                write!(
+                    f,
+                    "CoAP error: unrecognized critical option {} at offset {}",
+                    number, self.offset
+                )
+            }
+            ValidationRule::InvalidOptionValue(number) => {
+                write!(
+                    f,
+                    "CoAP error: invalid value of option {} at offset {}",
+                    number, self.offset
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for ValidationError {}
+
+/// The errors that can occur when building a message with
+/// [`crate::PacketBuilder`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuildError {
+    /// The token has a length that can't be encoded.
+    InvalidTokenLength(usize),
+    /// The URI can't be expressed as options.
+    InvalidUri,
+    /// The code is neither a request method nor a response status.
+    InvalidCode,
+    /// A response was requested for a message that isn't Confirmable or
+    /// Non-confirmable.
+    InvalidRequestType,
+    /// An option that doesn't belong on this kind of message.
+    UnexpectedOption(u16),
+    /// An option has a value that doesn't match its format or length bounds.
+    InvalidOptionValue(u16),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::InvalidTokenLength(length) => {
+                write!(f, "CoAP error: invalid token length {}", length)
+            }
+            BuildError::InvalidUri => write!(f, "CoAP error: invalid URI"),
+            BuildError::InvalidCode => {
+                write!(
+                    f,
+                    "CoAP error: message is neither request nor response"
+                )
+            }
+            BuildError::InvalidRequestType => {
+                write!(f, "CoAP error: message type can't be responded to")
+            }
+            BuildError::UnexpectedOption(number) => {
+                write!(f, "CoAP error: option {} not allowed here", number)
+            }
+            BuildError::InvalidOptionValue(number) => {
+                write!(f, "CoAP error: invalid value of option {}", number)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for BuildError {}
+
 /// The error that can occur when parsing a content-format.
 #[derive(Debug, PartialEq)]
 pub struct InvalidContentFormat;
@@ -58,6 +222,19 @@ impl fmt::Display for InvalidContentFormat {
 #[cfg(feature = "std")]
 impl error::Error for InvalidContentFormat {}
 
+/// The error that can occur when parsing a CoAP URI.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InvalidUri;
+
+impl fmt::Display for InvalidUri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CoAP error: invalid URI")
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for InvalidUri {}
+
 /// The error that can occur when parsing an observe option value.
 #[derive(Debug, PartialEq)]
 pub struct InvalidObserve;
@@ -161,3 +338,124 @@ impl HandlingError {
         }
     }
 }
+
+/// The errors that can occur when sending a request with
+/// [`crate::UdpClient`].
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum ClientError {
+    /// The socket failed.
+    Io(std::io::Error),
+    /// The URI is invalid, isn't a `coap` URI or its host can't be resolved.
+    InvalidUri,
+    /// The request can't be built, e.g. because of an overly long URI.
+    Build(BuildError),
+    /// The request can't be encoded.
+    Message(MessageError),
+    /// No response arrived within the timeout.
+    TimedOut,
+    /// The server rejected the request with a Reset message.
+    Reset,
+    /// The blocks of a block-wise transfer don't fit together.
+    InvalidBlock,
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Io(error) => write!(f, "CoAP error: {}", error),
+            ClientError::InvalidUri => write!(f, "CoAP error: invalid URI"),
+            ClientError::Build(error) => error.fmt(f),
+            ClientError::Message(error) => error.fmt(f),
+            ClientError::TimedOut => {
+                write!(f, "CoAP error: request timed out")
+            }
+            ClientError::Reset => {
+                write!(f, "CoAP error: request rejected by reset")
+            }
+            ClientError::InvalidBlock => {
+                write!(f, "CoAP error: inconsistent block-wise transfer")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ClientError::Io(error) => Some(error),
+            ClientError::Build(error) => Some(error),
+            ClientError::Message(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for ClientError {
+    fn from(error: std::io::Error) -> ClientError {
+        ClientError::Io(error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<BuildError> for ClientError {
+    fn from(error: BuildError) -> ClientError {
+        ClientError::Build(error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<MessageError> for ClientError {
+    fn from(error: MessageError) -> ClientError {
+        ClientError::Message(error)
+    }
+}
+
+/// The errors that can occur when exchanging messages with
+/// [`crate::NalClient`] or [`crate::NalServer`] over the network stack of
+/// `embedded-nal` or `embedded-nal-async`.
+#[cfg(any(feature = "embedded-nal", feature = "embedded-nal-async"))]
+#[derive(Debug)]
+pub enum NalError<E> {
+    /// The network stack failed.
+    Network(E),
+    /// A message can't be encoded, e.g. because it doesn't fit into the
+    /// buffer.
+    Message(MessageError),
+    /// No response arrived within the timeout.
+    TimedOut,
+    /// The server rejected the request with a Reset message.
+    Reset,
+}
+
+#[cfg(any(feature = "embedded-nal", feature = "embedded-nal-async"))]
+impl<E: fmt::Debug> fmt::Display for NalError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NalError::Network(error) => {
+                write!(f, "CoAP error: network stack failed: {:?}", error)
+            }
+            NalError::Message(error) => error.fmt(f),
+            NalError::TimedOut => write!(f, "CoAP error: request timed out"),
+            NalError::Reset => {
+                write!(f, "CoAP error: request rejected by reset")
+            }
+        }
+    }
+}
+
+#[cfg(all(
+    feature = "std",
+    any(feature = "embedded-nal", feature = "embedded-nal-async")
+))]
+impl<E: fmt::Debug> error::Error for NalError<E> {}
+
+#[cfg(any(feature = "embedded-nal", feature = "embedded-nal-async"))]
+impl<E> From<MessageError> for NalError<E> {
+    fn from(error: MessageError) -> NalError<E> {
+        NalError::Message(error)
+    }
+}
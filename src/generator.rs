@@ -0,0 +1,263 @@
+//! Generation of message IDs and tokens (RFC 7252, Sections 4.4 and 5.3.1).
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
+use core::time::Duration;
+
+use rand_core::RngCore;
+
+/// Allocates message IDs for outgoing messages, so that no ID is reused for
+/// the same endpoint within EXCHANGE_LIFETIME.
+///
+/// IDs are handed out sequentially per endpoint, starting at a random value
+/// so they don't repeat after a restart. Time is passed in by the caller as
+/// the duration since an arbitrary but fixed point, like for
+/// [`crate::ReliabilityLayer`].
+#[derive(Debug, Clone)]
+pub struct MessageIdAllocator<Endpoint: Ord + Clone, R: RngCore> {
+    rng: R,
+    exchange_lifetime: Duration,
+    endpoints: BTreeMap<Endpoint, EndpointIds>,
+}
+
+/// The message IDs allocated for an endpoint.
+#[derive(Debug, Clone)]
+struct EndpointIds {
+    next: u16,
+    /// The IDs that are in use along with the time they expire, in the order
+    /// they were allocated.
+    in_use: VecDeque<(u16, Duration)>,
+}
+
+impl EndpointIds {
+    fn expire(&mut self, now: Duration) {
+        while self.in_use.front().is_some_and(|&(_, until)| until <= now) {
+            self.in_use.pop_front();
+        }
+    }
+}
+
+impl<Endpoint: Ord + Clone, R: RngCore> MessageIdAllocator<Endpoint, R> {
+    /// Creates a new allocator that keeps IDs in use for
+    /// `exchange_lifetime`, which should be EXCHANGE_LIFETIME (see
+    /// [`crate::TransmissionParameters::exchange_lifetime`]).
+    pub fn new(rng: R, exchange_lifetime: Duration) -> Self {
+        Self {
+            rng,
+            exchange_lifetime,
+            endpoints: BTreeMap::new(),
+        }
+    }
+
+    /// Allocates a message ID for a message to an endpoint.
+    ///
+    /// Returns `None` if all 65536 IDs are in use for the endpoint.
+    pub fn allocate(
+        &mut self,
+        endpoint: &Endpoint,
+        now: Duration,
+    ) -> Option<u16> {
+        self.expire(now);
+
+        let rng = &mut self.rng;
+        let ids =
+            self.endpoints.entry(endpoint.clone()).or_insert_with(|| {
+                EndpointIds {
+                    next: rng.next_u32() as u16,
+                    in_use: VecDeque::new(),
+                }
+            });
+        if ids.in_use.len() > usize::from(u16::MAX) {
+            return None;
+        }
+
+        let id = ids.next;
+        ids.next = id.wrapping_add(1);
+        ids.in_use
+            .push_back((id, now.saturating_add(self.exchange_lifetime)));
+        Some(id)
+    }
+
+    /// Returns whether a message ID is in use for an endpoint.
+    pub fn is_in_use(
+        &self,
+        endpoint: &Endpoint,
+        message_id: u16,
+        now: Duration,
+    ) -> bool {
+        self.endpoints.get(endpoint).is_some_and(|ids| {
+            ids.in_use
+                .iter()
+                .any(|&(id, until)| id == message_id && until > now)
+        })
+    }
+
+    /// Forgets the IDs that are no longer in use, along with endpoints that
+    /// have none in use.
+    fn expire(&mut self, now: Duration) {
+        self.endpoints.retain(|_, ids| {
+            ids.expire(now);
+            !ids.in_use.is_empty()
+        });
+    }
+}
+
+/// Length of the tokens of the requests sent by the clients of this crate.
+#[cfg(any(
+    feature = "udp-client",
+    feature = "embedded-nal",
+    feature = "embedded-nal-async"
+))]
+pub(crate) const TOKEN_LENGTH: usize = 4;
+
+/// Generates tokens for requests.
+///
+/// Random tokens are unpredictable, which protects against spoofed responses
+/// from off-path attackers as recommended by RFC 7252. Sequential tokens are
+/// additionally guaranteed not to repeat until the counter wraps around, and
+/// start at a secret random offset so they can't be guessed from the number
+/// of requests sent.
+#[derive(Debug, Clone)]
+pub struct TokenGenerator<R: RngCore> {
+    rng: R,
+    length: usize,
+    /// The counter and the secret offset added to it for sequential tokens.
+    sequence: Option<(u64, u64)>,
+}
+
+impl<R: RngCore> TokenGenerator<R> {
+    /// Creates a generator of random tokens of the given length.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length is greater than 8.
+    pub fn random(rng: R, length: usize) -> Self {
+        assert!(length <= 8, "tokens can be at most 8 bytes long");
+        Self {
+            rng,
+            length,
+            sequence: None,
+        }
+    }
+
+    /// Creates a generator of sequential tokens of the given length.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length is greater than 8.
+    pub fn sequential(mut rng: R, length: usize) -> Self {
+        assert!(length <= 8, "tokens can be at most 8 bytes long");
+        let offset = rng.next_u64();
+        Self {
+            rng,
+            length,
+            sequence: Some((0, offset)),
+        }
+    }
+
+    /// Returns the length of the generated tokens.
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    /// Returns whether the generated tokens are empty.
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Generates the next token.
+    pub fn next_token(&mut self) -> Vec<u8> {
+        let mut token = vec![0; self.length];
+        match &mut self.sequence {
+            Some((counter, offset)) => {
+                let value = counter.wrapping_add(*offset).to_be_bytes();
+                token.copy_from_slice(&value[8 - self.length..]);
+                *counter = counter.wrapping_add(1);
+            }
+            None => self.rng.fill_bytes(&mut token),
+        }
+        token
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Yields consecutive numbers starting at a seed.
+    struct StepRng(u64);
+
+    impl RngCore for StepRng {
+        fn next_u32(&mut self) -> u32 {
+            self.next_u64() as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0 = self.0.wrapping_add(1);
+            self.0
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            rand_core::impls::fill_bytes_via_next(self, dest)
+        }
+
+        fn try_fill_bytes(
+            &mut self,
+            dest: &mut [u8],
+        ) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn message_ids() {
+        let lifetime = Duration::from_secs(247);
+        let mut ids = MessageIdAllocator::new(StepRng(0xFFFD), lifetime);
+        let now = Duration::ZERO;
+
+        assert_eq!(ids.allocate(&"a", now), Some(0xFFFE));
+        assert_eq!(ids.allocate(&"a", now), Some(0xFFFF));
+        assert_eq!(ids.allocate(&"a", now), Some(0));
+        assert_eq!(ids.allocate(&"b", now), Some(0xFFFF));
+        assert!(ids.is_in_use(&"a", 0xFFFF, now));
+        assert!(!ids.is_in_use(&"a", 1, now));
+        assert!(!ids.is_in_use(&"a", 0xFFFF, lifetime));
+
+        // Endpoints without IDs in use start over at a new random value
+        assert_eq!(ids.allocate(&"a", lifetime), Some(0));
+        assert_eq!(ids.endpoints.len(), 1);
+
+        let mut ids = MessageIdAllocator::new(StepRng(0), lifetime);
+        for _ in 0..=u16::MAX {
+            assert!(ids.allocate(&"a", now).is_some());
+        }
+        assert_eq!(ids.allocate(&"a", now), None);
+        assert!(ids.allocate(&"a", Duration::from_secs(1)).is_none());
+        assert_eq!(ids.allocate(&"a", lifetime), Some(2));
+    }
+
+    #[test]
+    fn tokens() {
+        let mut random = TokenGenerator::random(StepRng(0), 4);
+        assert_eq!(random.len(), 4);
+        assert_eq!(random.next_token(), [1, 0, 0, 0]);
+        assert_eq!(random.next_token(), [2, 0, 0, 0]);
+
+        let mut sequential = TokenGenerator::sequential(StepRng(0xFE), 2);
+        assert_eq!(sequential.next_token(), [0x00, 0xFF]);
+        assert_eq!(sequential.next_token(), [0x01, 0x00]);
+
+        let mut empty = TokenGenerator::sequential(StepRng(0), 0);
+        assert!(empty.is_empty());
+        assert_eq!(empty.next_token(), []);
+    }
+
+    #[test]
+    #[should_panic]
+    fn token_length_limit() {
+        TokenGenerator::random(StepRng(0), 9);
+    }
+}
@@ -25,13 +25,16 @@ impl HeaderRaw {
             return Err(MessageError::InvalidPacketLength);
         }
 
-        buf.push(self.ver_type_tkl);
-        buf.push(self.code);
-        let id_bytes = self.message_id.to_be_bytes();
-        buf.extend(&id_bytes);
+        buf.extend(&self.to_bytes());
 
         Ok(())
     }
+
+    /// Returns the encoded header.
+    pub fn to_bytes(&self) -> [u8; 4] {
+        let id_bytes = self.message_id.to_be_bytes();
+        [self.ver_type_tkl, self.code, id_bytes[0], id_bytes[1]]
+    }
 }
 
 impl Default for HeaderRaw {
@@ -69,6 +72,7 @@ pub enum MessageClass {
     Empty,
     Request(RequestType),
     Response(ResponseType),
+    Signaling(SignalingType),
     Reserved(u8),
 }
 
@@ -121,6 +125,12 @@ impl From<u8> for MessageClass {
             0xA5 => MessageClass::Response(ResponseType::ProxyingNotSupported),
             0xA8 => MessageClass::Response(ResponseType::HopLimitReached),
 
+            0xE1 => MessageClass::Signaling(SignalingType::Csm),
+            0xE2 => MessageClass::Signaling(SignalingType::Ping),
+            0xE3 => MessageClass::Signaling(SignalingType::Pong),
+            0xE4 => MessageClass::Signaling(SignalingType::Release),
+            0xE5 => MessageClass::Signaling(SignalingType::Abort),
+
             n => MessageClass::Reserved(n),
         }
     }
@@ -177,6 +187,13 @@ impl From<MessageClass> for u8 {
             MessageClass::Response(ResponseType::HopLimitReached) => 0xA8,
             MessageClass::Response(ResponseType::UnKnown) => 0xFF,
 
+            MessageClass::Signaling(SignalingType::Csm) => 0xE1,
+            MessageClass::Signaling(SignalingType::Ping) => 0xE2,
+            MessageClass::Signaling(SignalingType::Pong) => 0xE3,
+            MessageClass::Signaling(SignalingType::Release) => 0xE4,
+            MessageClass::Signaling(SignalingType::Abort) => 0xE5,
+            MessageClass::Signaling(SignalingType::UnKnown) => 0xFF,
+
             MessageClass::Reserved(c) => c,
         }
     }
@@ -243,6 +260,17 @@ pub enum ResponseType {
     UnKnown,
 }
 
+/// The signaling codes of reliable transports (RFC 8323).
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum SignalingType {
+    Csm,
+    Ping,
+    Pong,
+    Release,
+    Abort,
+    UnKnown,
+}
+
 impl ResponseType {
     pub fn is_error(&self) -> bool {
         MessageClass::Response(*self)
@@ -346,7 +374,8 @@ impl Header {
         self.ver_type_tkl = tkl | ver_type;
     }
 
-    /// Returns the token length.
+    /// Returns the token length field, which holds the token length for
+    /// tokens of up to 12 bytes and 13 or 14 for extended tokens (RFC 8974).
     #[inline]
     pub fn get_token_length(&self) -> u8 {
         0x0F & self.ver_type_tkl
@@ -426,6 +455,17 @@ mod test {
         assert_eq!(MessageType::Reset, h.get_type());
     }
 
+    #[test]
+    fn signaling_codes() {
+        let mut header = Header::new();
+        header.set_code("7.01");
+        assert_eq!(MessageClass::Signaling(SignalingType::Csm), header.code);
+        header.set_code("7.05");
+        assert_eq!(MessageClass::Signaling(SignalingType::Abort), header.code);
+        header.set_code("7.06");
+        assert_eq!(MessageClass::Reserved(0xE6), header.code);
+    }
+
     #[test]
     fn is_error() {
         assert!(!ResponseType::Created.is_error());
@@ -1,11 +1,12 @@
-use alloc::vec::Vec;
-
 use coap_message::{
     Code, MinimalWritableMessage, MutableWritableMessage, OptionNumber,
     ReadableMessage, SeekWritableMessage, WithSortedOptions,
 };
 
-use crate::{CoapOption, MessageClass, Packet};
+use crate::{
+    option_store::OptionIter, packet_ref::OptionsRef, CoapOption,
+    MessageClass, Packet, PacketRef,
+};
 
 impl Code for MessageClass {
     // Conveniently, it already satisfies the requirements
@@ -14,14 +15,7 @@ impl Code for MessageClass {
 // pub only in name: We don't expose this whole module, so all users will know
 // is that this is a suitable iterator.
 pub struct MessageOptionAdapter<'a> {
-    head: Option<(u16, alloc::collections::linked_list::Iter<'a, Vec<u8>>)>,
-    // right from Packet::options -- fortunately that doesn't say that it
-    // returns an impl Iterator
-    raw_iter: alloc::collections::btree_map::Iter<
-        'a,
-        u16,
-        alloc::collections::linked_list::LinkedList<Vec<u8>>,
-    >,
+    raw_iter: OptionIter<'a>,
 }
 
 // pub only in name: We don't expose this whole module, so all users will know
@@ -35,18 +29,9 @@ impl<'a> Iterator for MessageOptionAdapter<'a> {
     type Item = MessageOption<'a>;
 
     fn next(&mut self) -> Option<<Self as Iterator>::Item> {
-        loop {
-            if let Some((number, values)) = self.head.as_mut() {
-                if let Some(value) = values.next() {
-                    return Some(MessageOption {
-                        number: *number,
-                        value,
-                    });
-                }
-            }
-            let (number, values) = self.raw_iter.next()?;
-            self.head = Some((*number, values.iter()));
-        }
+        self.raw_iter
+            .next()
+            .map(|(number, value)| MessageOption { number, value })
     }
 }
 
@@ -74,13 +59,57 @@ impl ReadableMessage for Packet {
     fn options(&self) -> Self::OptionsIter<'_> {
         MessageOptionAdapter {
             raw_iter: self.options.iter(),
-            head: None,
         }
     }
 }
 
 impl WithSortedOptions for Packet {}
 
+// pub only in name: We don't expose this whole module, so all users will know
+// is that this is a suitable iterator.
+pub struct MessageOptionRefAdapter<'a> {
+    raw_iter: OptionsRef<'a>,
+}
+
+impl<'a> Iterator for MessageOptionRefAdapter<'a> {
+    type Item = MessageOption<'a>;
+
+    fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+        self.raw_iter
+            .next()
+            .map(|(number, value)| MessageOption { number, value })
+    }
+}
+
+impl<'b> ReadableMessage for PacketRef<'b> {
+    type Code = MessageClass;
+
+    type MessageOption<'a>
+        = MessageOption<'a>
+    where
+        Self: 'a;
+    type OptionsIter<'a>
+        = MessageOptionRefAdapter<'a>
+    where
+        Self: 'a;
+
+    fn code(&self) -> Self::Code {
+        self.header.code
+    }
+    fn payload(&self) -> &[u8] {
+        self.payload
+    }
+    fn options(&self) -> Self::OptionsIter<'_> {
+        MessageOptionRefAdapter {
+            raw_iter: self.options(),
+        }
+    }
+}
+
+// Options are sorted by construction, as the option deltas of the encoded
+// message are never negative.
+impl WithSortedOptions for PacketRef<'_> {}
+
 impl OptionNumber for CoapOption {}
 
 impl MinimalWritableMessage for Packet {
@@ -118,11 +147,8 @@ impl MutableWritableMessage for Packet {
     where
         F: FnMut(Self::OptionNumber, &mut [u8]),
     {
-        for (&number, ref mut values) in self.options.iter_mut() {
-            for v in values.iter_mut() {
-                callback(number.into(), v);
-            }
-        }
+        self.options
+            .for_each_mut(|number, value| callback(number.into(), value));
     }
 }
 
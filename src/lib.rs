@@ -16,6 +16,10 @@
 //! - CoAP Observe Option [RFC 7641](https://tools.ietf.org/html/rfc7641)
 //! - Too Many Requests Response Code [RFC 8516](https://tools.ietf.org/html/rfc8516)
 //! - Block-Wise Transfers [RFC 7959](https://tools.ietf.org/html/rfc7959)
+//! - CoAP over TCP and TLS [RFC 8323](https://tools.ietf.org/html/rfc8323)
+//!   (message framing and signaling)
+//! - Extended Tokens [RFC 8974](https://tools.ietf.org/html/rfc8974)
+//!   (with the `extended-tokens` feature)
 //! - Constrained RESTful Environments (CoRE) Link Format
 //!   [RFC6690](https://tools.ietf.org/html/rfc6690#:~:text=well-known%2Fcore)
 //!
@@ -52,6 +56,30 @@
 //! }
 //! ```
 //!
+//! With the `udp-client` feature, [`UdpClient`] takes care of
+//! retransmissions, matching responses to requests and block-wise transfers:
+//!
+//! ```rust,no_run
+//! use coap_lite::{UdpClient, UdpClientConfig};
+//!
+//! fn main() {
+//!     let mut client =
+//!         UdpClient::bind("127.0.0.1:0", UdpClientConfig::default()).unwrap();
+//!
+//!     let response = client.get("coap://127.0.0.1:5683/test").unwrap();
+//!     println!("{}", String::from_utf8_lossy(&response.payload));
+//! }
+//! ```
+//!
+//! The `tokio` feature adds `AsyncClient`, which can also observe resources
+//! as a `Stream`, and `CoapServer`, which passes requests to an async handler
+//! and takes care of duplicates, block-wise transfers and observers.
+//!
+//! For `no_std` targets, the `embedded-nal` and `embedded-nal-async`
+//! features add `NalClient` and `NalServer`, which exchange messages over
+//! any network stack implementing the UDP traits of those crates, using
+//! fixed-size buffers.
+//!
 //! ### Server
 //!
 //! ```rust
@@ -81,6 +109,10 @@
 //! }
 //! ```
 //!
+//! Instead of matching on the path by hand, requests can be dispatched to
+//! handlers by method and path pattern, like `sensors/{id}/value`, with
+//! [`Router`].
+//!
 //! ### Low-level binary conversion
 //!
 //! ```rust
@@ -129,27 +161,76 @@ extern crate alloc;
 
 pub mod error;
 
+// Declared first so that the logging macros are available in all modules
+#[macro_use]
+mod log;
+
+#[cfg(feature = "tokio")]
+mod async_client;
+#[cfg(feature = "tokio")]
+mod async_server;
 #[cfg(feature = "std")]
 pub mod block_handler;
+mod builder;
+mod client_exchanges;
+#[cfg(feature = "std")]
+mod dedup_cache;
+mod diagnostic;
+mod generator;
 mod header;
 pub mod link_format;
-#[macro_use]
-mod log;
+#[cfg(any(feature = "embedded-nal", feature = "embedded-nal-async"))]
+mod nal;
 mod observe;
+mod option_registry;
+mod option_store;
 pub mod option_value;
 mod packet;
+mod packet_ref;
+mod reliability;
 mod request;
 mod response;
+mod router;
+mod signaling;
+mod strict;
+pub mod tcp;
+#[cfg(feature = "udp-client")]
+mod udp_client;
+mod uri;
 
 mod impl_coap_message;
 mod impl_coap_message_0_3;
 
+#[cfg(feature = "tokio")]
+pub use async_client::{AsyncClient, Observation};
+#[cfg(feature = "tokio")]
+pub use async_server::{CoapServer, ResourceNotifier};
 #[cfg(feature = "std")]
 pub use block_handler::{BlockHandler, BlockHandlerConfig};
+pub use builder::PacketBuilder;
+pub use client_exchanges::{ClientExchanges, ExchangeMatch};
+#[cfg(feature = "std")]
+pub use dedup_cache::{DedupCache, DedupCacheConfig};
+pub use generator::{MessageIdAllocator, TokenGenerator};
 pub use header::{
     Header, HeaderRaw, MessageClass, MessageType, RequestType, ResponseType,
+    SignalingType,
 };
+#[cfg(any(feature = "embedded-nal", feature = "embedded-nal-async"))]
+pub use nal::{NalClient, NalServer, DEFAULT_BUFFER_SIZE};
 pub use observe::{create_notification, Subject};
+pub use option_registry::{OptionFormat, OptionProperties, OptionRegistry};
+pub use option_store::{OptionValues, OptionValuesIter, Options};
 pub use packet::{CoapOption, ContentFormat, ObserveOption, Packet};
+pub use packet_ref::{OptionsRef, PacketRef};
+pub use reliability::{
+    ReliabilityEvent, ReliabilityLayer, TransmissionParameters,
+};
 pub use request::CoapRequest;
 pub use response::CoapResponse;
+pub use router::{PathParams, Router};
+pub use signaling::{SignalingMessage, SignalingOption};
+pub use strict::StrictDecoder;
+#[cfg(feature = "udp-client")]
+pub use udp_client::{UdpClient, UdpClientConfig};
+pub use uri::{CoapUri, UriScheme};
@@ -0,0 +1,874 @@
+//! A client and a server for CoAP over UDP on top of the network stack
+//! traits of `embedded-nal` and `embedded-nal-async`, for `no_std` targets.
+//!
+//! Neither of them reads a clock or allocates buffers for datagrams: both
+//! receive into and encode from a fixed-size buffer, and the client is told
+//! the current time like [`crate::ReliabilityLayer`], whose retransmissions
+//! it relies on along with [`crate::ClientExchanges`],
+//! [`crate::MessageIdAllocator`] and [`crate::TokenGenerator`]. The server
+//! detects duplicate requests like [`crate::DedupCache`], which needs
+//! `std`, but remembers a fixed number of exchanges instead.
+
+use alloc::{collections::VecDeque, vec::Vec};
+use core::{net::SocketAddr, time::Duration};
+
+#[cfg(feature = "embedded-nal")]
+use embedded_nal::{nb, UdpClientStack, UdpFullStack};
+#[cfg(feature = "embedded-nal-async")]
+use embedded_nal_async::{ConnectedUdp, UnconnectedUdp};
+use rand_core::{RngCore, SeedableRng};
+
+use crate::{
+    error::NalError, generator::TOKEN_LENGTH, ClientExchanges, CoapRequest,
+    CoapResponse, ExchangeMatch, MessageClass, MessageIdAllocator,
+    MessageType, Packet, ReliabilityEvent, ReliabilityLayer, ResponseType,
+    TokenGenerator, TransmissionParameters,
+};
+
+/// Default size of the buffer for datagrams, which is the upper bound for
+/// the size of messages recommended by RFC 7252, Section 4.6.
+pub const DEFAULT_BUFFER_SIZE: usize = 1152;
+
+/// A CoAP server that handles one datagram at a time.
+///
+/// Requests are passed to the handler, which fills in the response of the
+/// request (see [`CoapRequest::response`]), while pings and unexpected
+/// responses are answered with a Reset. The responses to the latest
+/// `dedup_capacity` requests are remembered and sent again for duplicates
+/// instead of calling the handler once more, while duplicates of
+/// Non-confirmable requests are silently ignored. Since message IDs are usually
+/// allocated sequentially, a request is only mistaken for a duplicate if
+/// its source sends that many messages before reusing the message ID.
+///
+/// Datagrams must fit into the buffer of `N` bytes. Responses that don't are
+/// replaced by a 5.00 (Internal Server Error) response without payload.
+pub struct NalServer<const N: usize = DEFAULT_BUFFER_SIZE> {
+    buffer: [u8; N],
+    /// The sources and message IDs of recent requests along with their
+    /// responses, which are only kept for Confirmable requests, oldest
+    /// first.
+    recent: VecDeque<(SocketAddr, u16, Option<Packet>)>,
+    dedup_capacity: usize,
+}
+
+impl<const N: usize> NalServer<N> {
+    /// Creates a server that remembers the responses to the latest
+    /// `dedup_capacity` requests.
+    pub fn new(dedup_capacity: usize) -> Self {
+        Self {
+            buffer: [0; N],
+            recent: VecDeque::with_capacity(dedup_capacity),
+            dedup_capacity,
+        }
+    }
+
+    /// Receives a datagram on a bound socket (see [`UdpFullStack::bind`])
+    /// and handles it, returning [`nb::Error::WouldBlock`] if there is none.
+    #[cfg(feature = "embedded-nal")]
+    pub fn poll<S, F>(
+        &mut self,
+        stack: &mut S,
+        socket: &mut S::UdpSocket,
+        mut handler: F,
+    ) -> nb::Result<(), NalError<S::Error>>
+    where
+        S: UdpFullStack,
+        F: FnMut(&mut CoapRequest<SocketAddr>),
+    {
+        let (size, source) = stack
+            .receive(socket, &mut self.buffer)
+            .map_err(|e| e.map(NalError::Network))?;
+        if let Some(size) = self.handle_datagram(size, source, &mut handler) {
+            nb::block!(stack.send_to(socket, source, &self.buffer[..size]))
+                .map_err(NalError::Network)?;
+        }
+        Ok(())
+    }
+
+    /// Serves requests received on a bound socket with the handler until the
+    /// socket fails.
+    #[cfg(feature = "embedded-nal-async")]
+    pub async fn serve<U, F>(
+        &mut self,
+        socket: &mut U,
+        mut handler: F,
+    ) -> Result<(), NalError<U::Error>>
+    where
+        U: UnconnectedUdp,
+        F: FnMut(&mut CoapRequest<SocketAddr>),
+    {
+        loop {
+            let (size, local, source) = socket
+                .receive_into(&mut self.buffer)
+                .await
+                .map_err(NalError::Network)?;
+            if let Some(size) =
+                self.handle_datagram(size, source, &mut handler)
+            {
+                socket
+                    .send(local, source, &self.buffer[..size])
+                    .await
+                    .map_err(NalError::Network)?;
+            }
+        }
+    }
+
+    /// Handles the datagram in the buffer, encoding the reply into the
+    /// buffer if there is one and returning its size.
+    fn handle_datagram<F>(
+        &mut self,
+        size: usize,
+        source: SocketAddr,
+        handler: &mut F,
+    ) -> Option<usize>
+    where
+        F: FnMut(&mut CoapRequest<SocketAddr>),
+    {
+        let packet = Packet::from_bytes(&self.buffer[..size]).ok()?;
+        let mut reply = self.reply(packet, source, handler)?;
+        if let Ok(size) = reply.encode_into(&mut self.buffer) {
+            return Some(size);
+        }
+
+        // Tell the client that the response is too large rather than leaving
+        // it waiting
+        reply.header.code =
+            MessageClass::Response(ResponseType::InternalServerError);
+        reply.clear_all_options();
+        reply.payload.clear();
+        reply.encode_into(&mut self.buffer).ok()
+    }
+
+    /// Returns the reply to a packet, if there is one.
+    fn reply<F>(
+        &mut self,
+        packet: Packet,
+        source: SocketAddr,
+        handler: &mut F,
+    ) -> Option<Packet>
+    where
+        F: FnMut(&mut CoapRequest<SocketAddr>),
+    {
+        if let Some(reset) = packet
+            .reset_for_ping()
+            .or_else(|| packet.reset_for_unexpected_response())
+        {
+            return Some(reset);
+        }
+        match (packet.header.get_type(), packet.header.code) {
+            (
+                MessageType::Confirmable | MessageType::NonConfirmable,
+                MessageClass::Request(_),
+            ) => {}
+            _ => return None,
+        }
+
+        let message_id = packet.header.message_id;
+        let confirmable = packet.header.get_type() == MessageType::Confirmable;
+        if let Some((_, _, response)) = self
+            .recent
+            .iter()
+            .find(|(endpoint, id, _)| *endpoint == source && *id == message_id)
+        {
+            return response.clone();
+        }
+
+        let mut request = CoapRequest::from_packet(packet, source);
+        handler(&mut request);
+        let response = request.response.map(|response| response.message);
+        if self.dedup_capacity > 0 {
+            if self.recent.len() == self.dedup_capacity {
+                self.recent.pop_front();
+            }
+            let cached = response.clone().filter(|_| confirmable);
+            self.recent.push_back((source, message_id, cached));
+        }
+        response
+    }
+}
+
+/// A CoAP client that exchanges Confirmable requests with a single server,
+/// one at a time.
+///
+/// A request is queued with [`NalClient::send`] and transmitted, along with
+/// its retransmissions, by polling the client with the current time, which
+/// eventually yields the response, whether piggybacked or separate. The
+/// time is a [`Duration`] since an arbitrary fixed point, e.g. the start of
+/// the device. Notifications of observed resources are yielded like
+/// responses until another request is sent or the observation is cancelled,
+/// after which further notifications are rejected with a Reset message.
+///
+/// Requests and datagrams must fit into the buffer of `N` bytes.
+pub struct NalClient<
+    R: RngCore + SeedableRng,
+    const N: usize = DEFAULT_BUFFER_SIZE,
+> {
+    remote: SocketAddr,
+    timeout: Duration,
+    reliability: ReliabilityLayer<SocketAddr>,
+    exchanges: ClientExchanges<SocketAddr>,
+    message_ids: MessageIdAllocator<SocketAddr, R>,
+    tokens: TokenGenerator<R>,
+    /// The request to transmit on the next poll.
+    queued: Option<Packet>,
+    /// The token of the latest request, whose response or notifications are
+    /// yielded.
+    token: Option<Vec<u8>>,
+    /// The message ID of the latest request while its response is
+    /// outstanding, along with the time by which it has to arrive.
+    outstanding: Option<(u16, Duration)>,
+    buffer: [u8; N],
+}
+
+impl<R: RngCore + SeedableRng, const N: usize> NalClient<R, N> {
+    /// Creates a client for the server at `remote`, which should be the
+    /// address the socket is connected to.
+    ///
+    /// The random number generator provides the randomized retransmission
+    /// timeouts and seeds separate generators for the initial message ID and
+    /// the tokens, so that tokens can't be predicted from message IDs.
+    /// Responses have to arrive within MAX_TRANSMIT_WAIT (see
+    /// [`TransmissionParameters::max_transmit_wait`]).
+    pub fn new(
+        remote: SocketAddr,
+        mut rng: R,
+        transmission: TransmissionParameters,
+    ) -> Self {
+        let exchange_lifetime = transmission.exchange_lifetime();
+        Self {
+            remote,
+            timeout: transmission.max_transmit_wait(),
+            reliability: ReliabilityLayer::new(transmission, rng.next_u32()),
+            exchanges: ClientExchanges::new(exchange_lifetime),
+            message_ids: MessageIdAllocator::new(
+                reseed(&mut rng),
+                exchange_lifetime,
+            ),
+            tokens: TokenGenerator::random(reseed(&mut rng), TOKEN_LENGTH),
+            queued: None,
+            token: None,
+            outstanding: None,
+            buffer: [0; N],
+        }
+    }
+
+    /// Queues a request to be sent as a Confirmable message with a fresh
+    /// message ID and token on the next poll, giving up on the outstanding
+    /// request or observation, if any.
+    pub fn send(&mut self, request: Packet) {
+        self.cancel();
+        self.queued = Some(request);
+    }
+
+    /// Gives up on the outstanding request or observation, if any.
+    pub fn cancel(&mut self) {
+        self.queued = None;
+        if let Some((message_id, _)) = self.outstanding.take() {
+            self.reliability.cancel(&self.remote, message_id);
+        }
+        if let Some(token) = self.token.take() {
+            self.exchanges.cancel(&self.remote, &token);
+        }
+    }
+
+    /// Returns the time at which the client should be polled at the latest,
+    /// or `None` if there is no request outstanding.
+    pub fn poll_timeout(&self) -> Option<Duration> {
+        if self.queued.is_some() {
+            return Some(Duration::ZERO);
+        }
+        let (_, deadline) = self.outstanding.as_ref()?;
+        Some(
+            self.reliability
+                .poll_timeout()
+                .map_or(*deadline, |timeout| timeout.min(*deadline)),
+        )
+    }
+
+    /// Transmits what is due at `now` on a socket connected to the server
+    /// (see [`UdpClientStack::connect`]) and processes the received
+    /// datagrams, returning the response once it arrives, or
+    /// [`nb::Error::WouldBlock`] until then.
+    #[cfg(feature = "embedded-nal")]
+    pub fn poll<S: UdpClientStack>(
+        &mut self,
+        stack: &mut S,
+        socket: &mut S::UdpSocket,
+        now: Duration,
+    ) -> nb::Result<Packet, NalError<S::Error>> {
+        while let Some(size) = self.next_transmission(now)? {
+            nb::block!(stack.send(socket, &self.buffer[..size]))
+                .map_err(NalError::Network)?;
+        }
+        loop {
+            let (size, source) = stack
+                .receive(socket, &mut self.buffer)
+                .map_err(|e| e.map(NalError::Network))?;
+            let (reply, response) = self.handle_datagram(size, source, now)?;
+            if let Some(size) = reply {
+                nb::block!(stack.send(socket, &self.buffer[..size]))
+                    .map_err(NalError::Network)?;
+            }
+            if let Some(response) = response {
+                return Ok(response);
+            }
+        }
+    }
+
+    /// Transmits what is due at `now` on a socket connected to the server
+    /// and processes the received datagrams until the response arrives.
+    ///
+    /// Since `embedded-nal-async` has no notion of time, retransmissions and
+    /// timeouts are only handled while polling, so the returned future
+    /// shouldn't be awaited beyond [`NalClient::poll_timeout`], e.g. by
+    /// racing it with a timer, before polling again.
+    #[cfg(feature = "embedded-nal-async")]
+    pub async fn poll_async<C: ConnectedUdp>(
+        &mut self,
+        socket: &mut C,
+        now: Duration,
+    ) -> Result<Packet, NalError<C::Error>> {
+        while let Some(size) = self.next_transmission(now)? {
+            socket
+                .send(&self.buffer[..size])
+                .await
+                .map_err(NalError::Network)?;
+        }
+        loop {
+            let size = socket
+                .receive_into(&mut self.buffer)
+                .await
+                .map_err(NalError::Network)?;
+            let (reply, response) =
+                self.handle_datagram(size, self.remote, now)?;
+            if let Some(size) = reply {
+                socket
+                    .send(&self.buffer[..size])
+                    .await
+                    .map_err(NalError::Network)?;
+            }
+            if let Some(response) = response {
+                return Ok(response);
+            }
+        }
+    }
+
+    /// Encodes the next message that is due at `now` into the buffer,
+    /// returning its size, after starting the exchange of the queued
+    /// request.
+    fn next_transmission<E>(
+        &mut self,
+        now: Duration,
+    ) -> Result<Option<usize>, NalError<E>> {
+        if let Some(mut request) = self.queued.take() {
+            request.header.set_type(MessageType::Confirmable);
+            request.header.message_id = self
+                .message_ids
+                .allocate(&self.remote, now)
+                .ok_or(NalError::TimedOut)?;
+            request.set_token(self.tokens.next_token());
+            request.encode_into(&mut self.buffer)?;
+
+            self.token = Some(request.get_token().to_vec());
+            self.outstanding =
+                Some((request.header.message_id, now + self.timeout));
+            self.exchanges.register(self.remote, request.clone());
+            self.reliability.send(self.remote, request, now);
+        }
+
+        while let Some(event) = self.reliability.poll(now) {
+            match event {
+                ReliabilityEvent::Transmit(_, packet) => {
+                    return Ok(Some(packet.encode_into(&mut self.buffer)?));
+                }
+                ReliabilityEvent::TimedOut(_, _) => {
+                    self.cancel();
+                    return Err(NalError::TimedOut);
+                }
+                _ => {}
+            }
+        }
+
+        match &self.outstanding {
+            Some((_, deadline)) if *deadline <= now => {
+                self.cancel();
+                Err(NalError::TimedOut)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Processes the datagram in the buffer, encoding the reply into the
+    /// buffer if there is one and returning its size along with the
+    /// response, if the datagram is one.
+    fn handle_datagram<E>(
+        &mut self,
+        size: usize,
+        source: SocketAddr,
+        now: Duration,
+    ) -> Result<(Option<usize>, Option<Packet>), NalError<E>> {
+        if source != self.remote {
+            return Ok((None, None));
+        }
+        let packet = match Packet::from_bytes(&self.buffer[..size]) {
+            Ok(packet) => packet,
+            Err(_) => return Ok((None, None)),
+        };
+
+        // Resets are reported below as well
+        self.reliability.handle_incoming(&source, &packet);
+        let (reply, response) =
+            match self.exchanges.handle_incoming(&source, &packet, now) {
+                ExchangeMatch::Response(request)
+                    if self.complete(request.get_token(), true) =>
+                {
+                    (CoapResponse::empty_ack(&packet), Some(packet))
+                }
+                ExchangeMatch::Notification(request)
+                    if self.complete(request.get_token(), false) =>
+                {
+                    (CoapResponse::empty_ack(&packet), Some(packet))
+                }
+                ExchangeMatch::Response(_)
+                | ExchangeMatch::Notification(_) => {
+                    (CoapResponse::empty_ack(&packet), None)
+                }
+                ExchangeMatch::Duplicate => {
+                    (CoapResponse::empty_ack(&packet), None)
+                }
+                ExchangeMatch::Unexpected(reset) => (Some(reset), None),
+                ExchangeMatch::Reset(_) => {
+                    self.cancel();
+                    return Err(NalError::Reset);
+                }
+                _ => (None, None),
+            };
+
+        let reply = match reply {
+            Some(reply) => Some(reply.encode_into(&mut self.buffer)?),
+            None => None,
+        };
+        Ok((reply, response))
+    }
+
+    /// Stops retransmitting the latest request if it has the token, since
+    /// its response arrived, and forgets about it once it is `done`.
+    ///
+    /// Returns whether the request had the token.
+    fn complete(&mut self, token: &[u8], done: bool) -> bool {
+        if self.token.as_deref() != Some(token) {
+            return false;
+        }
+        if let Some((message_id, _)) = self.outstanding.take() {
+            self.reliability.cancel(&self.remote, message_id);
+        }
+        if done {
+            self.token = None;
+        }
+        true
+    }
+}
+
+/// Returns a new random number generator seeded from another one.
+fn reseed<R: RngCore + SeedableRng>(rng: &mut R) -> R {
+    let mut seed = R::Seed::default();
+    rng.fill_bytes(seed.as_mut());
+    R::from_seed(seed)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+    use crate::{PacketBuilder, RequestType};
+    #[cfg(feature = "embedded-nal-async")]
+    use core::convert::Infallible;
+    use rand_xorshift::XorShiftRng;
+    use std::time::Instant;
+    #[cfg(feature = "embedded-nal")]
+    use {
+        crate::error::MessageError,
+        std::{io, net::UdpSocket, thread},
+    };
+
+    /// Answers with the payload of the request twice, so that the responses
+    /// to large requests don't fit into the buffer.
+    fn double(request: &mut CoapRequest<SocketAddr>) {
+        if let Some(response) = &mut request.response {
+            response.message.payload = request.message.payload.repeat(2);
+        }
+    }
+
+    fn post(payload: &[u8]) -> Packet {
+        PacketBuilder::request(RequestType::Post)
+            .payload(payload)
+            .build()
+            .unwrap()
+    }
+
+    fn client(server: SocketAddr) -> NalClient<XorShiftRng> {
+        let rng = XorShiftRng::seed_from_u64(7);
+        NalClient::new(server, rng, TransmissionParameters::default())
+    }
+
+    /// The `embedded-nal` UDP stack on top of `std`, whose sockets only
+    /// exist once they're connected or bound.
+    #[cfg(feature = "embedded-nal")]
+    struct StdStack;
+
+    #[cfg(feature = "embedded-nal")]
+    impl StdStack {
+        fn open(
+            socket: &mut Option<UdpSocket>,
+            port: u16,
+        ) -> io::Result<&UdpSocket> {
+            let opened = UdpSocket::bind(("127.0.0.1", port))?;
+            opened.set_nonblocking(true)?;
+            Ok(socket.insert(opened))
+        }
+
+        fn get(socket: &Option<UdpSocket>) -> io::Result<&UdpSocket> {
+            socket
+                .as_ref()
+                .ok_or_else(|| io::ErrorKind::NotConnected.into())
+        }
+    }
+
+    #[cfg(feature = "embedded-nal")]
+    fn would_block(error: io::Error) -> nb::Error<io::Error> {
+        match error.kind() {
+            io::ErrorKind::WouldBlock => nb::Error::WouldBlock,
+            _ => nb::Error::Other(error),
+        }
+    }
+
+    #[cfg(feature = "embedded-nal")]
+    impl UdpClientStack for StdStack {
+        type UdpSocket = Option<UdpSocket>;
+        type Error = io::Error;
+
+        fn socket(&mut self) -> io::Result<Option<UdpSocket>> {
+            Ok(None)
+        }
+
+        fn connect(
+            &mut self,
+            socket: &mut Option<UdpSocket>,
+            remote: SocketAddr,
+        ) -> io::Result<()> {
+            Self::open(socket, 0)?.connect(remote)
+        }
+
+        fn send(
+            &mut self,
+            socket: &mut Option<UdpSocket>,
+            buffer: &[u8],
+        ) -> nb::Result<(), io::Error> {
+            Self::get(socket)?.send(buffer).map_err(would_block)?;
+            Ok(())
+        }
+
+        fn receive(
+            &mut self,
+            socket: &mut Option<UdpSocket>,
+            buffer: &mut [u8],
+        ) -> nb::Result<(usize, SocketAddr), io::Error> {
+            Self::get(socket)?.recv_from(buffer).map_err(would_block)
+        }
+
+        fn close(&mut self, _socket: Option<UdpSocket>) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "embedded-nal")]
+    impl UdpFullStack for StdStack {
+        fn bind(
+            &mut self,
+            socket: &mut Option<UdpSocket>,
+            local_port: u16,
+        ) -> io::Result<()> {
+            Self::open(socket, local_port).map(drop)
+        }
+
+        fn send_to(
+            &mut self,
+            socket: &mut Option<UdpSocket>,
+            remote: SocketAddr,
+            buffer: &[u8],
+        ) -> nb::Result<(), io::Error> {
+            Self::get(socket)?
+                .send_to(buffer, remote)
+                .map_err(would_block)?;
+            Ok(())
+        }
+    }
+
+    /// Runs a server on the loopback interface for a while, returning its
+    /// address.
+    #[cfg(feature = "embedded-nal")]
+    fn serve() -> SocketAddr {
+        let mut stack = StdStack;
+        let mut socket = stack.socket().unwrap();
+        stack.bind(&mut socket, 0).unwrap();
+        let address = StdStack::get(&socket).unwrap().local_addr().unwrap();
+
+        thread::spawn(move || {
+            let mut server: NalServer = NalServer::new(4);
+            let start = Instant::now();
+            while start.elapsed() < Duration::from_secs(5) {
+                match server.poll(&mut stack, &mut socket, double) {
+                    Err(nb::Error::WouldBlock) => {
+                        thread::sleep(Duration::from_millis(1))
+                    }
+                    result => result.unwrap(),
+                }
+            }
+        });
+        address
+    }
+
+    #[cfg(feature = "embedded-nal")]
+    #[test]
+    fn blocking_exchanges() {
+        let server = serve();
+        let mut stack = StdStack;
+        let mut socket = stack.socket().unwrap();
+        stack.connect(&mut socket, server).unwrap();
+        let mut client = client(server);
+        let start = Instant::now();
+        assert_eq!(client.poll_timeout(), None);
+
+        client.send(post(b"hello"));
+        let response =
+            nb::block!(client.poll(&mut stack, &mut socket, start.elapsed()))
+                .unwrap();
+        assert_eq!(response.header.get_type(), MessageType::Acknowledgement);
+        assert_eq!(response.payload, b"hellohello");
+        assert_eq!(client.poll_timeout(), None);
+
+        client.send(post(&[0; DEFAULT_BUFFER_SIZE]));
+        assert!(matches!(
+            client.poll(&mut stack, &mut socket, start.elapsed()),
+            Err(nb::Error::Other(NalError::Message(
+                MessageError::BufferTooSmall
+            )))
+        ));
+
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let silent = silent.local_addr().unwrap();
+        let mut socket = stack.socket().unwrap();
+        stack.connect(&mut socket, silent).unwrap();
+        let transmission = TransmissionParameters {
+            ack_timeout: Duration::from_millis(50),
+            max_retransmit: 1,
+            ..Default::default()
+        };
+        let rng = XorShiftRng::seed_from_u64(7);
+        let mut client: NalClient<XorShiftRng> =
+            NalClient::new(silent, rng, transmission);
+        client.send(post(b"hello"));
+        assert!(matches!(
+            nb::block!(client.poll(&mut stack, &mut socket, start.elapsed())),
+            Err(NalError::TimedOut)
+        ));
+    }
+
+    #[cfg(feature = "embedded-nal")]
+    #[test]
+    fn duplicates_and_pings() {
+        let server = serve();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let exchange = |packet: &Packet| {
+            socket.send_to(&packet.to_bytes().unwrap(), server).unwrap();
+            let mut buf = [0; DEFAULT_BUFFER_SIZE];
+            let size = socket.recv(&mut buf).unwrap();
+            Packet::from_bytes(&buf[..size]).unwrap()
+        };
+
+        let mut echo = post(b"first");
+        echo.header.message_id = 7;
+        let response = exchange(&echo);
+        assert_eq!(response.payload, b"firstfirst");
+        echo.payload = b"second".to_vec();
+        assert_eq!(exchange(&echo), response);
+
+        echo.header.message_id = 8;
+        assert_eq!(exchange(&echo).payload, b"secondsecond");
+        assert_eq!(exchange(&Packet::ping(9)), Packet::reset(9));
+
+        // Duplicates of Non-confirmable requests aren't answered
+        echo.header.set_type(MessageType::NonConfirmable);
+        echo.header.message_id = 12;
+        assert_eq!(exchange(&echo).payload, b"secondsecond");
+        socket.send_to(&echo.to_bytes().unwrap(), server).unwrap();
+        assert_eq!(exchange(&Packet::ping(13)), Packet::reset(13));
+
+        let mut big = post(&[0; DEFAULT_BUFFER_SIZE / 2 + 1]);
+        big.header.message_id = 10;
+        let response = exchange(&big);
+        assert_eq!(
+            response.header.code,
+            MessageClass::Response(ResponseType::InternalServerError)
+        );
+        assert!(response.payload.is_empty());
+        assert_eq!(exchange(&Packet::ping(11)), Packet::reset(11));
+    }
+
+    #[test]
+    fn independent_message_ids_and_tokens() {
+        let server = "127.0.0.1:5683".parse().unwrap();
+        let mut client = client(server);
+        client.send(post(b"hello"));
+        let size = client
+            .next_transmission::<()>(Duration::ZERO)
+            .unwrap()
+            .unwrap();
+
+        let sent = Packet::from_bytes(&client.buffer[..size]).unwrap();
+        let message_id = sent.header.message_id.to_le_bytes();
+        assert_ne!(sent.get_token()[..2], message_id);
+        assert_ne!(sent.get_token()[2..], message_id);
+    }
+
+    #[test]
+    fn requests_after_observations() {
+        let server = "127.0.0.1:5683".parse().unwrap();
+        let mut client = client(server);
+        let transmit = |client: &mut NalClient<XorShiftRng>| {
+            let size = client.next_transmission::<()>(Duration::ZERO);
+            Packet::from_bytes(&client.buffer[..size.unwrap().unwrap()])
+                .unwrap()
+        };
+        let deliver = |client: &mut NalClient<XorShiftRng>, packet: Packet| {
+            let size = packet.encode_into(&mut client.buffer).unwrap();
+            let (reply, response) = client
+                .handle_datagram::<()>(size, server, Duration::ZERO)
+                .unwrap();
+            let reply =
+                reply.map(|size| Packet::from_bytes(&client.buffer[..size]));
+            (reply.map(Result::unwrap), response)
+        };
+        let notification = |request: &Packet, message_id, sequence| {
+            PacketBuilder::response(request)
+                .message_type(MessageType::Confirmable)
+                .message_id(message_id)
+                .observe(sequence)
+                .build()
+                .unwrap()
+        };
+
+        let register = PacketBuilder::request(RequestType::Get)
+            .observe(0)
+            .build()
+            .unwrap();
+        client.send(register);
+        let register = transmit(&mut client);
+        let first = notification(&register, 100, 1);
+        let (reply, response) = deliver(&mut client, first.clone());
+        assert_eq!(reply, Some(Packet::empty_ack(100)));
+        assert_eq!(response, Some(first));
+        assert_eq!(client.poll_timeout(), None);
+
+        client.send(post(b"hello"));
+        let get = transmit(&mut client);
+        let (reply, response) =
+            deliver(&mut client, notification(&register, 101, 2));
+        assert_eq!(reply, Some(Packet::reset(101)));
+        assert_eq!(response, None);
+
+        let mut ack = PacketBuilder::response(&get).build().unwrap();
+        ack.payload = b"world".to_vec();
+        let (reply, response) = deliver(&mut client, ack.clone());
+        assert_eq!(reply, None);
+        assert_eq!(response, Some(ack));
+
+        // Cancelling an observation makes the client reject notifications
+        client.send(register.clone());
+        let register = transmit(&mut client);
+        deliver(&mut client, notification(&register, 102, 3));
+        client.cancel();
+        let (reply, response) =
+            deliver(&mut client, notification(&register, 103, 4));
+        assert_eq!(reply, Some(Packet::reset(103)));
+        assert_eq!(response, None);
+    }
+
+    /// A tokio socket with the `embedded-nal-async` UDP traits, which fails
+    /// the test on errors instead of mapping them to `embedded-io` errors.
+    #[cfg(feature = "embedded-nal-async")]
+    struct TokioUdp(tokio::net::UdpSocket);
+
+    #[cfg(feature = "embedded-nal-async")]
+    impl UnconnectedUdp for TokioUdp {
+        type Error = Infallible;
+
+        async fn send(
+            &mut self,
+            _local: SocketAddr,
+            remote: SocketAddr,
+            data: &[u8],
+        ) -> Result<(), Infallible> {
+            self.0.send_to(data, remote).await.unwrap();
+            Ok(())
+        }
+
+        async fn receive_into(
+            &mut self,
+            buffer: &mut [u8],
+        ) -> Result<(usize, SocketAddr, SocketAddr), Infallible> {
+            let (size, remote) = self.0.recv_from(buffer).await.unwrap();
+            Ok((size, self.0.local_addr().unwrap(), remote))
+        }
+    }
+
+    #[cfg(feature = "embedded-nal-async")]
+    impl ConnectedUdp for TokioUdp {
+        type Error = Infallible;
+
+        async fn send(&mut self, data: &[u8]) -> Result<(), Infallible> {
+            self.0.send(data).await.unwrap();
+            Ok(())
+        }
+
+        async fn receive_into(
+            &mut self,
+            buffer: &mut [u8],
+        ) -> Result<usize, Infallible> {
+            Ok(self.0.recv(buffer).await.unwrap())
+        }
+    }
+
+    #[cfg(feature = "embedded-nal-async")]
+    #[tokio::test]
+    async fn async_exchanges() {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await;
+        let mut socket = TokioUdp(socket.unwrap());
+        let server = socket.0.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut server: NalServer = NalServer::new(4);
+            server.serve(&mut socket, double).await
+        });
+
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await;
+        let mut socket = TokioUdp(socket.unwrap());
+        socket.0.connect(server).await.unwrap();
+        let mut client = client(server);
+        let start = Instant::now();
+
+        for payload in [&b"a"[..], b"bc"] {
+            client.send(post(payload));
+            let response = loop {
+                let now = start.elapsed();
+                let wait = client.poll_timeout().unwrap().saturating_sub(now);
+                let poll = client.poll_async(&mut socket, now);
+                if let Ok(result) = tokio::time::timeout(wait, poll).await {
+                    break result.unwrap();
+                }
+            };
+            assert_eq!(response.payload, payload.repeat(2));
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use alloc::collections::BTreeMap;
+
+use crate::packet::CoapOption;
+
+/// The format of an option value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OptionFormat {
+    /// A zero-length value.
+    Empty,
+    /// An opaque sequence of bytes.
+    Opaque,
+    /// A non-negative integer in network byte order, without leading zeros.
+    UInt,
+    /// A UTF-8 string.
+    String,
+}
+
+/// The properties of an option, as defined in its specification.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptionProperties {
+    pub name: &'static str,
+    pub format: OptionFormat,
+    pub repeatable: bool,
+    /// The minimum length of the value in bytes.
+    pub min_length: usize,
+    /// The maximum length of the value in bytes.
+    pub max_length: usize,
+}
+
+impl OptionProperties {
+    /// Creates the properties of an option.
+    pub const fn new(
+        name: &'static str,
+        format: OptionFormat,
+        repeatable: bool,
+        min_length: usize,
+        max_length: usize,
+    ) -> OptionProperties {
+        OptionProperties {
+            name,
+            format,
+            repeatable,
+            min_length,
+            max_length,
+        }
+    }
+
+    /// Returns whether `value` is a well-formed value of the option.
+    pub fn is_valid_value(&self, value: &[u8]) -> bool {
+        if value.len() < self.min_length || value.len() > self.max_length {
+            return false;
+        }
+
+        match self.format {
+            OptionFormat::Empty => value.is_empty(),
+            OptionFormat::Opaque | OptionFormat::UInt => true,
+            OptionFormat::String => core::str::from_utf8(value).is_ok(),
+        }
+    }
+}
+
+/// The options defined by the supported RFCs.
+static BUILTIN_OPTIONS: [(CoapOption, OptionProperties); 21] = {
+    use OptionFormat::*;
+
+    [
+        (
+            CoapOption::IfMatch,
+            OptionProperties::new("If-Match", Opaque, true, 0, 8),
+        ),
+        (
+            CoapOption::UriHost,
+            OptionProperties::new("Uri-Host", String, false, 1, 255),
+        ),
+        (
+            CoapOption::ETag,
+            OptionProperties::new("ETag", Opaque, true, 1, 8),
+        ),
+        (
+            CoapOption::IfNoneMatch,
+            OptionProperties::new("If-None-Match", Empty, false, 0, 0),
+        ),
+        (
+            CoapOption::Observe,
+            OptionProperties::new("Observe", UInt, false, 0, 3),
+        ),
+        (
+            CoapOption::UriPort,
+            OptionProperties::new("Uri-Port", UInt, false, 0, 2),
+        ),
+        (
+            CoapOption::LocationPath,
+            OptionProperties::new("Location-Path", String, true, 0, 255),
+        ),
+        (
+            CoapOption::Oscore,
+            OptionProperties::new("OSCORE", Opaque, false, 0, 255),
+        ),
+        (
+            CoapOption::UriPath,
+            OptionProperties::new("Uri-Path", String, true, 0, 255),
+        ),
+        (
+            CoapOption::ContentFormat,
+            OptionProperties::new("Content-Format", UInt, false, 0, 2),
+        ),
+        (
+            CoapOption::MaxAge,
+            OptionProperties::new("Max-Age", UInt, false, 0, 4),
+        ),
+        (
+            CoapOption::UriQuery,
+            OptionProperties::new("Uri-Query", String, true, 0, 255),
+        ),
+        (
+            CoapOption::Accept,
+            OptionProperties::new("Accept", UInt, false, 0, 2),
+        ),
+        (
+            CoapOption::LocationQuery,
+            OptionProperties::new("Location-Query", String, true, 0, 255),
+        ),
+        (
+            CoapOption::Block2,
+            OptionProperties::new("Block2", UInt, false, 0, 3),
+        ),
+        (
+            CoapOption::Block1,
+            OptionProperties::new("Block1", UInt, false, 0, 3),
+        ),
+        (
+            CoapOption::Size2,
+            OptionProperties::new("Size2", UInt, false, 0, 4),
+        ),
+        (
+            CoapOption::ProxyUri,
+            OptionProperties::new("Proxy-Uri", String, false, 1, 1034),
+        ),
+        (
+            CoapOption::ProxyScheme,
+            OptionProperties::new("Proxy-Scheme", String, false, 1, 255),
+        ),
+        (
+            CoapOption::Size1,
+            OptionProperties::new("Size1", UInt, false, 0, 4),
+        ),
+        (
+            CoapOption::NoResponse,
+            OptionProperties::new("No-Response", UInt, false, 0, 1),
+        ),
+    ]
+};
+
+/// Returns the properties of an option defined by the supported RFCs.
+pub(crate) fn builtin_properties(
+    option: CoapOption,
+) -> Option<&'static OptionProperties> {
+    BUILTIN_OPTIONS
+        .iter()
+        .find(|(candidate, _)| *candidate == option)
+        .map(|(_, properties)| properties)
+}
+
+/// The options known to an application and their properties.
+///
+/// The registry starts out with the options defined by the supported RFCs,
+/// and applications can register their own options on top of those. Whether
+/// an option is critical, unsafe or part of the cache key is encoded in its
+/// number, so the corresponding methods work for unregistered options too.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptionRegistry {
+    options: BTreeMap<u16, OptionProperties>,
+}
+
+impl Default for OptionRegistry {
+    fn default() -> Self {
+        Self {
+            options: BUILTIN_OPTIONS
+                .iter()
+                .map(|(option, properties)| {
+                    (u16::from(*option), properties.clone())
+                })
+                .collect(),
+        }
+    }
+}
+
+impl OptionRegistry {
+    /// Creates a new registry holding the built-in options.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Creates a new registry without any options.
+    pub fn empty() -> Self {
+        Self {
+            options: BTreeMap::new(),
+        }
+    }
+
+    /// Registers an option, replacing the properties it had before.
+    pub fn register(
+        &mut self,
+        option: CoapOption,
+        properties: OptionProperties,
+    ) {
+        self.options.insert(option.into(), properties);
+    }
+
+    /// Returns the properties of an option, if it is registered.
+    pub fn get(&self, option: CoapOption) -> Option<&OptionProperties> {
+        self.options.get(&option.into())
+    }
+
+    /// Returns whether an option is registered.
+    pub fn is_recognized(&self, option: CoapOption) -> bool {
+        self.options.contains_key(&option.into())
+    }
+
+    /// Returns whether an option may occur more than once in a message.
+    ///
+    /// Unregistered options are treated as non-repeatable.
+    pub fn is_repeatable(&self, option: CoapOption) -> bool {
+        self.get(option)
+            .is_some_and(|properties| properties.repeatable)
+    }
+
+    /// Returns whether an option is critical.
+    pub fn is_critical(&self, option: CoapOption) -> bool {
+        option.is_critical()
+    }
+
+    /// Returns whether an option is unsafe to forward.
+    pub fn is_unsafe(&self, option: CoapOption) -> bool {
+        option.is_unsafe()
+    }
+
+    /// Returns whether an option is not part of the cache key.
+    pub fn is_no_cache_key(&self, option: CoapOption) -> bool {
+        option.is_no_cache_key()
+    }
+
+    /// Returns an iterator over the registered options and their properties,
+    /// ordered by option number.
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = (CoapOption, &OptionProperties)> + '_ {
+        self.options.iter().map(|(&number, properties)| {
+            (CoapOption::from(number), properties)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn builtin_properties() {
+        let registry = OptionRegistry::new();
+
+        assert!(registry.is_critical(CoapOption::UriHost));
+        assert!(registry.is_unsafe(CoapOption::UriHost));
+        assert!(!registry.is_no_cache_key(CoapOption::UriHost));
+        assert!(!registry.is_repeatable(CoapOption::UriHost));
+
+        assert!(!registry.is_critical(CoapOption::Size1));
+        assert!(!registry.is_unsafe(CoapOption::Size1));
+        assert!(registry.is_no_cache_key(CoapOption::Size1));
+
+        assert!(!registry.is_critical(CoapOption::ETag));
+        assert!(registry.is_repeatable(CoapOption::ETag));
+        let etag = registry.get(CoapOption::ETag).unwrap();
+        assert_eq!(etag.name, "ETag");
+        assert_eq!(etag.format, OptionFormat::Opaque);
+        assert!(!etag.is_valid_value(b""));
+        assert!(etag.is_valid_value(b"12345678"));
+        assert!(!etag.is_valid_value(b"123456789"));
+
+        let if_none_match = registry.get(CoapOption::IfNoneMatch).unwrap();
+        assert!(if_none_match.is_valid_value(b""));
+        assert!(!if_none_match.is_valid_value(b"x"));
+        let uri_path = registry.get(CoapOption::UriPath).unwrap();
+        assert!(!uri_path.is_valid_value(&[0xFF]));
+
+        assert_eq!(registry.iter().count(), BUILTIN_OPTIONS.len());
+        assert!(registry
+            .iter()
+            .all(|(option, _)| !matches!(option, CoapOption::Unknown(_))));
+    }
+
+    #[test]
+    fn custom_options() {
+        let mut registry = OptionRegistry::new();
+        let option = CoapOption::Unknown(65001);
+        assert!(!registry.is_recognized(option));
+        assert!(registry.is_critical(option));
+        assert!(!registry.is_repeatable(option));
+
+        registry.register(
+            option,
+            OptionProperties::new("Custom", OptionFormat::Opaque, true, 0, 16),
+        );
+        assert!(registry.is_recognized(option));
+        assert!(registry.is_repeatable(option));
+        assert_eq!(registry.get(option).unwrap().name, "Custom");
+
+        assert!(!OptionRegistry::empty().is_recognized(CoapOption::UriPath));
+    }
+}
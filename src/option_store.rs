@@ -0,0 +1,447 @@
+use alloc::vec::Vec;
+use core::{fmt, ops::Range, slice};
+
+/// The location of an option value in the buffer of an [`OptionStore`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Entry {
+    number: u16,
+    start: usize,
+    len: usize,
+}
+
+/// The options of a packet, stored as a single buffer holding all values back
+/// to back and an index into it that is sorted by option number.
+///
+/// This keeps a decoded packet down to two allocations for its options, no
+/// matter how many there are. Values of the same option keep the order they
+/// were added in.
+#[derive(Clone, Default)]
+pub(crate) struct OptionStore {
+    data: Vec<u8>,
+    entries: Vec<Entry>,
+    /// The numbers of options that were cleared and are kept without values,
+    /// sorted.
+    empty: Vec<u16>,
+}
+
+impl OptionStore {
+    /// Creates a new store with room for `count` values totalling `length`
+    /// bytes.
+    pub(crate) fn with_capacity(count: usize, length: usize) -> OptionStore {
+        OptionStore {
+            data: Vec::with_capacity(length),
+            entries: Vec::with_capacity(count),
+            empty: Vec::new(),
+        }
+    }
+
+    /// Returns the range of entries holding the values of an option.
+    fn range(&self, number: u16) -> Range<usize> {
+        let start =
+            self.entries.partition_point(|entry| entry.number < number);
+        let len = self.entries[start..]
+            .partition_point(|entry| entry.number == number);
+        start..start + len
+    }
+
+    /// Returns the values of an option, or `None` if the option is absent.
+    ///
+    /// Options that were cleared are present without values.
+    pub(crate) fn get(&self, number: u16) -> Option<OptionValues<'_>> {
+        let range = self.range(number);
+        if range.is_empty() && self.empty.binary_search(&number).is_err() {
+            return None;
+        }
+
+        Some(OptionValues {
+            data: &self.data,
+            entries: &self.entries[range],
+        })
+    }
+
+    /// Appends a value to an option.
+    pub(crate) fn push(&mut self, number: u16, value: &[u8]) {
+        // Options are usually added in order, in which case the value simply
+        // goes at the end.
+        let idx = match self.entries.last() {
+            Some(last) if last.number > number => {
+                self.entries.partition_point(|entry| entry.number <= number)
+            }
+            _ => self.entries.len(),
+        };
+        self.entries.insert(
+            idx,
+            Entry {
+                number,
+                start: self.data.len(),
+                len: value.len(),
+            },
+        );
+        self.data.extend_from_slice(value);
+        if let Ok(idx) = self.empty.binary_search(&number) {
+            self.empty.remove(idx);
+        }
+    }
+
+    /// Adds an option without values, unless it already has some.
+    pub(crate) fn push_empty(&mut self, number: u16) {
+        if !self.range(number).is_empty() {
+            return;
+        }
+        if let Err(idx) = self.empty.binary_search(&number) {
+            self.empty.insert(idx, number);
+        }
+    }
+
+    /// Removes all values of an option, keeping the option itself without
+    /// values if it is present.
+    pub(crate) fn clear_values(&mut self, number: u16) {
+        if self.get(number).is_some() {
+            self.remove(number);
+            self.push_empty(number);
+        }
+    }
+
+    /// Removes an option along with its values.
+    pub(crate) fn remove(&mut self, number: u16) {
+        if let Ok(idx) = self.empty.binary_search(&number) {
+            self.empty.remove(idx);
+        }
+        let range = self.range(number);
+        if range.is_empty() {
+            return;
+        }
+
+        // Drop the bytes of the removed values from the buffer in place and
+        // move the values behind them forward.
+        let removed = &self.entries[range.clone()];
+        let mut offset = 0;
+        self.data.retain(|_| {
+            let keep = !removed.iter().any(|entry| {
+                entry.start <= offset && offset < entry.start + entry.len
+            });
+            offset += 1;
+            keep
+        });
+        let (before, rest) = self.entries.split_at_mut(range.start);
+        let (removed, after) = rest.split_at_mut(range.len());
+        for entry in before.iter_mut().chain(after.iter_mut()) {
+            entry.start -= removed
+                .iter()
+                .filter(|removed| removed.start < entry.start)
+                .map(|removed| removed.len)
+                .sum::<usize>();
+        }
+        self.entries.drain(range);
+    }
+
+    /// Returns whether there are no option values.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes all options.
+    pub(crate) fn clear(&mut self) {
+        self.data.clear();
+        self.entries.clear();
+        self.empty.clear();
+    }
+
+    /// Returns an iterator over all option values along with their option
+    /// number, ordered by option number.
+    pub(crate) fn iter(&self) -> OptionIter<'_> {
+        OptionIter {
+            data: &self.data,
+            entries: self.entries.iter(),
+        }
+    }
+
+    /// Returns an iterator over the options, grouping the values of each.
+    pub(crate) fn groups(&self) -> Options<'_> {
+        Options {
+            data: &self.data,
+            entries: &self.entries,
+            empty: &self.empty,
+        }
+    }
+
+    /// Calls `callback` with every option value, allowing it to be modified
+    /// in place.
+    pub(crate) fn for_each_mut<F>(&mut self, mut callback: F)
+    where
+        F: FnMut(u16, &mut [u8]),
+    {
+        for entry in self.entries.iter() {
+            callback(
+                entry.number,
+                &mut self.data[entry.start..entry.start + entry.len],
+            );
+        }
+    }
+}
+
+impl PartialEq for OptionStore {
+    fn eq(&self, other: &Self) -> bool {
+        self.empty == other.empty && self.iter().eq(other.iter())
+    }
+}
+
+impl fmt::Debug for OptionStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.groups()).finish()
+    }
+}
+
+/// An iterator over all option values of a packet along with their option
+/// number.
+#[derive(Debug, Clone)]
+pub(crate) struct OptionIter<'a> {
+    data: &'a [u8],
+    entries: slice::Iter<'a, Entry>,
+}
+
+impl<'a> Iterator for OptionIter<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next().map(|entry| {
+            (
+                entry.number,
+                &self.data[entry.start..entry.start + entry.len],
+            )
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+/// An iterator over the options of a packet, yielding each option number
+/// along with its values.
+#[derive(Debug, Clone)]
+pub struct Options<'a> {
+    data: &'a [u8],
+    entries: &'a [Entry],
+    empty: &'a [u16],
+}
+
+impl<'a> Iterator for Options<'a> {
+    type Item = (&'a u16, OptionValues<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let number = match (self.entries.first(), self.empty.split_first()) {
+            (Some(entry), Some((empty, rest))) if *empty < entry.number => {
+                self.empty = rest;
+                return Some((empty, self.empty_values()));
+            }
+            (None, Some((empty, rest))) => {
+                self.empty = rest;
+                return Some((empty, self.empty_values()));
+            }
+            (Some(entry), _) => &entry.number,
+            (None, None) => return None,
+        };
+        let len = self
+            .entries
+            .iter()
+            .take_while(|entry| entry.number == *number)
+            .count();
+        let (entries, rest) = self.entries.split_at(len);
+        self.entries = rest;
+
+        Some((
+            number,
+            OptionValues {
+                data: self.data,
+                entries,
+            },
+        ))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self
+            .entries
+            .windows(2)
+            .filter(|pair| pair[0].number != pair[1].number)
+            .count()
+            + usize::from(!self.entries.is_empty())
+            + self.empty.len();
+        (len, Some(len))
+    }
+}
+
+impl<'a> Options<'a> {
+    fn empty_values(&self) -> OptionValues<'a> {
+        OptionValues {
+            data: self.data,
+            entries: &[],
+        }
+    }
+}
+
+impl ExactSizeIterator for Options<'_> {}
+
+/// The values of an option, in the order they were added.
+#[derive(Clone, Copy)]
+pub struct OptionValues<'a> {
+    data: &'a [u8],
+    entries: &'a [Entry],
+}
+
+impl<'a> OptionValues<'a> {
+    /// Returns the number of values.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether there are no values.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the first value.
+    pub fn front(&self) -> Option<&'a [u8]> {
+        self.iter().next()
+    }
+
+    /// Returns the last value.
+    pub fn back(&self) -> Option<&'a [u8]> {
+        self.iter().next_back()
+    }
+
+    /// Returns an iterator over the values.
+    pub fn iter(&self) -> OptionValuesIter<'a> {
+        OptionValuesIter {
+            data: self.data,
+            entries: self.entries.iter(),
+        }
+    }
+}
+
+impl<'a> IntoIterator for OptionValues<'a> {
+    type Item = &'a [u8];
+    type IntoIter = OptionValuesIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl PartialEq for OptionValues<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl fmt::Debug for OptionValues<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// An iterator over the values of an option.
+#[derive(Debug, Clone)]
+pub struct OptionValuesIter<'a> {
+    data: &'a [u8],
+    entries: slice::Iter<'a, Entry>,
+}
+
+impl<'a> Iterator for OptionValuesIter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries
+            .next()
+            .map(|entry| &self.data[entry.start..entry.start + entry.len])
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for OptionValuesIter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.entries
+            .next_back()
+            .map(|entry| &self.data[entry.start..entry.start + entry.len])
+    }
+}
+
+impl ExactSizeIterator for OptionValuesIter<'_> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn push_keeps_options_sorted() {
+        let mut store = OptionStore::default();
+        store.push(11, b"a");
+        store.push(3, b"host");
+        store.push(11, b"b");
+        store.push(60, b"");
+        store.push(3, b"other");
+
+        let options: Vec<_> = store.iter().collect();
+        assert_eq!(
+            options,
+            [
+                (3, &b"host"[..]),
+                (3, b"other"),
+                (11, b"a"),
+                (11, b"b"),
+                (60, b"")
+            ]
+        );
+
+        let uri_path = store.get(11).unwrap();
+        assert_eq!(uri_path.len(), 2);
+        assert_eq!(uri_path.front(), Some(&b"a"[..]));
+        assert_eq!(uri_path.back(), Some(&b"b"[..]));
+        assert!(store.get(12).is_none());
+
+        let groups: Vec<_> = store
+            .groups()
+            .map(|(&number, values)| (number, values.len()))
+            .collect();
+        assert_eq!(groups, [(3, 2), (11, 2), (60, 1)]);
+    }
+
+    #[test]
+    fn remove_compacts_buffer() {
+        let mut store = OptionStore::default();
+        store.push(11, b"path");
+        store.push(3, b"host");
+        store.push(15, b"query");
+        store.push(3, b"other");
+        store.push(20, b"tag");
+        store.clear_values(3);
+        store.clear_values(4);
+        store.push_empty(4);
+        store.remove(20);
+
+        assert_eq!(store.data, b"pathquery");
+        let options: Vec<_> = store.iter().collect();
+        assert_eq!(options, [(11, &b"path"[..]), (15, b"query")]);
+        let groups: Vec<_> = store
+            .groups()
+            .map(|(&number, values)| (number, values.len()))
+            .collect();
+        assert_eq!(groups, [(3, 0), (4, 0), (11, 1), (15, 1)]);
+        assert_eq!(store.groups().len(), 4);
+        assert!(store.get(4).unwrap().is_empty());
+
+        store.push(4, b"x");
+        assert_eq!(store.get(4).unwrap().front(), Some(&b"x"[..]));
+        assert_eq!(store.groups().len(), 4);
+
+        store.for_each_mut(|_, value| value.make_ascii_uppercase());
+        assert_eq!(store.get(15).unwrap().front(), Some(&b"QUERY"[..]));
+
+        store.clear();
+        assert_eq!(store.iter().count(), 0);
+        assert_eq!(store, OptionStore::default());
+    }
+}
@@ -1,7 +1,4 @@
-use alloc::{
-    collections::{BTreeMap, LinkedList},
-    vec::Vec,
-};
+use alloc::{collections::LinkedList, string::String, vec::Vec};
 use core::convert::TryFrom;
 
 use crate::{
@@ -9,18 +6,15 @@ use crate::{
         IncompatibleOptionValueFormat, InvalidContentFormat, InvalidObserve,
         MessageError,
     },
-    header::{Header, HeaderRaw, MessageClass},
-    option_value::{OptionValueType, OptionValueU16, OptionValueU32},
+    header::{Header, MessageClass, MessageType},
+    option_registry::builtin_properties,
+    option_store::{OptionStore, OptionValues, Options},
+    option_value::{
+        OptionValueString, OptionValueType, OptionValueU16, OptionValueU32,
+    },
+    packet_ref::PacketRef,
 };
 
-macro_rules! u8_to_unsigned_be {
-    ($src:ident, $start:expr, $end:expr, $t:ty) => ({
-        (0..=$end - $start).rev().fold(
-            0, |acc, i| acc | $src[$start+i] as $t << i * 8
-        )
-    })
-}
-
 /// The CoAP options.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CoapOption {
@@ -106,6 +100,25 @@ impl From<CoapOption> for u16 {
     }
 }
 
+impl CoapOption {
+    /// Returns whether the option is critical, meaning a recipient that
+    /// doesn't recognize it must not process the message.
+    pub fn is_critical(&self) -> bool {
+        u16::from(*self) & 0x01 != 0
+    }
+
+    /// Returns whether the option is unsafe to forward for a proxy that
+    /// doesn't recognize it.
+    pub fn is_unsafe(&self) -> bool {
+        u16::from(*self) & 0x02 != 0
+    }
+
+    /// Returns whether the option is not part of the cache key.
+    pub fn is_no_cache_key(&self) -> bool {
+        u16::from(*self) & 0x1E == 0x1C
+    }
+}
+
 /// The content formats.
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[non_exhaustive]
@@ -319,6 +332,124 @@ impl From<ContentFormat> for usize {
     }
 }
 
+impl ContentFormat {
+    /// Returns the media type and content coding of the content format.
+    pub fn media_type(&self) -> &'static str {
+        match self {
+            ContentFormat::TextPlain => "text/plain; charset=utf-8",
+            ContentFormat::ApplicationCoseEncrypt0 => {
+                "application/cose; cose-type=\"cose-encrypt0\""
+            }
+            ContentFormat::ApplicationCoseMac0 => {
+                "application/cose; cose-type=\"cose-mac0\""
+            }
+            ContentFormat::ApplicationCoseSign1 => {
+                "application/cose; cose-type=\"cose-sign1\""
+            }
+            ContentFormat::ApplicationAceCbor => "application/ace+cbor",
+            ContentFormat::ImageGif => "image/gif",
+            ContentFormat::ImageJpeg => "image/jpeg",
+            ContentFormat::ImagePng => "image/png",
+            ContentFormat::ApplicationLinkFormat => "application/link-format",
+            ContentFormat::ApplicationXML => "application/xml",
+            ContentFormat::ApplicationOctetStream => {
+                "application/octet-stream"
+            }
+            ContentFormat::ApplicationEXI => "application/exi",
+            ContentFormat::ApplicationJSON => "application/json",
+            ContentFormat::ApplicationJsonPatchJson => {
+                "application/json-patch+json"
+            }
+            ContentFormat::ApplicationMergePatchJson => {
+                "application/merge-patch+json"
+            }
+            ContentFormat::ApplicationCBOR => "application/cbor",
+            ContentFormat::ApplicationCWt => "application/cwt",
+            ContentFormat::ApplicationMultipartCore => {
+                "application/multipart-core"
+            }
+            ContentFormat::ApplicationCborSeq => "application/cbor-seq",
+            ContentFormat::ApplicationCoseEncrypt => {
+                "application/cose; cose-type=\"cose-encrypt\""
+            }
+            ContentFormat::ApplicationCoseMac => {
+                "application/cose; cose-type=\"cose-mac\""
+            }
+            ContentFormat::ApplicationCoseSign => {
+                "application/cose; cose-type=\"cose-sign\""
+            }
+            ContentFormat::ApplicationCoseKey => "application/cose-key",
+            ContentFormat::ApplicationCoseKeySet => "application/cose-key-set",
+            ContentFormat::ApplicationSenmlJSON => "application/senml+json",
+            ContentFormat::ApplicationSensmlJSON => "application/sensml+json",
+            ContentFormat::ApplicationSenmlCBOR => "application/senml+cbor",
+            ContentFormat::ApplicationSensmlCBOR => "application/sensml+cbor",
+            ContentFormat::ApplicationSenmlExi => "application/senml-exi",
+            ContentFormat::ApplicationSensmlExi => "application/sensml-exi",
+            ContentFormat::ApplicationYangDataCborSid => {
+                "application/yang-data+cbor; id=sid"
+            }
+            ContentFormat::ApplicationCoapGroupJson => {
+                "application/coap-group+json"
+            }
+            ContentFormat::ApplicationDotsCbor => "application/dots+cbor",
+            ContentFormat::ApplicationMissingBlocksCborSeq => {
+                "application/missing-blocks+cbor-seq"
+            }
+            ContentFormat::ApplicationPkcs7MimeServerGeneratedKey => {
+                "application/pkcs7-mime; smime-type=server-generated-key"
+            }
+            ContentFormat::ApplicationPkcs7MimeCertsOnly => {
+                "application/pkcs7-mime; smime-type=certs-only"
+            }
+            ContentFormat::ApplicationPkcs8 => "application/pkcs8",
+            ContentFormat::ApplicationCsrattrs => "application/csrattrs",
+            ContentFormat::ApplicationPkcs10 => "application/pkcs10",
+            ContentFormat::ApplicationPkixCert => "application/pkix-cert",
+            ContentFormat::ApplicationAifCbor => "application/aif+cbor",
+            ContentFormat::ApplicationAifJson => "application/aif+json",
+            ContentFormat::ApplicationSenmlXML => "application/senml+xml",
+            ContentFormat::ApplicationSensmlXML => "application/sensml+xml",
+            ContentFormat::ApplicationSenmlEtchJson => {
+                "application/senml-etch+json"
+            }
+            ContentFormat::ApplicationSenmlEtchCbor => {
+                "application/senml-etch+cbor"
+            }
+            ContentFormat::ApplicationYangDataCbor => {
+                "application/yang-data+cbor"
+            }
+            ContentFormat::ApplicationYangDataCborName => {
+                "application/yang-data+cbor; id=name"
+            }
+            ContentFormat::ApplicationTdJson => "application/td+json",
+            ContentFormat::ApplicationVoucherCoseCbor => {
+                "application/voucher+cose"
+            }
+            ContentFormat::ApplicationVndOcfCbor => "application/vnd.ocf+cbor",
+            ContentFormat::ApplicationOscore => "application/oscore",
+            ContentFormat::ApplicationJavascript => "application/javascript",
+            ContentFormat::ApplicationJsonDeflate => {
+                "application/json; deflate"
+            }
+            ContentFormat::ApplicationCborDeflate => {
+                "application/cbor; deflate"
+            }
+            ContentFormat::ApplicationVndOmaLwm2mTlv => {
+                "application/vnd.oma.lwm2m+tlv"
+            }
+            ContentFormat::ApplicationVndOmaLwm2mJson => {
+                "application/vnd.oma.lwm2m+json"
+            }
+            ContentFormat::ApplicationVndOmaLwm2mCbor => {
+                "application/vnd.oma.lwm2m+cbor"
+            }
+            ContentFormat::TextCss => "text/css",
+            ContentFormat::ImageSvgXml => "image/svg+xml",
+        }
+    }
+}
+
 /// The values of the observe option.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ObserveOption {
@@ -352,14 +483,10 @@ impl From<ObserveOption> for usize {
 pub struct Packet {
     pub header: Header,
     token: Vec<u8>,
-    pub(crate) options: BTreeMap<u16, LinkedList<Vec<u8>>>,
+    pub(crate) options: OptionStore,
     pub payload: Vec<u8>,
 }
 
-/// An iterator over the options of a packet.
-pub type Options<'a> =
-    alloc::collections::btree_map::Iter<'a, u16, LinkedList<Vec<u8>>>;
-
 impl Packet {
     /// Maximum allowed packet size. By default limited to 1280 so that CoAP
     /// packets can be sent over TCP or UDP.
@@ -370,19 +497,74 @@ impl Packet {
     #[cfg(feature = "udp")]
     pub const MAX_SIZE: usize = 64_000;
 
+    /// The value of the Max-Age option when it is absent, in seconds.
+    pub const DEFAULT_MAX_AGE: u32 = 60;
+
     /// Creates a new packet.
     pub fn new() -> Packet {
         Default::default()
     }
 
+    /// Creates an Empty ACK, which acknowledges the Confirmable message with
+    /// the given ID without carrying a response.
+    pub fn empty_ack(message_id: u16) -> Packet {
+        Self::empty(MessageType::Acknowledgement, message_id)
+    }
+
+    /// Creates a Reset, which rejects the message with the given ID.
+    pub fn reset(message_id: u16) -> Packet {
+        Self::empty(MessageType::Reset, message_id)
+    }
+
+    /// Creates a CoAP ping, an Empty Confirmable message that the peer
+    /// answers with a Reset to show it is alive.
+    pub fn ping(message_id: u16) -> Packet {
+        Self::empty(MessageType::Confirmable, message_id)
+    }
+
+    fn empty(message_type: MessageType, message_id: u16) -> Packet {
+        let mut packet = Packet::new();
+        packet.header.set_type(message_type);
+        packet.header.code = MessageClass::Empty;
+        packet.header.message_id = message_id;
+        packet
+    }
+
+    /// Returns the Reset that answers this packet if it is a CoAP ping.
+    pub fn reset_for_ping(&self) -> Option<Packet> {
+        match (self.header.get_type(), self.header.code) {
+            (MessageType::Confirmable, MessageClass::Empty) => {
+                Some(Packet::reset(self.header.message_id))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the Reset that rejects this packet if it is a Confirmable or
+    /// Non-confirmable response, for servers which never expect any.
+    pub fn reset_for_unexpected_response(&self) -> Option<Packet> {
+        match (self.header.get_type(), self.header.code) {
+            (
+                MessageType::Confirmable | MessageType::NonConfirmable,
+                MessageClass::Response(_),
+            ) => Some(Packet::reset(self.header.message_id)),
+            _ => None,
+        }
+    }
+
     /// Returns an iterator over the options of the packet.
     pub fn options(&self) -> Options {
-        self.options.iter()
+        self.options.groups()
     }
 
     /// Sets the token.
+    ///
+    /// Tokens longer than 8 bytes can only be encoded with the
+    /// `extended-tokens` feature (RFC 8974), and tokens of 9 to 12 bytes
+    /// can't be encoded at all.
     pub fn set_token(&mut self, token: Vec<u8>) {
-        self.header.set_token_length(token.len() as u8);
+        self.header
+            .set_token_length(token_length_field(token.len()));
         self.token = token;
     }
 
@@ -392,23 +574,31 @@ impl Packet {
     }
 
     /// Sets an option's values.
-    pub fn set_option(&mut self, tp: CoapOption, value: LinkedList<Vec<u8>>) {
-        self.options.insert(tp.into(), value);
+    pub fn set_option<I>(&mut self, tp: CoapOption, value: I)
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let number = tp.into();
+        self.options.remove(number);
+        self.options.push_empty(number);
+        for value in value {
+            self.options.push(number, value.as_ref());
+        }
     }
 
     /// Sets an option's values using a structured option value format.
     pub fn set_options_as<T: OptionValueType>(
         &mut self,
         tp: CoapOption,
-        value: LinkedList<T>,
+        value: impl IntoIterator<Item = T>,
     ) {
-        let raw_value = value.into_iter().map(|x| x.into()).collect();
-        self.set_option(tp, raw_value);
+        self.set_option(tp, value.into_iter().map(Into::<Vec<u8>>::into));
     }
 
-    /// Returns an option's values.
-    pub fn get_option(&self, tp: CoapOption) -> Option<&LinkedList<Vec<u8>>> {
-        self.options.get(&tp.into())
+    /// Returns an option's values, or `None` if the option is absent.
+    pub fn get_option(&self, tp: CoapOption) -> Option<OptionValues<'_>> {
+        self.options.get(tp.into())
     }
 
     /// Returns an option's values all decoded using the specified structured
@@ -420,17 +610,15 @@ impl Packet {
         self.get_option(tp).map(|options| {
             options
                 .iter()
-                .map(|raw_value| T::try_from(raw_value.clone()))
+                .map(|raw_value| T::try_from(raw_value.to_vec()))
                 .collect()
         })
     }
 
     /// Returns an option's first value as a convenience when only one is
     /// expected.
-    pub fn get_first_option(&self, tp: CoapOption) -> Option<&Vec<u8>> {
-        self.options
-            .get(&tp.into())
-            .and_then(|options| options.front())
+    pub fn get_first_option(&self, tp: CoapOption) -> Option<&[u8]> {
+        self.get_option(tp).and_then(|options| options.front())
     }
 
     /// Returns an option's first value as a convenience when only one is
@@ -440,20 +628,12 @@ impl Packet {
         tp: CoapOption,
     ) -> Option<Result<T, IncompatibleOptionValueFormat>> {
         self.get_first_option(tp)
-            .map(|value| T::try_from(value.clone()))
+            .map(|value| T::try_from(value.to_vec()))
     }
 
     /// Adds an option value.
     pub fn add_option(&mut self, tp: CoapOption, value: Vec<u8>) {
-        let num = tp.into();
-        if let Some(list) = self.options.get_mut(&num) {
-            list.push_back(value);
-            return;
-        }
-
-        let mut list = LinkedList::new();
-        list.push_back(value);
-        self.options.insert(num, list);
+        self.options.push(tp.into(), &value);
     }
 
     /// Adds an option value using a structured option value format.
@@ -465,11 +645,16 @@ impl Packet {
         self.add_option(tp, value.into());
     }
 
-    /// Removes an option.
+    /// Removes all values of an option, keeping the option without values
+    /// if it is present.
     pub fn clear_option(&mut self, tp: CoapOption) {
-        if let Some(list) = self.options.get_mut(&tp.into()) {
-            list.clear()
-        }
+        self.options.clear_values(tp.into())
+    }
+
+    /// Removes an option along with its values, so that it is absent
+    /// afterwards.
+    pub fn remove_option(&mut self, tp: CoapOption) {
+        self.options.remove(tp.into())
     }
 
     /// Removes all options.
@@ -508,136 +693,280 @@ impl Packet {
             .map(|option| option.map(|value| value.0))
     }
 
+    /// Adds an ETag option value (1 to 8 bytes).
+    pub fn add_etag(
+        &mut self,
+        etag: &[u8],
+    ) -> Result<(), IncompatibleOptionValueFormat> {
+        self.add_checked(CoapOption::ETag, etag)
+    }
+
+    /// Returns the values of the ETag option.
+    pub fn get_etags(
+        &self,
+    ) -> Result<Vec<&[u8]>, IncompatibleOptionValueFormat> {
+        self.get_all_checked(CoapOption::ETag)
+    }
+
+    /// Adds an If-Match option value (0 to 8 bytes).
+    pub fn add_if_match(
+        &mut self,
+        etag: &[u8],
+    ) -> Result<(), IncompatibleOptionValueFormat> {
+        self.add_checked(CoapOption::IfMatch, etag)
+    }
+
+    /// Returns the values of the If-Match option.
+    pub fn get_if_match(
+        &self,
+    ) -> Result<Vec<&[u8]>, IncompatibleOptionValueFormat> {
+        self.get_all_checked(CoapOption::IfMatch)
+    }
+
+    /// Sets or removes the If-None-Match option.
+    pub fn set_if_none_match(&mut self, if_none_match: bool) {
+        self.remove_option(CoapOption::IfNoneMatch);
+        if if_none_match {
+            self.add_option(CoapOption::IfNoneMatch, Vec::new());
+        }
+    }
+
+    /// Returns whether the If-None-Match option is present with a value.
+    pub fn get_if_none_match(&self) -> bool {
+        self.get_option(CoapOption::IfNoneMatch)
+            .is_some_and(|values| !values.is_empty())
+    }
+
+    /// Sets the Max-Age option in seconds.
+    pub fn set_max_age(&mut self, max_age: u32) {
+        self.set_options_as(CoapOption::MaxAge, [OptionValueU32(max_age)]);
+    }
+
+    /// Returns the value of the Max-Age option in seconds, which is 60 if the
+    /// option is absent.
+    pub fn get_max_age(&self) -> Result<u32, IncompatibleOptionValueFormat> {
+        self.get_checked_as::<OptionValueU32>(CoapOption::MaxAge)
+            .unwrap_or(Ok(OptionValueU32(Self::DEFAULT_MAX_AGE)))
+            .map(|value| value.0)
+    }
+
+    /// Sets the Accept option.
+    pub fn set_accept(&mut self, cf: ContentFormat) {
+        let accept = u16::try_from(usize::from(cf)).unwrap();
+        self.set_options_as(CoapOption::Accept, [OptionValueU16(accept)]);
+    }
+
+    /// Returns the Accept option.
+    pub fn get_accept(&self) -> Option<ContentFormat> {
+        self.get_checked_as::<OptionValueU16>(CoapOption::Accept)
+            .and_then(|option| option.ok())
+            .map(|value| usize::from(value.0))
+            .and_then(|value| ContentFormat::try_from(value).ok())
+    }
+
+    /// Sets the Uri-Host option (1 to 255 bytes).
+    pub fn set_uri_host(
+        &mut self,
+        host: &str,
+    ) -> Result<(), IncompatibleOptionValueFormat> {
+        self.set_checked(CoapOption::UriHost, host.as_bytes())
+    }
+
+    /// Returns the value of the Uri-Host option.
+    pub fn get_uri_host(
+        &self,
+    ) -> Option<Result<String, IncompatibleOptionValueFormat>> {
+        self.get_checked_as::<OptionValueString>(CoapOption::UriHost)
+            .map(|option| option.map(|value| value.0))
+    }
+
+    /// Sets the Uri-Port option.
+    pub fn set_uri_port(&mut self, port: u16) {
+        self.set_options_as(CoapOption::UriPort, [OptionValueU16(port)]);
+    }
+
+    /// Returns the value of the Uri-Port option.
+    pub fn get_uri_port(
+        &self,
+    ) -> Option<Result<u16, IncompatibleOptionValueFormat>> {
+        self.get_checked_as::<OptionValueU16>(CoapOption::UriPort)
+            .map(|option| option.map(|value| value.0))
+    }
+
+    /// Adds a Uri-Query option value (0 to 255 bytes).
+    pub fn add_uri_query(
+        &mut self,
+        query: &str,
+    ) -> Result<(), IncompatibleOptionValueFormat> {
+        self.add_checked(CoapOption::UriQuery, query.as_bytes())
+    }
+
+    /// Returns the values of the Uri-Query option.
+    pub fn get_uri_query(
+        &self,
+    ) -> Result<Vec<String>, IncompatibleOptionValueFormat> {
+        self.get_all_checked_as::<OptionValueString>(CoapOption::UriQuery)
+            .map(|values| values.into_iter().map(|value| value.0).collect())
+    }
+
+    /// Adds a Location-Path option value, i.e. one segment of the path (0 to
+    /// 255 bytes).
+    pub fn add_location_path(
+        &mut self,
+        segment: &str,
+    ) -> Result<(), IncompatibleOptionValueFormat> {
+        self.add_checked(CoapOption::LocationPath, segment.as_bytes())
+    }
+
+    /// Returns the values of the Location-Path option.
+    pub fn get_location_path(
+        &self,
+    ) -> Result<Vec<String>, IncompatibleOptionValueFormat> {
+        self.get_all_checked_as::<OptionValueString>(CoapOption::LocationPath)
+            .map(|values| values.into_iter().map(|value| value.0).collect())
+    }
+
+    /// Adds a Location-Query option value (0 to 255 bytes).
+    pub fn add_location_query(
+        &mut self,
+        query: &str,
+    ) -> Result<(), IncompatibleOptionValueFormat> {
+        self.add_checked(CoapOption::LocationQuery, query.as_bytes())
+    }
+
+    /// Returns the values of the Location-Query option.
+    pub fn get_location_query(
+        &self,
+    ) -> Result<Vec<String>, IncompatibleOptionValueFormat> {
+        self.get_all_checked_as::<OptionValueString>(CoapOption::LocationQuery)
+            .map(|values| values.into_iter().map(|value| value.0).collect())
+    }
+
+    /// Sets the Size1 option in bytes.
+    pub fn set_size1(&mut self, size: u32) {
+        self.set_options_as(CoapOption::Size1, [OptionValueU32(size)]);
+    }
+
+    /// Returns the value of the Size1 option in bytes.
+    pub fn get_size1(
+        &self,
+    ) -> Option<Result<u32, IncompatibleOptionValueFormat>> {
+        self.get_checked_as::<OptionValueU32>(CoapOption::Size1)
+            .map(|option| option.map(|value| value.0))
+    }
+
+    /// Sets the Size2 option in bytes.
+    pub fn set_size2(&mut self, size: u32) {
+        self.set_options_as(CoapOption::Size2, [OptionValueU32(size)]);
+    }
+
+    /// Returns the value of the Size2 option in bytes.
+    pub fn get_size2(
+        &self,
+    ) -> Option<Result<u32, IncompatibleOptionValueFormat>> {
+        self.get_checked_as::<OptionValueU32>(CoapOption::Size2)
+            .map(|option| option.map(|value| value.0))
+    }
+
+    /// Sets the Proxy-Uri option (1 to 1034 bytes).
+    pub fn set_proxy_uri(
+        &mut self,
+        uri: &str,
+    ) -> Result<(), IncompatibleOptionValueFormat> {
+        self.set_checked(CoapOption::ProxyUri, uri.as_bytes())
+    }
+
+    /// Returns the value of the Proxy-Uri option.
+    pub fn get_proxy_uri(
+        &self,
+    ) -> Option<Result<String, IncompatibleOptionValueFormat>> {
+        self.get_checked_as::<OptionValueString>(CoapOption::ProxyUri)
+            .map(|option| option.map(|value| value.0))
+    }
+
+    /// Sets the Proxy-Scheme option (1 to 255 bytes).
+    pub fn set_proxy_scheme(
+        &mut self,
+        scheme: &str,
+    ) -> Result<(), IncompatibleOptionValueFormat> {
+        self.set_checked(CoapOption::ProxyScheme, scheme.as_bytes())
+    }
+
+    /// Returns the value of the Proxy-Scheme option.
+    pub fn get_proxy_scheme(
+        &self,
+    ) -> Option<Result<String, IncompatibleOptionValueFormat>> {
+        self.get_checked_as::<OptionValueString>(CoapOption::ProxyScheme)
+            .map(|option| option.map(|value| value.0))
+    }
+
+    /// Replaces an option's values with a single value after checking it
+    /// against the option's length bounds.
+    fn set_checked(
+        &mut self,
+        tp: CoapOption,
+        value: &[u8],
+    ) -> Result<(), IncompatibleOptionValueFormat> {
+        check_option_value(tp, value)?;
+        self.set_option(tp, [value]);
+        Ok(())
+    }
+
+    /// Adds an option value after checking it against the option's length
+    /// bounds.
+    fn add_checked(
+        &mut self,
+        tp: CoapOption,
+        value: &[u8],
+    ) -> Result<(), IncompatibleOptionValueFormat> {
+        check_option_value(tp, value)?;
+        self.add_option(tp, value.to_vec());
+        Ok(())
+    }
+
+    /// Returns an option's first value decoded using the specified
+    /// structured option value format, if it is within the option's bounds.
+    fn get_checked_as<T: OptionValueType>(
+        &self,
+        tp: CoapOption,
+    ) -> Option<Result<T, IncompatibleOptionValueFormat>> {
+        self.get_first_option(tp).map(|value| {
+            check_option_value(tp, value)?;
+            T::try_from(value.to_vec())
+        })
+    }
+
+    /// Returns all of an option's values, if they are within the option's
+    /// bounds.
+    fn get_all_checked(
+        &self,
+        tp: CoapOption,
+    ) -> Result<Vec<&[u8]>, IncompatibleOptionValueFormat> {
+        self.get_option(tp)
+            .into_iter()
+            .flatten()
+            .map(|value| check_option_value(tp, value).map(|()| value))
+            .collect()
+    }
+
+    /// Returns all of an option's values decoded using the specified
+    /// structured option value format, if they are within the option's
+    /// bounds.
+    fn get_all_checked_as<T: OptionValueType>(
+        &self,
+        tp: CoapOption,
+    ) -> Result<Vec<T>, IncompatibleOptionValueFormat> {
+        self.get_all_checked(tp)?
+            .into_iter()
+            .map(|value| T::try_from(value.to_vec()))
+            .collect()
+    }
+
     /// Decodes a byte slice and constructs the equivalent packet.
+    ///
+    /// See [`PacketRef::from_bytes`] to decode without copying.
     pub fn from_bytes(buf: &[u8]) -> Result<Packet, MessageError> {
-        let header_result = HeaderRaw::try_from(buf);
-        match header_result {
-            Ok(raw_header) => {
-                let header = Header::from_raw(&raw_header);
-                let token_length = header.get_token_length();
-                let options_start: usize = 4 + token_length as usize;
-
-                if token_length > 8 {
-                    return Err(MessageError::InvalidTokenLength);
-                }
-
-                if options_start > buf.len() {
-                    return Err(MessageError::InvalidTokenLength);
-                }
-
-                let token = buf[4..options_start].to_vec();
-
-                let mut idx = options_start;
-                let mut options_number = 0u16;
-                let mut options: BTreeMap<u16, LinkedList<Vec<u8>>> =
-                    BTreeMap::new();
-                while idx < buf.len() {
-                    let byte = buf[idx];
-
-                    if byte == 255 || idx > buf.len() {
-                        break;
-                    }
-
-                    let mut delta = (byte >> 4) as u16;
-                    let mut length = (byte & 0xF) as usize;
-
-                    idx += 1;
-
-                    // Check for special delta characters
-                    match delta {
-                        13 => {
-                            if idx >= buf.len() {
-                                return Err(MessageError::InvalidOptionLength);
-                            }
-                            delta = buf[idx] as u16 + 13;
-                            idx += 1;
-                        }
-                        14 => {
-                            if idx + 1 >= buf.len() {
-                                return Err(MessageError::InvalidOptionLength);
-                            }
-
-                            delta = u16::from_be(u8_to_unsigned_be!(
-                                buf,
-                                idx,
-                                idx + 1,
-                                u16
-                            ))
-                            .checked_add(269)
-                            .ok_or(MessageError::InvalidOptionDelta)?;
-                            idx += 2;
-                        }
-                        15 => {
-                            return Err(MessageError::InvalidOptionDelta);
-                        }
-                        _ => {}
-                    };
-
-                    // Check for special length characters
-                    match length {
-                        13 => {
-                            if idx >= buf.len() {
-                                return Err(MessageError::InvalidOptionLength);
-                            }
-
-                            length = buf[idx] as usize + 13;
-                            idx += 1;
-                        }
-                        14 => {
-                            if idx + 1 >= buf.len() {
-                                return Err(MessageError::InvalidOptionLength);
-                            }
-
-                            length = (u16::from_be(u8_to_unsigned_be!(
-                                buf,
-                                idx,
-                                idx + 1,
-                                u16
-                            ))
-                            .checked_add(269)
-                            .ok_or(MessageError::InvalidOptionLength)?)
-                            .into();
-                            idx += 2;
-                        }
-                        15 => {
-                            return Err(MessageError::InvalidOptionLength);
-                        }
-                        _ => {}
-                    };
-
-                    options_number = options_number
-                        .checked_add(delta)
-                        .ok_or(MessageError::InvalidOptionDelta)?;
-
-                    let end = idx + length;
-                    if end > buf.len() {
-                        return Err(MessageError::InvalidOptionLength);
-                    }
-                    let options_value = buf[idx..end].to_vec();
-
-                    options
-                        .entry(options_number)
-                        .or_default()
-                        .push_back(options_value);
-
-                    idx += length;
-                }
-
-                let payload = if idx < buf.len() {
-                    buf[(idx + 1)..buf.len()].to_vec()
-                } else {
-                    Vec::new()
-                };
-
-                Ok(Packet {
-                    header,
-                    token,
-                    options,
-                    payload,
-                })
-            }
-            Err(_) => Err(MessageError::InvalidHeader),
-        }
+        PacketRef::from_bytes(buf).map(Packet::from)
     }
 
     /// Returns a vector of bytes representing the Packet.
@@ -660,134 +989,320 @@ impl Packet {
         self.to_bytes_internal(None)
     }
 
+    /// Encodes the Packet into `buf`, returning the number of bytes written.
+    ///
+    /// Unlike [`Packet::to_bytes`], this does not allocate and is not limited
+    /// by [`Packet::MAX_SIZE`], only by the length of `buf`. If `buf` is too
+    /// small to hold the encoded packet, [`MessageError::BufferTooSmall`] is
+    /// returned and `buf` is left untouched.
+    pub fn encode_into(&self, buf: &mut [u8]) -> Result<usize, MessageError> {
+        if self.encoded_len() > buf.len() {
+            return Err(MessageError::BufferTooSmall);
+        }
+
+        let mut sink = SliceSink { buf, len: 0 };
+        self.encode(&mut sink)?;
+        Ok(sink.len)
+    }
+
+    /// Encodes the Packet into the given writer, returning the number of
+    /// bytes written.
+    #[cfg(feature = "std")]
+    pub fn write_to<W: std::io::Write>(
+        &self,
+        writer: &mut W,
+    ) -> std::io::Result<usize> {
+        let mut sink = WriteSink { writer, len: 0 };
+        self.encode(&mut sink)?;
+        Ok(sink.len)
+    }
+
     fn to_bytes_internal(
         &self,
         limit: Option<usize>,
     ) -> Result<Vec<u8>, MessageError> {
+        let buf_length = self.encoded_len();
+        if limit.is_some() && buf_length > limit.unwrap() {
+            return Err(MessageError::InvalidPacketLength);
+        }
+
+        let mut buf: Vec<u8> = Vec::with_capacity(buf_length);
+        self.encode(&mut buf)?;
+
+        Ok(buf)
+    }
+
+    /// Returns the number of bytes the Packet occupies when encoded, without
+    /// encoding it.
+    pub fn encoded_len(&self) -> usize {
+        let extended_token_length = extended_token_length(self.token.len())
+            .map_or(0, |(_, length)| length);
+
+        4 + extended_token_length
+            + self.token.len()
+            + self.options_encoded_len()
+            + self.payload_len()
+    }
+
+    /// Returns the number of bytes the options of the Packet occupy when
+    /// encoded, including the option headers.
+    pub fn options_encoded_len(&self) -> usize {
         let mut options_delta_length = 0;
-        let mut options_bytes: Vec<u8> = Vec::new();
-        for (number, value_list) in self.options.iter() {
-            for value in value_list.iter() {
-                let mut header: Vec<u8> = Vec::with_capacity(1 + 2 + 2);
-                let delta = number - options_delta_length;
-
-                let mut byte: u8 = 0;
-                if delta <= 12 {
-                    byte |= (delta << 4) as u8;
-                } else if delta < 269 {
-                    byte |= 13 << 4;
-                } else {
-                    byte |= 14 << 4;
-                }
-                if value.len() <= 12 {
-                    byte |= value.len() as u8;
-                } else if value.len() < 269 {
-                    byte |= 13;
-                } else {
-                    byte |= 14;
-                }
-                header.push(byte);
-
-                if delta > 12 && delta < 269 {
-                    header.push((delta - 13) as u8);
-                } else if delta >= 269 {
-                    let fix = delta - 269;
-                    header.push((fix >> 8) as u8);
-                    header.push((fix & 0xFF) as u8);
-                }
-
-                if value.len() > 12 && value.len() < 269 {
-                    header.push((value.len() - 13) as u8);
-                } else if value.len() >= 269 {
-                    let fix = (value.len() - 269) as u16;
-                    header.push((fix >> 8) as u8);
-                    header.push((fix & 0xFF) as u8);
-                }
-
-                options_delta_length += delta;
-
-                options_bytes.reserve(header.len() + value.len());
-                unsafe {
-                    use core::ptr;
-                    let buf_len = options_bytes.len();
-                    ptr::copy(
-                        header.as_ptr(),
-                        options_bytes.as_mut_ptr().add(buf_len),
-                        header.len(),
-                    );
-                    ptr::copy(
-                        value.as_ptr(),
-                        options_bytes.as_mut_ptr().add(buf_len + header.len()),
-                        value.len(),
-                    );
-                    options_bytes
-                        .set_len(buf_len + header.len() + value.len());
-                }
-            }
+        let mut length = 0;
+        for (number, value) in self.options.iter() {
+            let delta = number - options_delta_length;
+            length += option_header_len(delta, value.len()) + value.len();
+            options_delta_length += delta;
         }
+        length
+    }
 
-        let mut buf_length = 4 + self.payload.len() + self.token.len();
+    /// Returns the number of bytes of the payload including the payload
+    /// marker, if it is encoded at all.
+    pub(crate) fn payload_len(&self) -> usize {
         if self.header.code != MessageClass::Empty && !self.payload.is_empty()
         {
-            buf_length += 1;
+            1 + self.payload.len()
+        } else {
+            0
         }
-        buf_length += options_bytes.len();
+    }
 
-        if limit.is_some() && buf_length > limit.unwrap() {
-            return Err(MessageError::InvalidPacketLength);
+    fn encode<S: Sink>(&self, sink: &mut S) -> Result<(), S::Error> {
+        let (extended, extended_length) =
+            extended_token_length(self.token.len())?;
+        if self.header.code == MessageClass::Empty
+            && (!self.token.is_empty()
+                || !self.options.is_empty()
+                || !self.payload.is_empty())
+        {
+            return Err(MessageError::InvalidEmptyMessage.into());
         }
+        sink.put(&self.header.to_raw().to_bytes())?;
+        sink.put(&extended[..extended_length])?;
+        sink.put(&self.token)?;
+        self.encode_options_and_payload(sink)
+    }
 
-        let mut buf: Vec<u8> = Vec::with_capacity(buf_length);
-        let header_result = self.header.to_raw().serialize_into(&mut buf);
-
-        match header_result {
-            Ok(_) => {
-                buf.reserve(self.token.len() + options_bytes.len());
-                unsafe {
-                    use core::ptr;
-                    let buf_len = buf.len();
-                    ptr::copy(
-                        self.token.as_ptr(),
-                        buf.as_mut_ptr().add(buf_len),
-                        self.token.len(),
-                    );
-                    ptr::copy(
-                        options_bytes.as_ptr(),
-                        buf.as_mut_ptr().add(buf_len + self.token.len()),
-                        options_bytes.len(),
-                    );
-                    buf.set_len(
-                        buf_len + self.token.len() + options_bytes.len(),
-                    );
-                }
-
-                if self.header.code != MessageClass::Empty
-                    && !self.payload.is_empty()
-                {
-                    buf.push(0xFF);
-                    buf.reserve(self.payload.len());
-                    unsafe {
-                        use core::ptr;
-                        let buf_len = buf.len();
-                        ptr::copy(
-                            self.payload.as_ptr(),
-                            buf.as_mut_ptr().add(buf.len()),
-                            self.payload.len(),
-                        );
-                        buf.set_len(buf_len + self.payload.len());
-                    }
-                }
-                Ok(buf)
-            }
-            Err(_) => Err(MessageError::InvalidHeader),
+    /// Encodes everything that follows the token, which is the same for all
+    /// transports.
+    pub(crate) fn encode_options_and_payload<S: Sink>(
+        &self,
+        sink: &mut S,
+    ) -> Result<(), S::Error> {
+        let mut options_delta_length = 0;
+        for (number, value) in self.options.iter() {
+            let delta = number - options_delta_length;
+            let mut header = [0u8; 5];
+            let header_length =
+                encode_option_header(delta, value.len(), &mut header)?;
+            sink.put(&header[..header_length])?;
+            sink.put(value)?;
+            options_delta_length += delta;
+        }
+
+        if self.header.code != MessageClass::Empty && !self.payload.is_empty()
+        {
+            sink.put(&[0xFF])?;
+            sink.put(&self.payload)?;
         }
+
+        Ok(())
+    }
+}
+
+/// Checks an option value against the length bounds and format of the
+/// option.
+fn check_option_value(
+    tp: CoapOption,
+    value: &[u8],
+) -> Result<(), IncompatibleOptionValueFormat> {
+    match builtin_properties(tp) {
+        Some(properties)
+            if value.len() < properties.min_length
+                || value.len() > properties.max_length =>
+        {
+            Err(IncompatibleOptionValueFormat {
+                message: format!(
+                    "{} must be {} to {} bytes, got {}",
+                    properties.name,
+                    properties.min_length,
+                    properties.max_length,
+                    value.len()
+                ),
+            })
+        }
+        Some(properties) if !properties.is_valid_value(value) => {
+            Err(IncompatibleOptionValueFormat {
+                message: format!("invalid {} value", properties.name),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns the value of the token length field for a token of the given
+/// length. For extended tokens this indicates the size of the extended token
+/// length field instead.
+pub(crate) fn token_length_field(length: usize) -> u8 {
+    match length {
+        0..=12 => length as u8,
+        13..=268 => 13,
+        _ => 14,
+    }
+}
+
+/// Returns the extended token length field (RFC 8974) that precedes a token
+/// of the given length and the number of bytes it occupies, which is zero for
+/// regular tokens.
+pub(crate) fn extended_token_length(
+    length: usize,
+) -> Result<([u8; 2], usize), MessageError> {
+    match length {
+        0..=8 => Ok(([0; 2], 0)),
+        #[cfg(feature = "extended-tokens")]
+        13..=268 => Ok(([(length - 13) as u8, 0], 1)),
+        #[cfg(feature = "extended-tokens")]
+        269..=65804 => Ok((((length - 269) as u16).to_be_bytes(), 2)),
+        _ => Err(MessageError::InvalidTokenLength),
+    }
+}
+
+/// Decodes the token length from the token length field and the extended
+/// token length field (RFC 8974) at the start of `buf`, returning the token
+/// length and the number of bytes the extended field occupies, or `None` if
+/// `buf` is too short to hold it.
+pub(crate) fn decode_token_length(
+    field: u8,
+    buf: &[u8],
+) -> Result<Option<(usize, usize)>, MessageError> {
+    match (field, buf) {
+        (0..=8, _) => Ok(Some((field.into(), 0))),
+        #[cfg(feature = "extended-tokens")]
+        (13, [extended, ..]) => Ok(Some((usize::from(*extended) + 13, 1))),
+        #[cfg(feature = "extended-tokens")]
+        (14, [high, low, ..]) => Ok(Some((
+            usize::from(u16::from_be_bytes([*high, *low])) + 269,
+            2,
+        ))),
+        #[cfg(feature = "extended-tokens")]
+        (13 | 14, _) => Ok(None),
+        _ => Err(MessageError::InvalidTokenLength),
+    }
+}
+
+/// Returns the number of bytes the header of an option occupies.
+fn option_header_len(delta: u16, length: usize) -> usize {
+    let extended_len = |value: usize| match value {
+        0..=12 => 0,
+        13..=268 => 1,
+        _ => 2,
+    };
+    1 + extended_len(delta.into()) + extended_len(length)
+}
+
+/// Encodes the header of an option (the delta/length byte followed by the
+/// extended delta and length, if any) into `header`, returning the number of
+/// bytes used.
+fn encode_option_header(
+    delta: u16,
+    length: usize,
+    header: &mut [u8; 5],
+) -> Result<usize, MessageError> {
+    if length > u16::MAX as usize + 269 {
+        return Err(MessageError::InvalidOptionLength);
+    }
+
+    let mut idx = 1;
+    let mut byte: u8 = 0;
+    if delta <= 12 {
+        byte |= (delta << 4) as u8;
+    } else if delta < 269 {
+        byte |= 13 << 4;
+        header[idx] = (delta - 13) as u8;
+        idx += 1;
+    } else {
+        byte |= 14 << 4;
+        header[idx..idx + 2].copy_from_slice(&(delta - 269).to_be_bytes());
+        idx += 2;
+    }
+    if length <= 12 {
+        byte |= length as u8;
+    } else if length < 269 {
+        byte |= 13;
+        header[idx] = (length - 13) as u8;
+        idx += 1;
+    } else {
+        byte |= 14;
+        let fix = (length - 269) as u16;
+        header[idx..idx + 2].copy_from_slice(&fix.to_be_bytes());
+        idx += 2;
+    }
+    header[0] = byte;
+
+    Ok(idx)
+}
+
+/// A destination for the bytes of an encoded packet.
+pub(crate) trait Sink {
+    type Error: From<MessageError>;
+
+    fn put(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+}
+
+impl Sink for Vec<u8> {
+    type Error = MessageError;
+
+    fn put(&mut self, bytes: &[u8]) -> Result<(), MessageError> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// Writes into a fixed-size buffer, failing once it is full.
+pub(crate) struct SliceSink<'a> {
+    pub(crate) buf: &'a mut [u8],
+    pub(crate) len: usize,
+}
+
+impl Sink for SliceSink<'_> {
+    type Error = MessageError;
+
+    fn put(&mut self, bytes: &[u8]) -> Result<(), MessageError> {
+        let end = self.len + bytes.len();
+        if end > self.buf.len() {
+            return Err(MessageError::BufferTooSmall);
+        }
+        self.buf[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Writes into an [`std::io::Write`], counting the bytes written.
+#[cfg(feature = "std")]
+pub(crate) struct WriteSink<'a, W> {
+    pub(crate) writer: &'a mut W,
+    pub(crate) len: usize,
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> Sink for WriteSink<'_, W> {
+    type Error = std::io::Error;
+
+    fn put(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.len += bytes.len();
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{header, option_value::OptionValueString};
+    use crate::{
+        header, option_value::OptionValueString, RequestType, ResponseType,
+    };
     use alloc::borrow::ToOwned;
 
     #[test]
@@ -808,22 +1323,19 @@ mod test {
         );
         assert_eq!(packet.header.message_id, 33950);
         assert_eq!(*packet.get_token(), vec![0x51, 0x55, 0x77, 0xE8]);
-        assert_eq!(packet.options.len(), 2);
+        assert_eq!(packet.options().len(), 2);
 
         let uri_path = packet.get_option(CoapOption::UriPath);
         assert!(uri_path.is_some());
         let uri_path = uri_path.unwrap();
-        let mut expected_uri_path = LinkedList::new();
-        expected_uri_path.push_back("Hi".as_bytes().to_vec());
-        expected_uri_path.push_back("Test".as_bytes().to_vec());
-        assert_eq!(*uri_path, expected_uri_path);
+        let expected_uri_path = ["Hi".as_bytes(), "Test".as_bytes()];
+        assert!(uri_path.iter().eq(expected_uri_path));
 
         let uri_query = packet.get_option(CoapOption::UriQuery);
         assert!(uri_query.is_some());
         let uri_query = uri_query.unwrap();
-        let mut expected_uri_query = LinkedList::new();
-        expected_uri_query.push_back("a=1".as_bytes().to_vec());
-        assert_eq!(*uri_query, expected_uri_query);
+        let expected_uri_query = ["a=1".as_bytes()];
+        assert!(uri_query.iter().eq(expected_uri_query));
     }
 
     #[test]
@@ -971,6 +1483,20 @@ mod test {
         assert_eq!(3, pp.options().len());
     }
 
+    #[test]
+    fn remove_option() {
+        let mut p = Packet::new();
+        p.add_option(CoapOption::UriPath, vec![1]);
+        p.add_option(CoapOption::ETag, vec![2]);
+        p.clear_option(CoapOption::ETag);
+        assert!(p.get_option(CoapOption::ETag).unwrap().is_empty());
+        p.remove_option(CoapOption::ETag);
+        assert_eq!(None, p.get_option(CoapOption::ETag));
+        assert_eq!(1, p.options().len());
+        p.clear_option(CoapOption::ETag);
+        assert_eq!(1, p.options().len());
+    }
+
     #[test]
     fn test_option_u32_format() {
         let mut p = Packet::new();
@@ -1021,6 +1547,125 @@ mod test {
         assert!(packet.to_bytes_unlimited().is_ok());
     }
 
+    #[test]
+    fn encode_into_matches_to_bytes() {
+        let mut packet = Packet::new();
+        packet.header.message_id = 33950;
+        packet.set_token(vec![0x51, 0x55, 0x77, 0xE8]);
+        packet.add_option(CoapOption::UriPath, b"Hi".to_vec());
+        packet.add_option(CoapOption::UriQuery, vec![b'a'; 20]);
+        packet.add_option(CoapOption::NoResponse, vec![b'b'; 300]);
+        packet.payload = b"Hello".to_vec();
+        let expected = packet.to_bytes().unwrap();
+
+        let mut buf = [0u8; 512];
+        let len = packet.encode_into(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &expected[..]);
+
+        #[cfg(feature = "std")]
+        {
+            let mut written = Vec::new();
+            assert_eq!(packet.write_to(&mut written).unwrap(), expected.len());
+            assert_eq!(written, expected);
+        }
+
+        assert_eq!(Packet::from_bytes(&buf[..len]).unwrap(), packet);
+    }
+
+    #[test]
+    fn encoded_len_matches_to_bytes() {
+        let mut packet = Packet::new();
+        assert_eq!(packet.encoded_len(), packet.to_bytes().unwrap().len());
+
+        packet.set_token(vec![1, 2, 3, 4]);
+        packet.add_option(CoapOption::IfMatch, vec![]);
+        packet.add_option(CoapOption::UriPath, vec![b'a'; 12]);
+        packet.add_option(CoapOption::UriPath, vec![b'b'; 13]);
+        packet.add_option(CoapOption::UriQuery, vec![b'c'; 268]);
+        packet.add_option(CoapOption::Size1, vec![b'd'; 269]);
+        packet.add_option(CoapOption::NoResponse, vec![0]);
+        packet.add_option(CoapOption::Unknown(2000), vec![b'e'; 1000]);
+        let bytes = packet.to_bytes_unlimited().unwrap();
+        assert_eq!(packet.options_encoded_len(), bytes.len() - 8);
+        assert_eq!(packet.encoded_len(), bytes.len());
+
+        packet.payload = b"Hello".to_vec();
+        let bytes = packet.to_bytes_unlimited().unwrap();
+        assert_eq!(packet.encoded_len(), bytes.len());
+    }
+
+    #[test]
+    fn empty_messages() {
+        for (packet, message_type) in [
+            (Packet::empty_ack(0x1234), MessageType::Acknowledgement),
+            (Packet::reset(0x1234), MessageType::Reset),
+            (Packet::ping(0x1234), MessageType::Confirmable),
+        ] {
+            assert_eq!(packet.header.get_type(), message_type);
+            let bytes = packet.to_bytes().unwrap();
+            assert_eq!(bytes.len(), 4);
+            assert_eq!(Packet::from_bytes(&bytes).unwrap(), packet);
+        }
+
+        let mut packet = Packet::ping(1);
+        packet.set_token(vec![1]);
+        assert_eq!(packet.to_bytes(), Err(MessageError::InvalidEmptyMessage));
+
+        let mut packet = Packet::ping(1);
+        packet.add_option(CoapOption::UriPath, b"a".to_vec());
+        assert_eq!(
+            packet.encode_into(&mut [0; 16]),
+            Err(MessageError::InvalidEmptyMessage)
+        );
+
+        let mut packet = Packet::empty_ack(1);
+        packet.payload = b"Hello".to_vec();
+        assert_eq!(packet.to_bytes(), Err(MessageError::InvalidEmptyMessage));
+    }
+
+    #[test]
+    fn resets() {
+        let ping = Packet::ping(7);
+        assert_eq!(ping.reset_for_ping(), Some(Packet::reset(7)));
+        assert_eq!(ping.reset_for_unexpected_response(), None);
+        assert_eq!(Packet::empty_ack(7).reset_for_ping(), None);
+
+        let mut response = Packet::new();
+        response.header.message_id = 8;
+        response.header.code = MessageClass::Response(ResponseType::Content);
+        assert_eq!(response.reset_for_ping(), None);
+        assert_eq!(
+            response.reset_for_unexpected_response(),
+            Some(Packet::reset(8))
+        );
+        response.header.set_type(MessageType::NonConfirmable);
+        assert!(response.reset_for_unexpected_response().is_some());
+        response.header.set_type(MessageType::Acknowledgement);
+        assert_eq!(response.reset_for_unexpected_response(), None);
+
+        let request = Packet::request(RequestType::Get).build().unwrap();
+        assert_eq!(request.reset_for_ping(), None);
+        assert_eq!(request.reset_for_unexpected_response(), None);
+    }
+
+    #[test]
+    fn encode_into_buffer_too_small() {
+        let mut packet = Packet::new();
+        packet.payload = b"Hello".to_vec();
+        let expected = packet.to_bytes().unwrap();
+
+        let mut buf = vec![0u8; expected.len() - 1];
+        assert_eq!(
+            packet.encode_into(&mut buf),
+            Err(MessageError::BufferTooSmall)
+        );
+        let mut buf = [0u8; 2];
+        assert_eq!(
+            packet.encode_into(&mut buf),
+            Err(MessageError::BufferTooSmall)
+        );
+    }
+
     #[test]
     fn option_delta_u8_overflow() {
         // Build a packet with options 1 and 258, which have a delta of 257.
@@ -1039,11 +1684,8 @@ mod test {
         // Verify everything round-trips
         let output = Packet::from_bytes(&bytes).unwrap();
         assert_eq!(output.options().len(), 2);
-        assert_eq!(output.get_first_option(option_1), Some(vec![0]).as_ref());
-        assert_eq!(
-            output.get_first_option(option_258),
-            Some(vec![1]).as_ref()
-        );
+        assert_eq!(output.get_first_option(option_1), Some(&[0][..]));
+        assert_eq!(output.get_first_option(option_258), Some(&[1][..]));
     }
 
     #[test]
@@ -1085,4 +1727,138 @@ mod test {
         let result = Packet::from_bytes(&bytes);
         assert_eq!(result, Err(MessageError::InvalidOptionDelta));
     }
+
+    #[cfg(feature = "extended-tokens")]
+    #[test]
+    fn extended_tokens() {
+        let mut packet = Packet::new();
+        packet.set_token(vec![0xAB; 13]);
+        let bytes = packet.to_bytes().unwrap();
+        assert_eq!(bytes[..6], [0x4D, 0x01, 0x00, 0x00, 0x00, 0xAB]);
+        assert_eq!(packet.encoded_len(), bytes.len());
+        assert_eq!(Packet::from_bytes(&bytes).unwrap(), packet);
+
+        for token_length in [268, 269, 1000, 65804] {
+            let mut packet = Packet::new();
+            packet.set_token(vec![0xAB; token_length]);
+            packet.payload = b"Hello".to_vec();
+            let bytes = packet.to_bytes_internal(None).unwrap();
+            assert_eq!(packet.encoded_len(), bytes.len());
+            assert_eq!(Packet::from_bytes(&bytes).unwrap(), packet);
+        }
+
+        for token_length in [9, 12, 65805] {
+            let mut packet = Packet::new();
+            packet.set_token(vec![0xAB; token_length]);
+            assert_eq!(
+                packet.to_bytes_internal(None),
+                Err(MessageError::InvalidTokenLength)
+            );
+        }
+
+        assert_eq!(
+            Packet::from_bytes(&[0x4D, 0x00, 0x00, 0x00]),
+            Err(MessageError::InvalidTokenLength)
+        );
+        assert_eq!(
+            Packet::from_bytes(&[0x4D, 0x00, 0x00, 0x00, 0x01, 0xAB]),
+            Err(MessageError::InvalidTokenLength)
+        );
+        assert_eq!(
+            Packet::from_bytes(&[0x4F, 0x00, 0x00, 0x00, 0x00, 0x00]),
+            Err(MessageError::InvalidTokenLength)
+        );
+    }
+
+    #[cfg(not(feature = "extended-tokens"))]
+    #[test]
+    fn reject_extended_tokens() {
+        let mut packet = Packet::new();
+        packet.set_token(vec![0xAB; 13]);
+        assert_eq!(packet.to_bytes(), Err(MessageError::InvalidTokenLength));
+
+        let mut bytes = vec![0x4D, 0x00, 0x00, 0x00, 0x00];
+        bytes.extend([0xAB; 13]);
+        assert_eq!(
+            Packet::from_bytes(&bytes),
+            Err(MessageError::InvalidTokenLength)
+        );
+    }
+
+    #[test]
+    fn typed_accessors() {
+        let mut packet = Packet::new();
+        assert_eq!(packet.get_max_age(), Ok(60));
+        assert_eq!(packet.get_etags(), Ok(vec![]));
+        assert!(packet.get_uri_host().is_none());
+        assert!(!packet.get_if_none_match());
+
+        packet.add_etag(b"v1").unwrap();
+        packet.add_etag(b"v2").unwrap();
+        packet.add_if_match(b"").unwrap();
+        packet.set_if_none_match(true);
+        packet.set_max_age(3600);
+        packet.set_accept(ContentFormat::ApplicationCBOR);
+        packet.set_uri_host("example.com").unwrap();
+        packet.set_uri_port(5683);
+        packet.add_uri_query("a=1").unwrap();
+        packet.add_uri_query("b").unwrap();
+        packet.add_location_path("items").unwrap();
+        packet.add_location_path("1").unwrap();
+        packet.add_location_query("v=2").unwrap();
+        packet.set_size1(1024);
+        packet.set_size2(0);
+        packet.set_proxy_uri("coap://example.com/").unwrap();
+        packet.set_proxy_scheme("coap").unwrap();
+
+        let packet = Packet::from_bytes(&packet.to_bytes().unwrap()).unwrap();
+        assert_eq!(packet.get_etags(), Ok(vec![&b"v1"[..], b"v2"]));
+        assert_eq!(packet.get_if_match(), Ok(vec![&b""[..]]));
+        assert!(packet.get_if_none_match());
+        assert_eq!(packet.get_max_age(), Ok(3600));
+        assert_eq!(packet.get_accept(), Some(ContentFormat::ApplicationCBOR));
+        assert_eq!(packet.get_uri_host(), Some(Ok("example.com".to_owned())));
+        assert_eq!(packet.get_uri_port(), Some(Ok(5683)));
+        assert_eq!(packet.get_uri_query(), Ok(vec!["a=1".into(), "b".into()]));
+        assert_eq!(
+            packet.get_location_path(),
+            Ok(vec!["items".into(), "1".into()])
+        );
+        assert_eq!(packet.get_location_query(), Ok(vec!["v=2".into()]));
+        assert_eq!(packet.get_size1(), Some(Ok(1024)));
+        assert_eq!(packet.get_size2(), Some(Ok(0)));
+        assert_eq!(
+            packet.get_proxy_uri(),
+            Some(Ok("coap://example.com/".to_owned()))
+        );
+        assert_eq!(packet.get_proxy_scheme(), Some(Ok("coap".to_owned())));
+
+        let mut packet = packet;
+        packet.clear_option(CoapOption::IfNoneMatch);
+        assert!(!packet.get_if_none_match());
+    }
+
+    #[test]
+    fn typed_accessors_enforce_limits() {
+        let mut packet = Packet::new();
+        assert_eq!(
+            packet.add_etag(b""),
+            Err(IncompatibleOptionValueFormat {
+                message: "ETag must be 1 to 8 bytes, got 0".to_owned()
+            })
+        );
+        assert!(packet.add_if_match(&[0; 9]).is_err());
+        assert!(packet.set_uri_host("").is_err());
+        assert!(packet.add_uri_query(&"q".repeat(256)).is_err());
+        assert!(packet.set_proxy_uri(&"p".repeat(1035)).is_err());
+        assert!(packet.set_proxy_uri(&"p".repeat(1034)).is_ok());
+        assert!(packet.get_option(CoapOption::ETag).is_none());
+
+        packet.add_option(CoapOption::MaxAge, vec![1, 0, 0, 0, 0]);
+        packet.add_option(CoapOption::UriHost, vec![0xFF]);
+        packet.add_option(CoapOption::ETag, vec![0; 9]);
+        assert!(packet.get_max_age().is_err());
+        assert!(matches!(packet.get_uri_host(), Some(Err(_))));
+        assert!(packet.get_etags().is_err());
+    }
 }
@@ -0,0 +1,390 @@
+use core::convert::TryFrom;
+
+use crate::{
+    error::{IncompatibleOptionValueFormat, MessageError},
+    header::{Header, HeaderRaw},
+    option_store::OptionStore,
+    option_value::{OptionValueType, OptionValueU16, OptionValueU32},
+    packet::{decode_token_length, CoapOption, ContentFormat, Packet},
+};
+
+/// A borrowed, read-only view of an encoded CoAP packet.
+///
+/// The buffer is validated once when the view is created, after which the
+/// token, options and payload are handed out as slices of the original buffer
+/// without any allocation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PacketRef<'a> {
+    pub header: Header,
+    token: &'a [u8],
+    options: &'a [u8],
+    pub payload: &'a [u8],
+}
+
+/// An iterator over the options of a [`PacketRef`], yielding the option
+/// number and the borrowed option value.
+#[derive(Debug, Clone)]
+pub struct OptionsRef<'a> {
+    buf: &'a [u8],
+    idx: usize,
+    number: u16,
+}
+
+impl<'a> OptionsRef<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> OptionsRef<'a> {
+        OptionsRef {
+            buf,
+            idx: 0,
+            number: 0,
+        }
+    }
+
+    /// Decodes the next option, returning `None` once the end of the buffer
+    /// or the payload marker is reached.
+    pub(crate) fn next_option(
+        &mut self,
+    ) -> Result<Option<(u16, &'a [u8])>, MessageError> {
+        let buf = self.buf;
+        let mut idx = self.idx;
+
+        if idx >= buf.len() || buf[idx] == 0xFF {
+            return Ok(None);
+        }
+
+        let byte = buf[idx];
+        let mut delta = (byte >> 4) as u16;
+        let mut length = (byte & 0xF) as usize;
+
+        idx += 1;
+
+        // Check for special delta characters
+        match delta {
+            13 => {
+                if idx >= buf.len() {
+                    return Err(MessageError::InvalidOptionLength);
+                }
+                delta = buf[idx] as u16 + 13;
+                idx += 1;
+            }
+            14 => {
+                if idx + 1 >= buf.len() {
+                    return Err(MessageError::InvalidOptionLength);
+                }
+                delta = u16::from_be_bytes([buf[idx], buf[idx + 1]])
+                    .checked_add(269)
+                    .ok_or(MessageError::InvalidOptionDelta)?;
+                idx += 2;
+            }
+            15 => {
+                return Err(MessageError::InvalidOptionDelta);
+            }
+            _ => {}
+        };
+
+        // Check for special length characters
+        match length {
+            13 => {
+                if idx >= buf.len() {
+                    return Err(MessageError::InvalidOptionLength);
+                }
+                length = buf[idx] as usize + 13;
+                idx += 1;
+            }
+            14 => {
+                if idx + 1 >= buf.len() {
+                    return Err(MessageError::InvalidOptionLength);
+                }
+                length = u16::from_be_bytes([buf[idx], buf[idx + 1]])
+                    .checked_add(269)
+                    .ok_or(MessageError::InvalidOptionLength)?
+                    .into();
+                idx += 2;
+            }
+            15 => {
+                return Err(MessageError::InvalidOptionLength);
+            }
+            _ => {}
+        };
+
+        let number = self
+            .number
+            .checked_add(delta)
+            .ok_or(MessageError::InvalidOptionDelta)?;
+
+        let end = idx + length;
+        if end > buf.len() {
+            return Err(MessageError::InvalidOptionLength);
+        }
+
+        self.idx = end;
+        self.number = number;
+
+        Ok(Some((number, &buf[idx..end])))
+    }
+
+    /// Returns the offset of the next option in the buffer.
+    pub(crate) fn position(&self) -> usize {
+        self.idx
+    }
+}
+
+impl<'a> Iterator for OptionsRef<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The options were validated when the PacketRef was created, so an
+        // error can only mean we are at the end.
+        self.next_option().ok().flatten()
+    }
+}
+
+impl<'a> PacketRef<'a> {
+    /// Decodes a byte slice into a view of the packet, without copying any
+    /// of its contents.
+    pub fn from_bytes(buf: &'a [u8]) -> Result<PacketRef<'a>, MessageError> {
+        let raw_header = HeaderRaw::try_from(buf)
+            .map_err(|_| MessageError::InvalidHeader)?;
+        let header = Header::from_raw(&raw_header);
+        let (token_length, extended_length) =
+            decode_token_length(header.get_token_length(), &buf[4..])?
+                .ok_or(MessageError::InvalidTokenLength)?;
+        let token_start = 4 + extended_length;
+        let options_start = token_start + token_length;
+
+        if options_start > buf.len() {
+            return Err(MessageError::InvalidTokenLength);
+        }
+
+        let token = &buf[token_start..options_start];
+        let (options, payload) = Self::split_body(&buf[options_start..])?;
+
+        Ok(PacketRef {
+            header,
+            token,
+            options,
+            payload,
+        })
+    }
+
+    /// Creates a view from an already decoded header and token, validating
+    /// the options and payload in `body`. This allows transports with a
+    /// different header layout to share the option and payload codec.
+    pub(crate) fn from_parts(
+        header: Header,
+        token: &'a [u8],
+        body: &'a [u8],
+    ) -> Result<PacketRef<'a>, MessageError> {
+        let (options, payload) = Self::split_body(body)?;
+
+        Ok(PacketRef {
+            header,
+            token,
+            options,
+            payload,
+        })
+    }
+
+    /// Validates the options in `body` and splits it into the encoded options
+    /// and the payload.
+    fn split_body(
+        body: &'a [u8],
+    ) -> Result<(&'a [u8], &'a [u8]), MessageError> {
+        let mut options = OptionsRef::new(body);
+        while options.next_option()?.is_some() {}
+
+        let idx = options.idx;
+        let payload = if idx < body.len() {
+            &body[(idx + 1)..]
+        } else {
+            &[]
+        };
+
+        Ok((&body[..idx], payload))
+    }
+
+    /// Returns the token.
+    pub fn get_token(&self) -> &'a [u8] {
+        self.token
+    }
+
+    /// Returns an iterator over the options of the packet, in the order they
+    /// appear on the wire.
+    pub fn options(&self) -> OptionsRef<'a> {
+        OptionsRef::new(self.options)
+    }
+
+    /// Returns an iterator over an option's values.
+    pub fn get_option(
+        &self,
+        tp: CoapOption,
+    ) -> impl Iterator<Item = &'a [u8]> + 'a {
+        let number = u16::from(tp);
+        self.options()
+            .skip_while(move |&(n, _)| n < number)
+            .take_while(move |&(n, _)| n == number)
+            .map(|(_, value)| value)
+    }
+
+    /// Returns an option's first value as a convenience when only one is
+    /// expected.
+    pub fn get_first_option(&self, tp: CoapOption) -> Option<&'a [u8]> {
+        self.get_option(tp).next()
+    }
+
+    /// Returns an option's first value as a convenience when only one is
+    /// expected.
+    pub fn get_first_option_as<T: OptionValueType>(
+        &self,
+        tp: CoapOption,
+    ) -> Option<Result<T, IncompatibleOptionValueFormat>> {
+        self.get_first_option(tp)
+            .map(|value| T::try_from(value.to_vec()))
+    }
+
+    /// Returns the content-format.
+    pub fn get_content_format(&self) -> Option<ContentFormat> {
+        self.get_first_option_as::<OptionValueU16>(CoapOption::ContentFormat)
+            .and_then(|option| option.ok())
+            .map(|value| usize::from(value.0))
+            .and_then(|value| ContentFormat::try_from(value).ok())
+    }
+
+    /// Returns the value of the observe option.
+    pub fn get_observe_value(
+        &self,
+    ) -> Option<Result<u32, IncompatibleOptionValueFormat>> {
+        self.get_first_option_as::<OptionValueU32>(CoapOption::Observe)
+            .map(|option| option.map(|value| value.0))
+    }
+
+    /// Copies the contents of the view into an owned packet.
+    pub fn to_packet(&self) -> Packet {
+        let mut packet = Packet::new();
+        packet.header = self.header.clone();
+        packet.set_token(self.token.to_vec());
+        packet.options = OptionStore::with_capacity(
+            self.options().count(),
+            self.options.len(),
+        );
+        for (number, value) in self.options() {
+            packet.options.push(number, value);
+        }
+        packet.payload = self.payload.to_vec();
+        packet
+    }
+}
+
+impl From<PacketRef<'_>> for Packet {
+    fn from(packet: PacketRef<'_>) -> Packet {
+        packet.to_packet()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::header::{MessageClass, MessageType, RequestType};
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_decode_packet_ref_with_options() {
+        let buf = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69,
+            0x04, 0x54, 0x65, 0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
+        ];
+        let packet = PacketRef::from_bytes(&buf).unwrap();
+        assert_eq!(packet.header.get_type(), MessageType::Confirmable);
+        assert_eq!(
+            packet.header.code,
+            MessageClass::Request(RequestType::Get)
+        );
+        assert_eq!(packet.header.message_id, 33950);
+        assert_eq!(packet.get_token(), &[0x51, 0x55, 0x77, 0xE8]);
+        assert_eq!(packet.options().count(), 3);
+
+        let uri_path: Vec<_> =
+            packet.get_option(CoapOption::UriPath).collect();
+        assert_eq!(uri_path, [&b"Hi"[..], &b"Test"[..]]);
+        assert_eq!(
+            packet.get_first_option(CoapOption::UriQuery),
+            Some(&b"a=1"[..])
+        );
+        assert_eq!(packet.get_first_option(CoapOption::ETag), None);
+        assert!(packet.payload.is_empty());
+    }
+
+    #[test]
+    fn test_decode_packet_ref_with_payload() {
+        let buf = [
+            0x64, 0x45, 0x13, 0xFD, 0xD0, 0xE2, 0x4D, 0xAC, 0xFF, 0x48, 0x65,
+            0x6C, 0x6C, 0x6F,
+        ];
+        let packet = PacketRef::from_bytes(&buf).unwrap();
+        assert_eq!(packet.get_token(), &[0xD0, 0xE2, 0x4D, 0xAC]);
+        assert_eq!(packet.options().count(), 0);
+        assert_eq!(packet.payload, b"Hello");
+    }
+
+    #[test]
+    fn test_packet_ref_to_packet() {
+        let mut packet = Packet::new();
+        packet.header.message_id = 42;
+        packet.set_token(vec![1, 2, 3]);
+        packet.add_option(CoapOption::UriPath, b"sensors".to_vec());
+        packet.add_option(CoapOption::UriPath, b"temp".to_vec());
+        packet.set_content_format(ContentFormat::ApplicationCBOR);
+        packet.set_observe_value(7);
+        packet.payload = b"payload".to_vec();
+        let bytes = packet.to_bytes().unwrap();
+
+        let packet_ref = PacketRef::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            packet_ref.get_content_format(),
+            Some(ContentFormat::ApplicationCBOR)
+        );
+        assert_eq!(packet_ref.get_observe_value(), Some(Ok(7)));
+        assert_eq!(packet_ref.to_packet(), packet);
+        assert_eq!(Packet::from(packet_ref), packet);
+    }
+
+    #[test]
+    fn test_packet_ref_readable_message() {
+        use coap_message_0_3::{MessageOption, ReadableMessage};
+
+        let mut packet = Packet::new();
+        packet.add_option(CoapOption::UriPath, b"a".to_vec());
+        packet.add_option(CoapOption::UriHost, b"host".to_vec());
+        packet.payload = b"hi".to_vec();
+        let bytes = packet.to_bytes().unwrap();
+        let packet_ref = PacketRef::from_bytes(&bytes).unwrap();
+
+        let options: Vec<_> = ReadableMessage::options(&packet_ref)
+            .map(|o| (o.number(), o.value().to_vec()))
+            .collect();
+        let expected: Vec<_> = ReadableMessage::options(&packet)
+            .map(|o| (o.number(), o.value().to_vec()))
+            .collect();
+        assert_eq!(options, expected);
+        assert_eq!(ReadableMessage::payload(&packet_ref), b"hi");
+    }
+
+    #[test]
+    fn test_packet_ref_rejects_malformed() {
+        assert_eq!(
+            PacketRef::from_bytes(&[0x40, 0x01, 0x00]),
+            Err(MessageError::InvalidHeader)
+        );
+        assert_eq!(
+            PacketRef::from_bytes(&[0x49, 0x01, 0x00, 0x00]),
+            Err(MessageError::InvalidTokenLength)
+        );
+        assert_eq!(
+            PacketRef::from_bytes(&[0x40, 0x01, 0x00, 0x00, 0xf0]),
+            Err(MessageError::InvalidOptionDelta)
+        );
+        assert_eq!(
+            PacketRef::from_bytes(&[0x40, 0x01, 0x00, 0x00, 0x13, 0x00]),
+            Err(MessageError::InvalidOptionLength)
+        );
+    }
+}
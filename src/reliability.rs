@@ -0,0 +1,443 @@
+//! Reliable transmission of Confirmable messages (RFC 7252, Section 4.2).
+//!
+//! [`ReliabilityLayer`] doesn't perform any I/O or read the clock itself:
+//! the caller hands it outgoing messages and incoming acknowledgements along
+//! with the current time, and it reports which messages to transmit and
+//! which exchanges have completed.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use core::time::Duration;
+
+use crate::{header::MessageType, packet::Packet};
+
+/// The maximum time a datagram is expected to take from the start of its
+/// transmission to the completion of its reception (MAX_LATENCY).
+const MAX_LATENCY: Duration = Duration::from_secs(100);
+
+/// The transmission parameters of RFC 7252, Section 4.8.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransmissionParameters {
+    /// The minimum time to wait for an acknowledgement before retransmitting
+    /// a Confirmable message.
+    pub ack_timeout: Duration,
+    /// The factor by which the initial timeout is randomly stretched, which
+    /// must be at least 1. Smaller values, including NaN, are treated as 1.
+    pub ack_random_factor: f32,
+    /// The number of retransmissions after which an exchange times out.
+    pub max_retransmit: u8,
+    /// The period within which a server randomly delays its response to a
+    /// multicast request (Leisure, see [`crate::CoapRequest::set_multicast`]).
+    pub default_leisure: Duration,
+}
+
+impl Default for TransmissionParameters {
+    fn default() -> Self {
+        Self {
+            ack_timeout: Duration::from_secs(2),
+            ack_random_factor: 1.5,
+            max_retransmit: 4,
+            default_leisure: Duration::from_secs(5),
+        }
+    }
+}
+
+impl TransmissionParameters {
+    /// Returns the maximum time from the first transmission of a Confirmable
+    /// message to its last retransmission (MAX_TRANSMIT_SPAN).
+    ///
+    /// Like the other derived durations, this saturates at [`Duration::MAX`]
+    /// for parameters too large to represent it.
+    pub fn max_transmit_span(&self) -> Duration {
+        self.stretched_ack_timeout(self.ack_random_factor)
+            .saturating_mul(backoff_factor(self.max_retransmit.into()))
+    }
+
+    /// Returns the maximum time from the first transmission of a Confirmable
+    /// message to when the sender gives up on receiving an acknowledgement
+    /// (MAX_TRANSMIT_WAIT).
+    pub fn max_transmit_wait(&self) -> Duration {
+        self.stretched_ack_timeout(self.ack_random_factor)
+            .saturating_mul(backoff_factor(u32::from(self.max_retransmit) + 1))
+    }
+
+    /// Returns the time from the first transmission of a Confirmable message
+    /// until its message ID can safely be reused (EXCHANGE_LIFETIME), using
+    /// the default MAX_LATENCY of 100 seconds and ACK_TIMEOUT as
+    /// PROCESSING_DELAY.
+    pub fn exchange_lifetime(&self) -> Duration {
+        self.max_transmit_span()
+            .saturating_add(2 * MAX_LATENCY)
+            .saturating_add(self.ack_timeout)
+    }
+
+    /// Returns ACK_TIMEOUT stretched by a factor, which is raised to 1 if it
+    /// is smaller.
+    fn stretched_ack_timeout(&self, factor: f32) -> Duration {
+        let factor = factor.max(1.0);
+        Duration::try_from_secs_f32(self.ack_timeout.as_secs_f32() * factor)
+            .unwrap_or(Duration::MAX)
+    }
+}
+
+/// Returns the sum of the timeout multipliers of `transmissions`
+/// transmissions with exponential back-off, 2^transmissions - 1.
+fn backoff_factor(transmissions: u32) -> u32 {
+    1u32.checked_shl(transmissions)
+        .map_or(u32::MAX, |power| power - 1)
+}
+
+/// What the caller of a [`ReliabilityLayer`] needs to act upon.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReliabilityEvent<Endpoint> {
+    /// The packet should be sent to the endpoint now.
+    Transmit(Endpoint, Packet),
+    /// The Confirmable packet was acknowledged by the endpoint.
+    Acknowledged(Endpoint, Packet),
+    /// The endpoint rejected the Confirmable packet with a Reset message.
+    Reset(Endpoint, Packet),
+    /// The Confirmable packet wasn't acknowledged after the last
+    /// retransmission, so the endpoint should be considered unreachable.
+    TimedOut(Endpoint, Packet),
+}
+
+/// A Confirmable message waiting for an acknowledgement.
+#[derive(Debug, Clone)]
+struct Exchange {
+    packet: Packet,
+    /// How often the packet has been sent so far.
+    transmissions: u16,
+    /// The time to wait after the latest transmission.
+    timeout: Duration,
+    /// When the next transmission or the timeout is due.
+    deadline: Duration,
+}
+
+/// Keeps track of outgoing Confirmable messages, retransmitting them with
+/// exponential back-off until they are acknowledged or time out.
+///
+/// Time is passed in by the caller as the duration since an arbitrary but
+/// fixed point, e.g. the start of the program, so this works without an OS
+/// clock and in deterministic tests. Exchanges are identified by endpoint
+/// and message ID, so message IDs must not be reused while an exchange with
+/// the same endpoint is outstanding.
+#[derive(Debug, Clone)]
+pub struct ReliabilityLayer<Endpoint: Ord + Clone> {
+    parameters: TransmissionParameters,
+    exchanges: BTreeMap<(Endpoint, u16), Exchange>,
+    /// Messages to transmit once without awaiting an acknowledgement.
+    unreliable: VecDeque<(Endpoint, Packet)>,
+    /// State of the generator for the random initial timeouts.
+    random_state: u32,
+}
+
+impl<Endpoint: Ord + Clone> ReliabilityLayer<Endpoint> {
+    /// Creates a new reliability layer.
+    ///
+    /// The seed randomizes the initial timeouts, so it should differ between
+    /// devices to keep them from retransmitting in lockstep.
+    pub fn new(parameters: TransmissionParameters, seed: u32) -> Self {
+        Self {
+            parameters,
+            exchanges: BTreeMap::new(),
+            unreliable: VecDeque::new(),
+            random_state: seed,
+        }
+    }
+
+    /// Returns the transmission parameters.
+    pub fn parameters(&self) -> &TransmissionParameters {
+        &self.parameters
+    }
+
+    /// Queues a packet for transmission to an endpoint.
+    ///
+    /// Confirmable packets are retransmitted until they are acknowledged or
+    /// time out, while all other packets are transmitted once.
+    pub fn send(&mut self, endpoint: Endpoint, packet: Packet, now: Duration) {
+        if packet.header.get_type() != MessageType::Confirmable {
+            self.unreliable.push_back((endpoint, packet));
+            return;
+        }
+
+        let timeout = self.initial_timeout();
+        self.exchanges.insert(
+            (endpoint, packet.header.message_id),
+            Exchange {
+                packet,
+                transmissions: 0,
+                timeout,
+                deadline: now,
+            },
+        );
+    }
+
+    /// Cancels the exchange of a Confirmable packet, e.g. because the
+    /// response arrived in a separate message, returning the packet if the
+    /// exchange was outstanding.
+    pub fn cancel(
+        &mut self,
+        endpoint: &Endpoint,
+        message_id: u16,
+    ) -> Option<Packet> {
+        self.exchanges
+            .remove(&(endpoint.clone(), message_id))
+            .map(|exchange| exchange.packet)
+    }
+
+    /// Handles a packet received from an endpoint, completing the exchange
+    /// it acknowledges or rejects.
+    ///
+    /// Returns the event for the completed exchange, or `None` if the packet
+    /// is not an Acknowledgement or Reset of an outstanding exchange (for
+    /// example because it is a duplicate).
+    ///
+    /// Either way, the packet may still need to be processed further by the
+    /// caller: an Acknowledgement completing an exchange can carry a
+    /// piggybacked response, which has to be passed on like any other
+    /// response.
+    pub fn handle_incoming(
+        &mut self,
+        endpoint: &Endpoint,
+        packet: &Packet,
+    ) -> Option<ReliabilityEvent<Endpoint>> {
+        let completion = match packet.header.get_type() {
+            MessageType::Acknowledgement => ReliabilityEvent::Acknowledged,
+            MessageType::Reset => ReliabilityEvent::Reset,
+            _ => return None,
+        };
+        let key = (endpoint.clone(), packet.header.message_id);
+        let exchange = self.exchanges.remove(&key)?;
+        Some(completion(key.0, exchange.packet))
+    }
+
+    /// Returns the next event that is due at `now`, if any.
+    ///
+    /// This should be called until it returns `None` whenever a packet was
+    /// queued and whenever the time returned by
+    /// [`ReliabilityLayer::poll_timeout`] has been reached.
+    pub fn poll(
+        &mut self,
+        now: Duration,
+    ) -> Option<ReliabilityEvent<Endpoint>> {
+        if let Some((endpoint, packet)) = self.unreliable.pop_front() {
+            return Some(ReliabilityEvent::Transmit(endpoint, packet));
+        }
+
+        let (key, exchange) = self
+            .exchanges
+            .iter_mut()
+            .filter(|(_, exchange)| exchange.deadline <= now)
+            .min_by_key(|(_, exchange)| exchange.deadline)?;
+
+        if exchange.transmissions > self.parameters.max_retransmit.into() {
+            let key = key.clone();
+            let exchange = self.exchanges.remove(&key)?;
+            return Some(ReliabilityEvent::TimedOut(key.0, exchange.packet));
+        }
+
+        if exchange.transmissions > 0 {
+            exchange.timeout = exchange.timeout.saturating_mul(2);
+        }
+        exchange.transmissions += 1;
+        exchange.deadline = now.saturating_add(exchange.timeout);
+        Some(ReliabilityEvent::Transmit(
+            key.0.clone(),
+            exchange.packet.clone(),
+        ))
+    }
+
+    /// Returns the time at which [`ReliabilityLayer::poll`] should be called
+    /// next, or `None` if there is nothing to wait for.
+    pub fn poll_timeout(&self) -> Option<Duration> {
+        if !self.unreliable.is_empty() {
+            return Some(Duration::ZERO);
+        }
+
+        self.exchanges
+            .values()
+            .map(|exchange| exchange.deadline)
+            .min()
+    }
+
+    /// Returns the number of Confirmable packets awaiting an acknowledgement
+    /// from an endpoint.
+    pub fn outstanding(&self, endpoint: &Endpoint) -> usize {
+        self.exchanges
+            .keys()
+            .filter(|(candidate, _)| candidate == endpoint)
+            .count()
+    }
+
+    /// Returns a random initial timeout between ACK_TIMEOUT and ACK_TIMEOUT
+    /// times ACK_RANDOM_FACTOR.
+    fn initial_timeout(&mut self) -> Duration {
+        // Linear congruential generator, which is plenty for spreading out
+        // retransmissions
+        self.random_state = self
+            .random_state
+            .wrapping_mul(1_664_525)
+            .wrapping_add(1_013_904_223);
+        let random = (self.random_state >> 8) as f32 / (1 << 24) as f32;
+
+        let stretch =
+            (self.parameters.ack_random_factor.max(1.0) - 1.0) * random;
+        self.parameters.stretched_ack_timeout(1.0 + stretch)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::header::{MessageClass, RequestType};
+    use alloc::vec::Vec;
+
+    fn confirmable(message_id: u16) -> Packet {
+        let mut packet = Packet::new();
+        packet.header.message_id = message_id;
+        packet
+    }
+
+    fn empty(message_type: MessageType, message_id: u16) -> Packet {
+        let mut packet = Packet::new();
+        packet.header.set_type(message_type);
+        packet.header.code = MessageClass::Empty;
+        packet.header.message_id = message_id;
+        packet
+    }
+
+    fn deterministic() -> TransmissionParameters {
+        TransmissionParameters {
+            ack_random_factor: 1.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn retransmits_with_backoff_until_timeout() {
+        let mut layer = ReliabilityLayer::new(deterministic(), 0);
+        let packet = confirmable(1);
+        layer.send("peer", packet.clone(), Duration::ZERO);
+        assert_eq!(layer.poll_timeout(), Some(Duration::ZERO));
+
+        let mut transmissions = Vec::new();
+        let mut now = Duration::ZERO;
+        let timed_out = loop {
+            match layer.poll(now) {
+                Some(ReliabilityEvent::Transmit("peer", sent)) => {
+                    assert_eq!(sent, packet);
+                    transmissions.push(now.as_secs());
+                }
+                Some(event) => break event,
+                None => now = layer.poll_timeout().unwrap(),
+            }
+        };
+
+        assert_eq!(transmissions, [0, 2, 6, 14, 30]);
+        assert_eq!(now.as_secs(), 62);
+        assert_eq!(now, deterministic().max_transmit_wait());
+        assert_eq!(
+            TransmissionParameters::default().exchange_lifetime(),
+            Duration::from_secs(247)
+        );
+        assert_eq!(timed_out, ReliabilityEvent::TimedOut("peer", packet));
+        assert_eq!(layer.poll_timeout(), None);
+    }
+
+    #[test]
+    fn extreme_parameters_saturate() {
+        let parameters = TransmissionParameters {
+            ack_random_factor: -1.0,
+            max_retransmit: u8::MAX,
+            ..Default::default()
+        };
+        assert_eq!(
+            parameters.max_transmit_span(),
+            Duration::from_secs(2) * u32::MAX
+        );
+        assert_eq!(
+            parameters.max_transmit_wait(),
+            parameters.max_transmit_span()
+        );
+        let huge = TransmissionParameters {
+            ack_random_factor: f32::INFINITY,
+            ..Default::default()
+        };
+        assert_eq!(huge.exchange_lifetime(), Duration::MAX);
+
+        let nan = TransmissionParameters {
+            ack_random_factor: f32::NAN,
+            ..Default::default()
+        };
+        assert_eq!(nan.max_transmit_span(), Duration::from_secs(30));
+
+        let mut layer = ReliabilityLayer::new(parameters, 0);
+        layer.send("peer", confirmable(1), Duration::ZERO);
+        let mut now = Duration::ZERO;
+        for _ in 0..100 {
+            assert!(matches!(
+                layer.poll(now),
+                Some(ReliabilityEvent::Transmit(..))
+            ));
+            now = layer.poll_timeout().unwrap();
+        }
+        assert_eq!(now, Duration::MAX);
+    }
+
+    #[test]
+    fn completes_on_ack_and_reset() {
+        let mut layer = ReliabilityLayer::new(deterministic(), 0);
+        layer.send("a", confirmable(1), Duration::ZERO);
+        layer.send("a", confirmable(2), Duration::ZERO);
+        layer.send("b", confirmable(1), Duration::ZERO);
+        assert!(matches!(
+            layer.poll(Duration::ZERO),
+            Some(ReliabilityEvent::Transmit(_, _))
+        ));
+        assert_eq!(layer.outstanding(&"a"), 2);
+
+        let ack = empty(MessageType::Acknowledgement, 1);
+        assert_eq!(
+            layer.handle_incoming(&"a", &ack),
+            Some(ReliabilityEvent::Acknowledged("a", confirmable(1)))
+        );
+        assert_eq!(layer.handle_incoming(&"a", &ack), None);
+
+        let reset = empty(MessageType::Reset, 1);
+        assert_eq!(
+            layer.handle_incoming(&"b", &reset),
+            Some(ReliabilityEvent::Reset("b", confirmable(1)))
+        );
+        assert_eq!(
+            layer.handle_incoming(&"a", &confirmable(2)),
+            None,
+            "only ACK and RST complete exchanges"
+        );
+        assert_eq!(layer.cancel(&"a", 2), Some(confirmable(2)));
+        assert_eq!(layer.outstanding(&"a"), 0);
+        assert_eq!(layer.poll(Duration::from_secs(100)), None);
+    }
+
+    #[test]
+    fn non_confirmable_and_random_timeouts() {
+        let mut layer = ReliabilityLayer::new(Default::default(), 42);
+        let mut packet = Packet::new();
+        packet.header.set_type(MessageType::NonConfirmable);
+        packet.header.code = MessageClass::Request(RequestType::Get);
+        layer.send("a", packet.clone(), Duration::ZERO);
+        assert_eq!(
+            layer.poll(Duration::ZERO),
+            Some(ReliabilityEvent::Transmit("a", packet))
+        );
+        assert_eq!(layer.poll(Duration::ZERO), None);
+        assert_eq!(layer.poll_timeout(), None);
+
+        for message_id in 0..100 {
+            layer.send("a", confirmable(message_id), Duration::ZERO);
+            layer.poll(Duration::ZERO).unwrap();
+            let timeout = layer.poll_timeout().unwrap();
+            assert!(timeout >= Duration::from_secs(2));
+            assert!(timeout < Duration::from_secs(3));
+            layer.cancel(&"a", message_id);
+        }
+    }
+}
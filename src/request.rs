@@ -2,14 +2,20 @@ use alloc::{
     string::{String, ToString},
     vec::Vec,
 };
-use core::convert::TryFrom;
+use core::{convert::TryFrom, str::FromStr, time::Duration};
+
+use rand_core::RngCore;
 
 use crate::{
-    error::{HandlingError, IncompatibleOptionValueFormat, InvalidObserve},
-    header::{MessageClass, RequestType as Method},
+    error::{
+        HandlingError, IncompatibleOptionValueFormat, InvalidObserve,
+        InvalidUri,
+    },
+    header::{MessageClass, MessageType, RequestType as Method},
     option_value::OptionValueString,
     packet::{CoapOption, ObserveOption, Packet},
     response::CoapResponse,
+    uri::percent_decode,
     ContentFormat,
 };
 
@@ -19,6 +25,7 @@ pub struct CoapRequest<Endpoint> {
     pub message: Packet,
     pub response: Option<CoapResponse>,
     pub source: Option<Endpoint>,
+    multicast: bool,
 }
 
 impl<Endpoint> CoapRequest<Endpoint> {
@@ -36,6 +43,7 @@ impl<Endpoint> CoapRequest<Endpoint> {
             response: CoapResponse::new(&packet),
             message: packet,
             source: Some(source),
+            multicast: false,
         }
     }
 
@@ -54,6 +62,53 @@ impl<Endpoint> CoapRequest<Endpoint> {
         false
     }
 
+    /// Sets whether the request was sent to a multicast address, which is up
+    /// to the transport to tell since the message itself doesn't show it.
+    pub fn set_multicast(&mut self, multicast: bool) {
+        self.multicast = multicast;
+    }
+
+    /// Returns whether the request was sent to a multicast address (see
+    /// [`CoapRequest::set_multicast`]).
+    pub fn is_multicast(&self) -> bool {
+        self.multicast
+    }
+
+    /// Returns the response that should be sent, along with how long to wait
+    /// before sending it.
+    ///
+    /// Responses to unicast requests are sent right away. For multicast
+    /// requests, many servers may answer at once, so following RFC 7252,
+    /// Section 8.2 and RFC 7390, Section 2.7, the response is delayed by a
+    /// random time within the `leisure` period (see
+    /// [`crate::TransmissionParameters::default_leisure`]), and it is
+    /// suppressed if it is an error or the request was Confirmable, which
+    /// multicast requests must not be.
+    pub fn outgoing_response<R: RngCore>(
+        &self,
+        leisure: Duration,
+        rng: &mut R,
+    ) -> Option<(&Packet, Duration)> {
+        let response = &self.response.as_ref()?.message;
+        if !self.multicast {
+            return Some((response, Duration::ZERO));
+        }
+
+        match (self.message.header.get_type(), response.header.code) {
+            (MessageType::NonConfirmable, MessageClass::Response(status))
+                if !status.is_error() =>
+            {
+                let millis = leisure.as_millis().min(u128::from(u32::MAX));
+                let delay = match millis {
+                    0 => 0,
+                    millis => u64::from(rng.next_u32()) % millis as u64,
+                };
+                Some((response, Duration::from_millis(delay)))
+            }
+            _ => None,
+        }
+    }
+
     /// Sets the method.
     pub fn set_method(&mut self, method: Method) {
         self.message.header.code = MessageClass::Request(method);
@@ -124,6 +179,89 @@ impl<Endpoint> CoapRequest<Endpoint> {
             )
     }
 
+    /// Returns the arguments in the Uri-Query options as key-value pairs, in
+    /// the order they appear in.
+    ///
+    /// Arguments are split at the first `=`, and those without one (flags
+    /// like `obs`) have no value. Arguments that aren't valid UTF-8 are
+    /// skipped.
+    pub fn get_query_pairs(&self) -> Vec<(&str, Option<&str>)> {
+        self.message
+            .get_option(CoapOption::UriQuery)
+            .into_iter()
+            .flatten()
+            .filter_map(|argument| core::str::from_utf8(argument).ok())
+            .map(|argument| match argument.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (argument, None),
+            })
+            .collect()
+    }
+
+    /// Returns the value of the first query argument with the given key,
+    /// which is empty for a flag.
+    pub fn get_query(&self, key: &str) -> Option<&str> {
+        self.get_query_all(key).into_iter().next()
+    }
+
+    /// Returns the values of all query arguments with the given key.
+    pub fn get_query_all(&self, key: &str) -> Vec<&str> {
+        self.get_query_pairs()
+            .into_iter()
+            .filter(|(candidate, _)| *candidate == key)
+            .map(|(_, value)| value.unwrap_or(""))
+            .collect()
+    }
+
+    /// Returns the value of the first query argument with the given key,
+    /// parsed as `T`.
+    pub fn get_query_as<T: FromStr>(
+        &self,
+        key: &str,
+    ) -> Option<Result<T, T::Err>> {
+        self.get_query(key).map(str::parse)
+    }
+
+    /// Replaces the Uri-Query options with the arguments of a URI query
+    /// string like `rt=temp&obs`, percent-decoding them.
+    pub fn set_query(&mut self, query: &str) -> Result<(), InvalidUri> {
+        let arguments = if query.is_empty() {
+            Vec::new()
+        } else {
+            query
+                .split('&')
+                .map(percent_decode)
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        self.message.remove_option(CoapOption::UriQuery);
+        for argument in arguments {
+            self.message
+                .add_option(CoapOption::UriQuery, argument.into());
+        }
+        Ok(())
+    }
+
+    /// Adds a query argument, or a flag if there is no value.
+    ///
+    /// Returns InvalidUri if the key contains `=`, since the argument would
+    /// be read back with a different key.
+    pub fn add_query(
+        &mut self,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<(), InvalidUri> {
+        if key.contains('=') {
+            return Err(InvalidUri);
+        }
+        let argument = match value {
+            Some(value) => format!("{}={}", key, value),
+            None => key.to_string(),
+        };
+        self.message
+            .add_option(CoapOption::UriQuery, argument.into_bytes());
+        Ok(())
+    }
+
     /// Returns the flag in the Observe option or InvalidObserve if the flag
     /// was provided but not understood.
     pub fn get_observe_flag(
@@ -151,6 +289,7 @@ impl<Endpoint> Default for CoapRequest<Endpoint> {
             response: None,
             message: Packet::new(),
             source: None,
+            multicast: false,
         }
     }
 }
@@ -316,4 +455,103 @@ mod test {
         let actual = request.get_observe_flag();
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_query() {
+        let mut request: CoapRequest<Endpoint> = CoapRequest::new();
+        assert!(request.get_query_pairs().is_empty());
+        assert_eq!(request.get_query("rt"), None);
+
+        request
+            .set_query("rt=core.s&obs&if=a%26b&rt=temp&n=42")
+            .unwrap();
+        request.add_query("href", Some("/sensors*")).unwrap();
+        request.add_query("x", Some("=")).unwrap();
+        assert_eq!(request.add_query("x=", None), Err(InvalidUri));
+        assert_eq!(request.add_query("a=b", Some("c")), Err(InvalidUri));
+        assert_eq!(
+            request.get_query_pairs(),
+            [
+                ("rt", Some("core.s")),
+                ("obs", None),
+                ("if", Some("a&b")),
+                ("rt", Some("temp")),
+                ("n", Some("42")),
+                ("href", Some("/sensors*")),
+                ("x", Some("=")),
+            ]
+        );
+        assert_eq!(request.get_query("rt"), Some("core.s"));
+        assert_eq!(request.get_query_all("rt"), ["core.s", "temp"]);
+        assert_eq!(request.get_query("obs"), Some(""));
+        assert_eq!(request.get_query_as::<u8>("n"), Some(Ok(42)));
+        assert!(matches!(request.get_query_as::<u8>("if"), Some(Err(_))));
+        assert_eq!(request.get_query_as::<u8>("missing"), None);
+
+        assert_eq!(request.set_query("a=%2"), Err(InvalidUri));
+        request.set_query("").unwrap();
+        assert_eq!(request.message.get_option(CoapOption::UriQuery), None);
+    }
+
+    /// Always yields the same number.
+    struct FixedRng(u32);
+
+    impl RngCore for FixedRng {
+        fn next_u32(&mut self) -> u32 {
+            self.0
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            u64::from(self.0)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            rand_core::impls::fill_bytes_via_next(self, dest)
+        }
+
+        fn try_fill_bytes(
+            &mut self,
+            dest: &mut [u8],
+        ) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_outgoing_response() {
+        let leisure = Duration::from_secs(5);
+        let mut rng = FixedRng(7_300);
+        let mut packet = Packet::new();
+        packet.header.set_type(MessageType::NonConfirmable);
+        packet.header.code = MessageClass::Request(Method::Get);
+        let mut request =
+            CoapRequest::from_packet(packet, Endpoint("a".to_string()));
+        let response = request.response.clone().unwrap().message;
+        assert_eq!(
+            request.outgoing_response(leisure, &mut rng),
+            Some((&response, Duration::ZERO))
+        );
+
+        request.set_multicast(true);
+        assert_eq!(
+            request.outgoing_response(leisure, &mut rng),
+            Some((&response, Duration::from_millis(2_300)))
+        );
+        assert_eq!(
+            request.outgoing_response(Duration::ZERO, &mut rng),
+            Some((&response, Duration::ZERO))
+        );
+
+        request.apply_from_error(HandlingError::not_found());
+        assert_eq!(request.outgoing_response(leisure, &mut rng), None);
+
+        request.response = CoapResponse::new(&request.message);
+        request.message.header.set_type(MessageType::Confirmable);
+        assert_eq!(request.outgoing_response(leisure, &mut rng), None);
+
+        request.response = None;
+        request.set_multicast(false);
+        assert_eq!(request.outgoing_response(leisure, &mut rng), None);
+    }
 }
@@ -11,6 +11,11 @@ pub struct CoapResponse {
 
 impl CoapResponse {
     /// Creates a new response.
+    ///
+    /// Returns `None` if the request is an ACK or RST, which are never
+    /// answered with a response. See [`Packet::reset_for_ping`] and
+    /// [`Packet::reset_for_unexpected_response`] for messages a server needs
+    /// to reject instead.
     pub fn new(request: &Packet) -> Option<CoapResponse> {
         let mut packet = Packet::new();
 
@@ -28,6 +33,57 @@ impl CoapResponse {
         Some(CoapResponse { message: packet })
     }
 
+    /// Creates the Empty ACK that acknowledges a Confirmable request
+    /// without a response, so that the response can be sent separately once
+    /// it's ready (see [`CoapResponse::make_separate`]).
+    ///
+    /// Returns `None` if the request is not Confirmable, since only those
+    /// are acknowledged.
+    pub fn empty_ack(request: &Packet) -> Option<Packet> {
+        match request.header.get_type() {
+            MessageType::Confirmable => {
+                Some(Packet::empty_ack(request.header.message_id))
+            }
+            _ => None,
+        }
+    }
+
+    /// Turns the response into a separate response, which is sent in its own
+    /// Confirmable (if `is_confirmable` is `true`) or Non-confirmable message
+    /// with a new message ID after the request has been acknowledged with an
+    /// Empty ACK. The token stays the same so the client can match the
+    /// response to its request.
+    ///
+    /// A Confirmable separate response has to be retransmitted until the
+    /// client acknowledges it, which [`crate::ReliabilityLayer`] takes care
+    /// of, or [`CoapResponse::is_acknowledged_by`] can be used to check for
+    /// manually.
+    pub fn make_separate(&mut self, is_confirmable: bool, message_id: u16) {
+        self.message.header.set_type(if is_confirmable {
+            MessageType::Confirmable
+        } else {
+            MessageType::NonConfirmable
+        });
+        self.message.header.message_id = message_id;
+    }
+
+    /// Returns whether the response is sent separately rather than
+    /// piggybacked on the acknowledgement of the request.
+    pub fn is_separate(&self) -> bool {
+        self.message.header.get_type() != MessageType::Acknowledgement
+    }
+
+    /// Returns whether `packet` is the ACK of this response, which is only
+    /// sent for separate Confirmable responses.
+    ///
+    /// Note that the packet has to come from the endpoint the response was
+    /// sent to, which the caller needs to check.
+    pub fn is_acknowledged_by(&self, packet: &Packet) -> bool {
+        self.message.header.get_type() == MessageType::Confirmable
+            && packet.header.get_type() == MessageType::Acknowledgement
+            && packet.header.message_id == self.message.header.message_id
+    }
+
     /// Sets the status.
     pub fn set_status(&mut self, status: Status) {
         self.message.header.code = MessageClass::Response(status);
@@ -65,4 +121,36 @@ mod test {
         packet.header.set_type(MessageType::Acknowledgement);
         assert!(CoapResponse::new(&packet).is_none());
     }
+
+    #[test]
+    fn test_separate_response() {
+        let mut request = Packet::new();
+        request.header.message_id = 1;
+        request.set_token(vec![0xAB]);
+
+        let ack = CoapResponse::empty_ack(&request).unwrap();
+        assert_eq!(ack.header.get_type(), MessageType::Acknowledgement);
+        assert_eq!(ack.header.code, MessageClass::Empty);
+        assert_eq!(ack.header.message_id, 1);
+        assert!(ack.get_token().is_empty());
+
+        let mut response = CoapResponse::new(&request).unwrap();
+        assert!(!response.is_separate());
+        response.make_separate(true, 2);
+        assert!(response.is_separate());
+        assert_eq!(response.message.header.message_id, 2);
+        assert_eq!(response.message.get_token(), [0xAB]);
+
+        assert!(!response.is_acknowledged_by(&ack));
+        let mut client_ack = ack.clone();
+        client_ack.header.message_id = 2;
+        assert!(response.is_acknowledged_by(&client_ack));
+
+        response.make_separate(false, 3);
+        client_ack.header.message_id = 3;
+        assert!(!response.is_acknowledged_by(&client_ack));
+
+        request.header.set_type(MessageType::NonConfirmable);
+        assert!(CoapResponse::empty_ack(&request).is_none());
+    }
 }
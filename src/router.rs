@@ -0,0 +1,355 @@
+//! Dispatching requests to handlers by method and path.
+//!
+//! [`Router`] replaces matching on [`CoapRequest::get_path`] by hand: routes
+//! are registered for a method and a path pattern, and requests are passed
+//! to the handler of the first route that matches, along with the values of
+//! the parameters in the pattern.
+
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::{error::HandlingError, CoapRequest, RequestType};
+
+/// The signature of the handlers of a [`Router`].
+type Handler<Endpoint> = Box<
+    dyn FnMut(
+            &mut CoapRequest<Endpoint>,
+            &PathParams,
+        ) -> Result<(), HandlingError>
+        + Send,
+>;
+
+/// A segment of a path pattern.
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    /// Matches the segment itself.
+    Literal(String),
+    /// Matches any segment, whose value is captured under the name.
+    Param(String),
+    /// Matches any remaining segments.
+    Wildcard,
+}
+
+struct Route<Endpoint> {
+    method: RequestType,
+    pattern: Vec<Segment>,
+    handler: Handler<Endpoint>,
+}
+
+/// The values of the parameters of the path pattern that matched a request.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PathParams {
+    params: Vec<(String, String)>,
+    wildcard: Option<String>,
+}
+
+impl PathParams {
+    /// Returns the value of the parameter with the name, e.g. `id` for the
+    /// pattern `sensors/{id}/value`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the segments matched by the trailing `*` of the pattern,
+    /// joined by `/`, or `None` if the pattern has none.
+    pub fn wildcard(&self) -> Option<&str> {
+        self.wildcard.as_deref()
+    }
+}
+
+/// Dispatches requests to handlers by method and path.
+///
+/// The segments of a path pattern are separated by `/` and either match
+/// themselves, match any single segment if they are a parameter in braces
+/// like `{id}`, or match all remaining segments, including none, if the last
+/// one is `*`. For example, `sensors/{id}/value` matches `sensors/7/value`
+/// with the parameter `id` set to `7`, and `fw/*` matches `fw/v2/image.bin`.
+///
+/// ```rust
+/// use coap_lite::{RequestType, Router};
+/// use std::net::SocketAddr;
+///
+/// let router: Router<SocketAddr> = Router::new().route(
+///     RequestType::Get,
+///     "sensors/{id}/value",
+///     |request, params| {
+///         let id = params.get("id").unwrap_or_default();
+///         if let Some(response) = &mut request.response {
+///             response.message.payload = id.as_bytes().to_vec();
+///         }
+///         Ok(())
+///     },
+/// );
+/// ```
+pub struct Router<Endpoint> {
+    routes: Vec<Route<Endpoint>>,
+}
+
+impl<Endpoint> Default for Router<Endpoint> {
+    fn default() -> Self {
+        Self { routes: Vec::new() }
+    }
+}
+
+impl<Endpoint> Router<Endpoint> {
+    /// Creates a router without routes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a route for requests with a method and a path matching the
+    /// pattern. Routes are tried in the order they were added.
+    pub fn route<F>(
+        mut self,
+        method: RequestType,
+        pattern: &str,
+        handler: F,
+    ) -> Self
+    where
+        F: FnMut(
+                &mut CoapRequest<Endpoint>,
+                &PathParams,
+            ) -> Result<(), HandlingError>
+            + Send
+            + 'static,
+    {
+        let segments: Vec<&str> = split_path(pattern).collect();
+        let last = segments.len().saturating_sub(1);
+        let pattern = segments
+            .iter()
+            .enumerate()
+            .map(|(i, segment)| match *segment {
+                "*" if i == last => Segment::Wildcard,
+                segment
+                    if segment.starts_with('{')
+                        && segment.ends_with('}')
+                        && segment.len() > 1 =>
+                {
+                    Segment::Param(segment[1..segment.len() - 1].to_string())
+                }
+                segment => Segment::Literal(segment.to_string()),
+            })
+            .collect();
+        self.routes.push(Route {
+            method,
+            pattern,
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Passes a request to the handler of the first route matching its
+    /// method and path.
+    ///
+    /// The response of the request is turned into 4.04 (Not Found) if no
+    /// route matches its path, 4.05 (Method Not Allowed) if routes only
+    /// match its path but not its method, and into the error the handler
+    /// returns, if any (see [`CoapRequest::apply_from_error`]).
+    pub fn handle(&mut self, request: &mut CoapRequest<Endpoint>) {
+        let path = match request.get_path_as_vec() {
+            Ok(path) => path,
+            Err(e) => {
+                request.apply_from_error(HandlingError::bad_request(e));
+                return;
+            }
+        };
+        let method = *request.get_method();
+
+        let mut path_matched = false;
+        for route in &mut self.routes {
+            let params = match match_pattern(&route.pattern, &path) {
+                Some(params) => params,
+                None => continue,
+            };
+            if route.method != method {
+                path_matched = true;
+                continue;
+            }
+
+            if let Err(error) = (route.handler)(request, &params) {
+                request.apply_from_error(error);
+            }
+            return;
+        }
+
+        if path_matched {
+            request.apply_from_error(HandlingError::method_not_supported());
+        } else {
+            request.apply_from_error(HandlingError::not_found());
+        }
+    }
+}
+
+/// Returns the segments of a path or pattern, ignoring leading and trailing
+/// slashes.
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    let path = path.trim_matches('/');
+    path.split('/').filter(move |_| !path.is_empty())
+}
+
+/// Matches the segments of a path against a pattern, returning the values of
+/// its parameters if it matches.
+fn match_pattern(pattern: &[Segment], path: &[String]) -> Option<PathParams> {
+    let mut params = PathParams::default();
+    let mut remaining = path.iter();
+    for segment in pattern {
+        match segment {
+            Segment::Wildcard => {
+                let rest: Vec<&str> =
+                    remaining.by_ref().map(String::as_str).collect();
+                params.wildcard = Some(rest.join("/"));
+            }
+            Segment::Param(name) => {
+                params
+                    .params
+                    .push((name.clone(), remaining.next()?.clone()));
+            }
+            Segment::Literal(literal) => {
+                if remaining.next()? != literal {
+                    return None;
+                }
+            }
+        }
+    }
+    remaining.next().is_none().then_some(params)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{MessageClass, Packet, PacketBuilder, ResponseType};
+
+    fn request(method: RequestType, path: &str) -> CoapRequest<()> {
+        let packet = PacketBuilder::request(method).path(path).build();
+        CoapRequest::from_packet(packet.unwrap(), ())
+    }
+
+    fn respond(request: &mut CoapRequest<()>, payload: &str) {
+        if let Some(response) = &mut request.response {
+            response.message.payload = payload.as_bytes().to_vec();
+        }
+    }
+
+    fn response(request: &CoapRequest<()>) -> &Packet {
+        &request.response.as_ref().unwrap().message
+    }
+
+    fn router() -> Router<()> {
+        Router::new()
+            .route(RequestType::Get, "sensors/all/value", |request, _| {
+                respond(request, "all");
+                Ok(())
+            })
+            .route(
+                RequestType::Get,
+                "/sensors/{id}/value/",
+                |request, params| {
+                    respond(request, params.get("id").unwrap());
+                    Ok(())
+                },
+            )
+            .route(RequestType::Put, "fw/*", |request, params| {
+                respond(request, params.wildcard().unwrap());
+                Ok(())
+            })
+            .route(RequestType::Delete, "/", |_, _| {
+                Err(HandlingError::with_code(ResponseType::Forbidden, "No"))
+            })
+    }
+
+    #[test]
+    fn dispatches_requests() {
+        let mut router = router();
+        for (method, path, payload) in [
+            (RequestType::Get, "sensors/7/value", "7"),
+            (RequestType::Get, "/sensors/all/value", "all"),
+            (RequestType::Put, "fw/v2/image.bin", "v2/image.bin"),
+            (RequestType::Put, "fw", ""),
+        ] {
+            let mut request = request(method, path);
+            router.handle(&mut request);
+            assert_eq!(
+                response(&request).header.code,
+                MessageClass::Response(ResponseType::Content)
+            );
+            assert_eq!(response(&request).payload, payload.as_bytes());
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let mut router = router();
+        for (method, path, status) in [
+            (RequestType::Get, "sensors/7", ResponseType::NotFound),
+            (
+                RequestType::Get,
+                "sensors/7/value/x",
+                ResponseType::NotFound,
+            ),
+            (RequestType::Get, "firmware", ResponseType::NotFound),
+            (
+                RequestType::Post,
+                "sensors/7/value",
+                ResponseType::MethodNotAllowed,
+            ),
+            (
+                RequestType::Get,
+                "fw/image.bin",
+                ResponseType::MethodNotAllowed,
+            ),
+            (RequestType::Delete, "", ResponseType::Forbidden),
+        ] {
+            let mut request = request(method, path);
+            router.handle(&mut request);
+            assert_eq!(
+                response(&request).header.code,
+                MessageClass::Response(status),
+                "{:?} {}",
+                method,
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn patterns() {
+        let path: Vec<String> =
+            ["a", "b"].iter().map(|s| s.to_string()).collect();
+        let pattern = |pattern: &str| {
+            Router::<()>::new()
+                .route(RequestType::Get, pattern, |_, _| Ok(()))
+                .routes
+                .remove(0)
+                .pattern
+        };
+
+        assert_eq!(
+            pattern("{x}/*/{}/*"),
+            [
+                Segment::Param("x".to_string()),
+                Segment::Literal("*".to_string()),
+                Segment::Param("".to_string()),
+                Segment::Wildcard,
+            ]
+        );
+        let params = match_pattern(&pattern("{x}/*"), &path).unwrap();
+        assert_eq!(params.get("x"), Some("a"));
+        assert_eq!(params.get("y"), None);
+        assert_eq!(params.wildcard(), Some("b"));
+        assert_eq!(
+            match_pattern(&pattern("a/b"), &path).unwrap().wildcard(),
+            None
+        );
+        assert_eq!(match_pattern(&pattern("a"), &path), None);
+        assert_eq!(
+            match_pattern(&pattern(""), &[]),
+            Some(PathParams::default())
+        );
+    }
+}
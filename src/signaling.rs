@@ -0,0 +1,360 @@
+use alloc::{string::String, vec::Vec};
+
+use crate::{
+    error::IncompatibleOptionValueFormat,
+    header::{MessageClass, SignalingType},
+    option_value::{OptionValueString, OptionValueU16, OptionValueU32},
+    packet::{CoapOption, Packet},
+};
+
+/// The signal-specific options of RFC 8323.
+///
+/// Signaling messages have their own option number space for each signaling
+/// code, so the same number means something different depending on the code
+/// of the message it is used in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignalingOption {
+    /// Option 2 of a CSM message.
+    MaxMessageSize,
+    /// Option 4 of a CSM message.
+    BlockWiseTransfer,
+    /// Option 2 of a Ping or Pong message.
+    Custody,
+    /// Option 2 of a Release message.
+    AlternativeAddress,
+    /// Option 4 of a Release message.
+    HoldOff,
+    /// Option 2 of an Abort message.
+    BadCsmOption,
+    Unknown(SignalingType, u16),
+}
+
+impl SignalingOption {
+    /// Returns the option with the given number in messages with the given
+    /// signaling code.
+    pub fn from_number(signal: SignalingType, number: u16) -> SignalingOption {
+        match (signal, number) {
+            (SignalingType::Csm, 2) => SignalingOption::MaxMessageSize,
+            (SignalingType::Csm, 4) => SignalingOption::BlockWiseTransfer,
+            (SignalingType::Ping, 2) => SignalingOption::Custody,
+            (SignalingType::Pong, 2) => SignalingOption::Custody,
+            (SignalingType::Release, 2) => SignalingOption::AlternativeAddress,
+            (SignalingType::Release, 4) => SignalingOption::HoldOff,
+            (SignalingType::Abort, 2) => SignalingOption::BadCsmOption,
+            _ => SignalingOption::Unknown(signal, number),
+        }
+    }
+
+    /// Returns the option number.
+    pub fn number(&self) -> u16 {
+        match self {
+            SignalingOption::MaxMessageSize => 2,
+            SignalingOption::BlockWiseTransfer => 4,
+            SignalingOption::Custody => 2,
+            SignalingOption::AlternativeAddress => 2,
+            SignalingOption::HoldOff => 4,
+            SignalingOption::BadCsmOption => 2,
+            SignalingOption::Unknown(_, number) => *number,
+        }
+    }
+}
+
+impl From<SignalingOption> for CoapOption {
+    fn from(option: SignalingOption) -> CoapOption {
+        CoapOption::from(option.number())
+    }
+}
+
+/// A signaling message (7.xx) of a reliable transport (RFC 8323).
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignalingMessage {
+    pub message: Packet,
+}
+
+impl SignalingMessage {
+    /// Creates a new signaling message without any options.
+    pub fn new(signal: SignalingType) -> SignalingMessage {
+        let mut packet = Packet::new();
+        packet.header.code = MessageClass::Signaling(signal);
+        SignalingMessage { message: packet }
+    }
+
+    /// Creates a signaling message from a packet, if it has a signaling code.
+    pub fn from_packet(packet: Packet) -> Option<SignalingMessage> {
+        match packet.header.code {
+            MessageClass::Signaling(_) => {
+                Some(SignalingMessage { message: packet })
+            }
+            _ => None,
+        }
+    }
+
+    /// Creates a Capabilities and Settings Message (7.01).
+    pub fn csm(
+        max_message_size: Option<u32>,
+        block_wise_transfer: bool,
+    ) -> SignalingMessage {
+        let mut signal = Self::new(SignalingType::Csm);
+        if let Some(max_message_size) = max_message_size {
+            signal.add_option(
+                SignalingOption::MaxMessageSize,
+                OptionValueU32(max_message_size).into(),
+            );
+        }
+        if block_wise_transfer {
+            signal.add_option(SignalingOption::BlockWiseTransfer, Vec::new());
+        }
+        signal
+    }
+
+    /// Creates a Ping message (7.02), asking the peer to hold on to the
+    /// Pong until all outstanding messages have been handled if `custody`
+    /// is set.
+    pub fn ping(custody: bool) -> SignalingMessage {
+        let mut signal = Self::new(SignalingType::Ping);
+        if custody {
+            signal.add_option(SignalingOption::Custody, Vec::new());
+        }
+        signal
+    }
+
+    /// Creates a Pong message (7.03).
+    pub fn pong(custody: bool) -> SignalingMessage {
+        let mut signal = Self::new(SignalingType::Pong);
+        if custody {
+            signal.add_option(SignalingOption::Custody, Vec::new());
+        }
+        signal
+    }
+
+    /// Creates a Release message (7.04), optionally pointing the peer to
+    /// alternative addresses and asking it not to reconnect for `hold_off`
+    /// seconds.
+    pub fn release(
+        alternative_addresses: &[&str],
+        hold_off: Option<u32>,
+    ) -> SignalingMessage {
+        let mut signal = Self::new(SignalingType::Release);
+        for address in alternative_addresses {
+            signal.add_option(
+                SignalingOption::AlternativeAddress,
+                address.as_bytes().to_vec(),
+            );
+        }
+        if let Some(hold_off) = hold_off {
+            signal.add_option(
+                SignalingOption::HoldOff,
+                OptionValueU32(hold_off).into(),
+            );
+        }
+        signal
+    }
+
+    /// Creates an Abort message (7.05), optionally indicating the option of a
+    /// CSM that could not be processed.
+    pub fn abort(bad_csm_option: Option<u16>) -> SignalingMessage {
+        let mut signal = Self::new(SignalingType::Abort);
+        if let Some(bad_csm_option) = bad_csm_option {
+            signal.add_option(
+                SignalingOption::BadCsmOption,
+                OptionValueU16(bad_csm_option).into(),
+            );
+        }
+        signal
+    }
+
+    /// Creates the Pong answering this message if it is a Ping, carrying the
+    /// same token.
+    pub fn to_pong(&self) -> Option<SignalingMessage> {
+        if self.get_signal() != SignalingType::Ping {
+            return None;
+        }
+
+        let mut pong = Self::pong(self.has_custody());
+        pong.message.set_token(self.message.get_token().to_vec());
+        Some(pong)
+    }
+
+    /// Returns the signaling code.
+    pub fn get_signal(&self) -> SignalingType {
+        match self.message.header.code {
+            MessageClass::Signaling(signal) => signal,
+            _ => SignalingType::UnKnown,
+        }
+    }
+
+    /// Returns the options of the message, interpreted for its signaling
+    /// code.
+    pub fn options(
+        &self,
+    ) -> impl Iterator<Item = (SignalingOption, &[u8])> + '_ {
+        let signal = self.get_signal();
+        self.message.options().flat_map(move |(&number, values)| {
+            values.iter().map(move |value| {
+                (SignalingOption::from_number(signal, number), value)
+            })
+        })
+    }
+
+    /// Returns the Max-Message-Size the sender of a CSM can receive.
+    pub fn get_max_message_size(
+        &self,
+    ) -> Option<Result<u32, IncompatibleOptionValueFormat>> {
+        self.get_first_option_as::<OptionValueU32>(
+            SignalingOption::MaxMessageSize,
+        )
+        .map(|option| option.map(|value| value.0))
+    }
+
+    /// Returns whether the sender of a CSM supports block-wise transfers.
+    pub fn has_block_wise_transfer(&self) -> bool {
+        self.get_first_option(SignalingOption::BlockWiseTransfer)
+            .is_some()
+    }
+
+    /// Returns whether a Ping or Pong carries the Custody option.
+    pub fn has_custody(&self) -> bool {
+        self.get_first_option(SignalingOption::Custody).is_some()
+    }
+
+    /// Returns the alternative addresses of a Release.
+    pub fn get_alternative_addresses(
+        &self,
+    ) -> Result<Vec<String>, IncompatibleOptionValueFormat> {
+        self.options()
+            .filter(|(option, _)| {
+                *option == SignalingOption::AlternativeAddress
+            })
+            .map(|(_, value)| {
+                OptionValueString::try_from(value.to_vec()).map(|v| v.0)
+            })
+            .collect()
+    }
+
+    /// Returns the number of seconds the sender of a Release asks not to be
+    /// reconnected to.
+    pub fn get_hold_off(
+        &self,
+    ) -> Option<Result<u32, IncompatibleOptionValueFormat>> {
+        self.get_first_option_as::<OptionValueU32>(SignalingOption::HoldOff)
+            .map(|option| option.map(|value| value.0))
+    }
+
+    /// Returns the number of the CSM option that caused an Abort.
+    pub fn get_bad_csm_option(
+        &self,
+    ) -> Option<Result<u16, IncompatibleOptionValueFormat>> {
+        self.get_first_option_as::<OptionValueU16>(
+            SignalingOption::BadCsmOption,
+        )
+        .map(|option| option.map(|value| value.0))
+    }
+
+    fn add_option(&mut self, option: SignalingOption, value: Vec<u8>) {
+        self.message.add_option(option.into(), value);
+    }
+
+    fn get_first_option(&self, option: SignalingOption) -> Option<&[u8]> {
+        self.options()
+            .find(|(candidate, _)| *candidate == option)
+            .map(|(_, value)| value)
+    }
+
+    fn get_first_option_as<T: TryFrom<Vec<u8>>>(
+        &self,
+        option: SignalingOption,
+    ) -> Option<Result<T, T::Error>> {
+        self.get_first_option(option)
+            .map(|value| T::try_from(value.to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tcp;
+
+    #[test]
+    fn csm() {
+        let csm = SignalingMessage::csm(Some(8192), true);
+        let bytes = tcp::encode(&csm.message).unwrap();
+        assert_eq!(bytes, [0x40, 0xE1, 0x22, 0x20, 0x00, 0x20]);
+
+        let decoded =
+            SignalingMessage::from_packet(tcp::decode(&bytes).unwrap())
+                .unwrap();
+        assert_eq!(decoded.get_signal(), SignalingType::Csm);
+        assert_eq!(decoded.get_max_message_size(), Some(Ok(8192)));
+        assert!(decoded.has_block_wise_transfer());
+        assert!(!decoded.has_custody());
+
+        let csm = SignalingMessage::csm(None, false);
+        assert_eq!(csm.get_max_message_size(), None);
+        assert!(!csm.has_block_wise_transfer());
+    }
+
+    #[test]
+    fn ping_pong() {
+        let mut ping = SignalingMessage::ping(true);
+        ping.message.set_token(vec![0x42]);
+        assert!(ping.has_custody());
+
+        let pong = ping.to_pong().unwrap();
+        assert_eq!(pong.get_signal(), SignalingType::Pong);
+        assert_eq!(pong.message.get_token(), &[0x42]);
+        assert!(pong.has_custody());
+        assert!(pong.to_pong().is_none());
+
+        assert!(!SignalingMessage::ping(false)
+            .to_pong()
+            .unwrap()
+            .has_custody());
+    }
+
+    #[test]
+    fn release() {
+        let release = SignalingMessage::release(
+            &["coap+tcp://[2001:db8::1]", "coap+tcp://example.com"],
+            Some(30),
+        );
+        assert_eq!(
+            release.get_alternative_addresses(),
+            Ok(vec![
+                String::from("coap+tcp://[2001:db8::1]"),
+                String::from("coap+tcp://example.com")
+            ])
+        );
+        assert_eq!(release.get_hold_off(), Some(Ok(30)));
+        assert_eq!(release.get_max_message_size(), None);
+    }
+
+    #[test]
+    fn abort() {
+        let mut abort = SignalingMessage::abort(Some(4));
+        abort.message.payload = b"unsupported".to_vec();
+        let bytes = tcp::encode(&abort.message).unwrap();
+        let decoded =
+            SignalingMessage::from_packet(tcp::decode(&bytes).unwrap())
+                .unwrap();
+        assert_eq!(decoded.get_signal(), SignalingType::Abort);
+        assert_eq!(decoded.get_bad_csm_option(), Some(Ok(4)));
+        assert_eq!(decoded.message.payload, b"unsupported");
+    }
+
+    #[test]
+    fn options_depend_on_code() {
+        assert_eq!(
+            SignalingOption::from_number(SignalingType::Csm, 2),
+            SignalingOption::MaxMessageSize
+        );
+        assert_eq!(
+            SignalingOption::from_number(SignalingType::Abort, 2),
+            SignalingOption::BadCsmOption
+        );
+        assert_eq!(
+            SignalingOption::from_number(SignalingType::Abort, 4),
+            SignalingOption::Unknown(SignalingType::Abort, 4)
+        );
+        assert!(SignalingMessage::from_packet(Packet::new()).is_none());
+    }
+}
@@ -0,0 +1,359 @@
+use core::convert::TryFrom;
+
+use crate::{
+    error::{MessageError, Reaction, ValidationError, ValidationRule},
+    header::{Header, HeaderRaw, MessageClass, MessageType},
+    option_registry::OptionRegistry,
+    packet::{decode_token_length, CoapOption, Packet},
+    packet_ref::{OptionsRef, PacketRef},
+};
+
+/// A decoder that checks messages against the rules of RFC 7252 which
+/// [`Packet::from_bytes`] doesn't enforce, reporting which rule was broken, at
+/// what offset and how the message should be handled.
+///
+/// Every check can be turned off individually. Options are recognized and
+/// checked using the properties in the `registry`. Problems with elective
+/// options are never reported, since RFC 7252 requires them to be silently
+/// ignored rather than treated as errors.
+#[derive(Debug, Clone)]
+pub struct StrictDecoder {
+    /// Whether to check that the version is 1.
+    pub check_version: bool,
+    /// Whether to reject codes in the reserved classes 1, 6 and 7.
+    pub reject_reserved_codes: bool,
+    /// Whether to check that Empty messages consist of the header only.
+    pub check_empty_messages: bool,
+    /// Whether to reject a payload marker followed by an empty payload.
+    pub reject_empty_payload: bool,
+    /// Whether to reject critical options that occur more often than they
+    /// may.
+    pub reject_repeated_options: bool,
+    /// Whether to reject unrecognized critical options.
+    pub reject_unrecognized_options: bool,
+    /// Whether to reject critical options with a value that doesn't match
+    /// their format or length bounds.
+    pub check_option_values: bool,
+    /// The options that are recognized.
+    pub registry: OptionRegistry,
+}
+
+impl Default for StrictDecoder {
+    fn default() -> Self {
+        Self {
+            check_version: true,
+            reject_reserved_codes: true,
+            check_empty_messages: true,
+            reject_empty_payload: true,
+            reject_repeated_options: true,
+            reject_unrecognized_options: true,
+            check_option_values: true,
+            registry: OptionRegistry::new(),
+        }
+    }
+}
+
+impl StrictDecoder {
+    /// Creates a new decoder with all checks enabled.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Decodes and validates a byte slice into a view of the packet.
+    pub fn decode_ref<'a>(
+        &self,
+        buf: &'a [u8],
+    ) -> Result<PacketRef<'a>, ValidationError> {
+        let raw_header =
+            HeaderRaw::try_from(buf).map_err(|_| ValidationError {
+                rule: ValidationRule::Malformed(MessageError::InvalidHeader),
+                offset: 0,
+                reaction: Reaction::Ignore,
+            })?;
+        let header = Header::from_raw(&raw_header);
+        let message_type = header.get_type();
+        let is_request = matches!(header.code, MessageClass::Request(_));
+        let error = |rule, offset| ValidationError {
+            rule,
+            offset,
+            reaction: reaction(rule, message_type, is_request),
+        };
+
+        if self.check_version && header.get_version() != 1 {
+            return Err(error(ValidationRule::UnknownVersion, 0));
+        }
+        if self.reject_reserved_codes && matches!(buf[1] >> 5, 1 | 6 | 7) {
+            return Err(error(ValidationRule::ReservedCode, 1));
+        }
+
+        let invalid_token_length = || {
+            error(
+                ValidationRule::Malformed(MessageError::InvalidTokenLength),
+                0,
+            )
+        };
+        let (token_length, extended_length) =
+            decode_token_length(header.get_token_length(), &buf[4..])
+                .ok()
+                .flatten()
+                .ok_or_else(invalid_token_length)?;
+        let token_start = 4 + extended_length;
+        let options_start = token_start + token_length;
+        if options_start > buf.len() {
+            return Err(invalid_token_length());
+        }
+
+        if self.check_empty_messages
+            && header.code == MessageClass::Empty
+            && buf.len() > 4
+        {
+            return Err(error(ValidationRule::NonEmptyEmptyMessage, 4));
+        }
+
+        let body = &buf[options_start..];
+        let mut options = OptionsRef::new(body);
+        let mut previous = None;
+        loop {
+            let offset = options_start + options.position();
+            let (number, value) = match options.next_option() {
+                Ok(Some(option)) => option,
+                Ok(None) => break,
+                Err(e) => {
+                    return Err(error(ValidationRule::Malformed(e), offset))
+                }
+            };
+
+            let option = CoapOption::from(number);
+            let is_critical = option.is_critical();
+            if is_critical
+                && self.reject_unrecognized_options
+                && !self.registry.is_recognized(option)
+            {
+                return Err(error(
+                    ValidationRule::UnrecognizedCriticalOption(number),
+                    offset,
+                ));
+            }
+            if is_critical
+                && self.reject_repeated_options
+                && previous == Some(number)
+                && !self.registry.is_repeatable(option)
+            {
+                return Err(error(
+                    ValidationRule::RepeatedOption(number),
+                    offset,
+                ));
+            }
+            if is_critical
+                && self.check_option_values
+                && self.registry.get(option).is_some_and(|properties| {
+                    !properties.is_valid_value(value)
+                })
+            {
+                return Err(error(
+                    ValidationRule::InvalidOptionValue(number),
+                    offset,
+                ));
+            }
+            previous = Some(number);
+        }
+
+        let marker = options_start + options.position();
+        if self.reject_empty_payload && marker + 1 == buf.len() {
+            return Err(error(ValidationRule::EmptyPayload, marker));
+        }
+
+        PacketRef::from_parts(header, &buf[token_start..options_start], body)
+            .map_err(|e| error(ValidationRule::Malformed(e), options_start))
+    }
+
+    /// Decodes and validates a byte slice and constructs the equivalent
+    /// packet.
+    pub fn decode(&self, buf: &[u8]) -> Result<Packet, ValidationError> {
+        self.decode_ref(buf).map(Packet::from)
+    }
+}
+
+/// Returns how a message breaking `rule` should be handled according to
+/// RFC 7252.
+fn reaction(
+    rule: ValidationRule,
+    message_type: MessageType,
+    is_request: bool,
+) -> Reaction {
+    match rule {
+        ValidationRule::UnknownVersion => Reaction::Ignore,
+        _ if matches!(
+            message_type,
+            MessageType::Acknowledgement | MessageType::Reset
+        ) =>
+        {
+            Reaction::Ignore
+        }
+        ValidationRule::RepeatedOption(_)
+        | ValidationRule::UnrecognizedCriticalOption(_)
+        | ValidationRule::InvalidOptionValue(_)
+            if message_type == MessageType::Confirmable && is_request =>
+        {
+            Reaction::BadOption
+        }
+        _ => Reaction::Reject,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::option_registry::{OptionFormat, OptionProperties};
+
+    fn strict_error(buf: &[u8]) -> (ValidationRule, usize, Reaction) {
+        let error = StrictDecoder::new().decode_ref(buf).unwrap_err();
+        (error.rule, error.offset, error.reaction)
+    }
+
+    #[test]
+    fn accepts_valid_messages() {
+        let mut packet = Packet::new();
+        packet.set_token(vec![1, 2]);
+        packet.add_option(CoapOption::UriPath, b"a".to_vec());
+        packet.add_option(CoapOption::UriPath, b"b".to_vec());
+        packet.add_option(CoapOption::Unknown(2000), b"elective".to_vec());
+        packet.payload = b"payload".to_vec();
+        let bytes = packet.to_bytes().unwrap();
+
+        assert_eq!(StrictDecoder::new().decode(&bytes), Ok(packet));
+        assert!(StrictDecoder::new()
+            .decode(&[0x60, 0x00, 0x12, 0x34])
+            .is_ok());
+    }
+
+    #[test]
+    fn header_rules() {
+        assert_eq!(
+            strict_error(&[0x40, 0x01]),
+            (
+                ValidationRule::Malformed(MessageError::InvalidHeader),
+                0,
+                Reaction::Ignore
+            )
+        );
+        assert_eq!(
+            strict_error(&[0x80, 0x01, 0x00, 0x00]),
+            (ValidationRule::UnknownVersion, 0, Reaction::Ignore)
+        );
+        assert_eq!(
+            strict_error(&[0x40, 0x21, 0x00, 0x00]),
+            (ValidationRule::ReservedCode, 1, Reaction::Reject)
+        );
+        assert_eq!(
+            strict_error(&[0x40, 0xE1, 0x00, 0x00]),
+            (ValidationRule::ReservedCode, 1, Reaction::Reject)
+        );
+        assert_eq!(
+            strict_error(&[0x42, 0x01, 0x00, 0x00, 0x01]),
+            (
+                ValidationRule::Malformed(MessageError::InvalidTokenLength),
+                0,
+                Reaction::Reject
+            )
+        );
+        assert_eq!(
+            strict_error(&[0x40, 0x00, 0x00, 0x00, 0xFF, 0x01]),
+            (ValidationRule::NonEmptyEmptyMessage, 4, Reaction::Reject)
+        );
+    }
+
+    #[test]
+    fn option_and_payload_rules() {
+        // Uri-Host twice
+        let repeated =
+            [0x40, 0x01, 0x00, 0x00, 0x31, 0x61, 0x01, 0x62, 0xB1, 0x63];
+        assert_eq!(
+            strict_error(&repeated),
+            (ValidationRule::RepeatedOption(3), 6, Reaction::BadOption)
+        );
+
+        // Critical option 2001 in a non-confirmable request
+        let unrecognized = [0x50, 0x01, 0x00, 0x00, 0xE0, 0x06, 0xC4];
+        assert_eq!(
+            strict_error(&unrecognized),
+            (
+                ValidationRule::UnrecognizedCriticalOption(2001),
+                4,
+                Reaction::Reject
+            )
+        );
+
+        // The same option piggybacked in an acknowledgement
+        let unrecognized = [0x60, 0x45, 0x00, 0x00, 0xE0, 0x06, 0xC4];
+        assert_eq!(
+            strict_error(&unrecognized),
+            (
+                ValidationRule::UnrecognizedCriticalOption(2001),
+                4,
+                Reaction::Ignore
+            )
+        );
+
+        let empty_payload = [0x40, 0x01, 0x00, 0x00, 0xB1, 0x61, 0xFF];
+        assert_eq!(
+            strict_error(&empty_payload),
+            (ValidationRule::EmptyPayload, 6, Reaction::Reject)
+        );
+
+        let bad_option = [0x40, 0x01, 0x00, 0x00, 0xB1, 0x61, 0x13, 0x00];
+        assert_eq!(
+            strict_error(&bad_option),
+            (
+                ValidationRule::Malformed(MessageError::InvalidOptionLength),
+                6,
+                Reaction::Reject
+            )
+        );
+    }
+
+    #[test]
+    fn uses_registry() {
+        // Uri-Host with an empty value
+        let invalid = [0x40, 0x01, 0x00, 0x00, 0x30];
+        assert_eq!(
+            strict_error(&invalid),
+            (
+                ValidationRule::InvalidOptionValue(3),
+                4,
+                Reaction::BadOption
+            )
+        );
+
+        let custom = [0x40, 0x01, 0x00, 0x00, 0xE0, 0x06, 0xC4];
+        let mut decoder = StrictDecoder::new();
+        decoder.registry.register(
+            CoapOption::Unknown(2001),
+            OptionProperties::new("Custom", OptionFormat::Empty, false, 0, 0),
+        );
+        assert!(decoder.decode_ref(&custom).is_ok());
+    }
+
+    #[test]
+    fn checks_can_be_disabled() {
+        let decoder = StrictDecoder {
+            check_version: false,
+            reject_reserved_codes: false,
+            check_empty_messages: false,
+            reject_empty_payload: false,
+            reject_repeated_options: false,
+            reject_unrecognized_options: false,
+            check_option_values: false,
+            registry: OptionRegistry::empty(),
+        };
+
+        assert!(decoder.decode_ref(&[0x80, 0x01, 0x00, 0x00]).is_ok());
+        assert!(decoder.decode_ref(&[0x40, 0x21, 0x00, 0x00]).is_ok());
+        assert!(decoder
+            .decode_ref(&[0x40, 0x01, 0x00, 0x00, 0xB1, 0x61, 0xFF])
+            .is_ok());
+        assert!(decoder
+            .decode_ref(&[0x40, 0x01, 0x00, 0x00, 0xE0, 0x06, 0xC4])
+            .is_ok());
+    }
+}
@@ -0,0 +1,457 @@
+//! Message framing for CoAP over reliable transports such as TCP and TLS
+//! ([RFC 8323]).
+//!
+//! Reliable transports don't need the message type and message ID of the UDP
+//! header, so a message starts with a variable length header made up of the
+//! length of the options and payload, the token length and the code, followed
+//! by the token, options and payload encoded exactly like they are for UDP.
+//! The message type and message ID of the [`Packet`] header are ignored when
+//! encoding and left at their defaults when decoding.
+//!
+//! Because TCP delivers a byte stream rather than datagrams, [`FrameDecoder`]
+//! can be used to collect incoming data and pull out complete messages as
+//! they become available.
+//!
+//! [RFC 8323]: https://tools.ietf.org/html/rfc8323
+
+use alloc::vec::Vec;
+
+use crate::{
+    error::MessageError,
+    header::Header,
+    packet::{
+        decode_token_length, extended_token_length, token_length_field, Sink,
+        SliceSink,
+    },
+    Packet, PacketRef,
+};
+
+/// Default maximum message size a peer is assumed to accept until it
+/// indicates otherwise, taken from RFC 8323.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1152;
+
+/// The maximum length of the frame header: one byte for the length and token
+/// length, up to four bytes of extended length, one byte for the code and up
+/// to two bytes of extended token length.
+const MAX_FRAME_HEADER_LENGTH: usize = 8;
+
+/// The decoded header of a frame.
+struct FrameHeader {
+    code: u8,
+    token_start: usize,
+    token_length: usize,
+    body_length: usize,
+}
+
+impl FrameHeader {
+    /// Parses the header at the start of `buf`, returning `None` if more
+    /// bytes are required to do so.
+    fn parse(buf: &[u8]) -> Result<Option<FrameHeader>, MessageError> {
+        let first = match buf.first() {
+            Some(&first) => first,
+            None => return Ok(None),
+        };
+        let length = (first >> 4) as usize;
+
+        let extended_length = match length {
+            13 => 1,
+            14 => 2,
+            15 => 4,
+            _ => 0,
+        };
+        let code_idx = 1 + extended_length;
+        if buf.len() <= code_idx {
+            return Ok(None);
+        }
+        let (token_length, extended_token_length) =
+            match decode_token_length(first & 0xF, &buf[code_idx + 1..])? {
+                Some(token_length) => token_length,
+                None => return Ok(None),
+            };
+
+        let extended = &buf[1..1 + extended_length];
+        let body_length = match length {
+            13 => extended[0] as usize + 13,
+            14 => {
+                u16::from_be_bytes([extended[0], extended[1]]) as usize + 269
+            }
+            15 => usize::try_from(u32::from_be_bytes([
+                extended[0],
+                extended[1],
+                extended[2],
+                extended[3],
+            ]))
+            .ok()
+            .and_then(|length| length.checked_add(65805))
+            .ok_or(MessageError::InvalidPacketLength)?,
+            _ => length,
+        };
+
+        Ok(Some(FrameHeader {
+            code: buf[code_idx],
+            token_start: code_idx + 1 + extended_token_length,
+            token_length,
+            body_length,
+        }))
+    }
+
+    /// Returns the length of the whole frame.
+    fn frame_length(&self) -> Result<usize, MessageError> {
+        (self.token_start + self.token_length)
+            .checked_add(self.body_length)
+            .ok_or(MessageError::InvalidPacketLength)
+    }
+}
+
+/// Encodes the header of a frame into `header`, returning the number of bytes
+/// used.
+fn encode_frame_header(
+    packet: &Packet,
+    header: &mut [u8; MAX_FRAME_HEADER_LENGTH],
+) -> Result<usize, MessageError> {
+    let token_length = packet.get_token().len();
+    let (extended_token, extended_token_length) =
+        extended_token_length(token_length)?;
+
+    let body_length = packet.options_encoded_len() + packet.payload_len();
+    let mut idx = 1;
+    let length = if body_length < 13 {
+        body_length as u8
+    } else if body_length < 269 {
+        header[idx] = (body_length - 13) as u8;
+        idx += 1;
+        13
+    } else if body_length < 65805 {
+        let fix = (body_length - 269) as u16;
+        header[idx..idx + 2].copy_from_slice(&fix.to_be_bytes());
+        idx += 2;
+        14
+    } else {
+        let fix = u32::try_from(body_length - 65805)
+            .map_err(|_| MessageError::InvalidPacketLength)?;
+        header[idx..idx + 4].copy_from_slice(&fix.to_be_bytes());
+        idx += 4;
+        15
+    };
+    header[0] = length << 4 | token_length_field(token_length);
+    header[idx] = packet.header.code.into();
+    idx += 1;
+    header[idx..idx + extended_token_length]
+        .copy_from_slice(&extended_token[..extended_token_length]);
+
+    Ok(idx + extended_token_length)
+}
+
+fn encode_frame<S: Sink>(
+    packet: &Packet,
+    sink: &mut S,
+) -> Result<(), S::Error> {
+    let mut header = [0u8; MAX_FRAME_HEADER_LENGTH];
+    let header_length = encode_frame_header(packet, &mut header)?;
+    sink.put(&header[..header_length])?;
+    sink.put(packet.get_token())?;
+    packet.encode_options_and_payload(sink)
+}
+
+/// Returns the number of bytes the packet occupies when framed for a reliable
+/// transport.
+pub fn encoded_len(packet: &Packet) -> usize {
+    let body_length = packet.options_encoded_len() + packet.payload_len();
+    let extended_length = if body_length < 13 {
+        0
+    } else if body_length < 269 {
+        1
+    } else if body_length < 65805 {
+        2
+    } else {
+        4
+    };
+
+    let token_length = packet.get_token().len();
+    let extended_token_length =
+        extended_token_length(token_length).map_or(0, |(_, length)| length);
+
+    1 + extended_length
+        + 1
+        + extended_token_length
+        + token_length
+        + body_length
+}
+
+/// Returns a vector of bytes representing the packet framed for a reliable
+/// transport.
+pub fn encode(packet: &Packet) -> Result<Vec<u8>, MessageError> {
+    let mut buf = Vec::with_capacity(encoded_len(packet));
+    encode_frame(packet, &mut buf)?;
+    Ok(buf)
+}
+
+/// Encodes the packet framed for a reliable transport into `buf`, returning
+/// the number of bytes written.
+///
+/// If `buf` is too small to hold the frame, [`MessageError::BufferTooSmall`]
+/// is returned and `buf` is left untouched.
+pub fn encode_into(
+    packet: &Packet,
+    buf: &mut [u8],
+) -> Result<usize, MessageError> {
+    if encoded_len(packet) > buf.len() {
+        return Err(MessageError::BufferTooSmall);
+    }
+
+    let mut sink = SliceSink { buf, len: 0 };
+    encode_frame(packet, &mut sink)?;
+    Ok(sink.len)
+}
+
+/// Returns the length of the frame at the start of `buf`, or `None` if `buf`
+/// is too short to tell yet.
+///
+/// The frame is only complete once `buf` holds at least that many bytes.
+pub fn frame_len(buf: &[u8]) -> Result<Option<usize>, MessageError> {
+    match FrameHeader::parse(buf)? {
+        Some(header) => header.frame_length().map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Decodes a byte slice holding exactly one frame into a view of the packet,
+/// without copying any of its contents.
+pub fn decode_ref(buf: &[u8]) -> Result<PacketRef<'_>, MessageError> {
+    let frame_header =
+        FrameHeader::parse(buf)?.ok_or(MessageError::InvalidHeader)?;
+    if frame_header.frame_length()? != buf.len() {
+        return Err(MessageError::InvalidPacketLength);
+    }
+
+    let mut header = Header::new();
+    header.code = frame_header.code.into();
+    header.set_token_length(token_length_field(frame_header.token_length));
+
+    let body_start = frame_header.token_start + frame_header.token_length;
+    PacketRef::from_parts(
+        header,
+        &buf[frame_header.token_start..body_start],
+        &buf[body_start..],
+    )
+}
+
+/// Decodes a byte slice holding exactly one frame and constructs the
+/// equivalent packet.
+pub fn decode(buf: &[u8]) -> Result<Packet, MessageError> {
+    decode_ref(buf).map(Packet::from)
+}
+
+/// Incrementally decodes packets from a byte stream that arrives in arbitrary
+/// chunks.
+///
+/// Framing can't be recovered once the stream contains invalid data, so after
+/// [`FrameDecoder::next_packet`] returns an error the connection should be
+/// aborted.
+#[derive(Debug, Clone)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+    max_message_size: usize,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::with_max_message_size(DEFAULT_MAX_MESSAGE_SIZE)
+    }
+}
+
+impl FrameDecoder {
+    /// Creates a new decoder accepting messages of up to
+    /// [`DEFAULT_MAX_MESSAGE_SIZE`] bytes.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Creates a new decoder accepting messages of up to `max_message_size`
+    /// bytes.
+    pub fn with_max_message_size(max_message_size: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            max_message_size,
+        }
+    }
+
+    /// Sets the size of the largest message that will be accepted.
+    pub fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.max_message_size = max_message_size;
+    }
+
+    /// Returns the size of the largest message that will be accepted.
+    pub fn get_max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    /// Appends data received from the stream.
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Returns the number of bytes received but not yet decoded.
+    pub fn buffered_len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Decodes the next packet if it has been received in its entirety.
+    pub fn next_packet(&mut self) -> Result<Option<Packet>, MessageError> {
+        let frame_length = match frame_len(&self.buf)? {
+            Some(frame_length) => frame_length,
+            None => return Ok(None),
+        };
+        if frame_length > self.max_message_size {
+            return Err(MessageError::InvalidPacketLength);
+        }
+        if self.buf.len() < frame_length {
+            return Ok(None);
+        }
+
+        let packet = decode(&self.buf[..frame_length])?;
+        self.buf.drain(..frame_length);
+        Ok(Some(packet))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{CoapOption, MessageClass, RequestType, ResponseType};
+
+    #[test]
+    fn encode_decode() {
+        let mut packet = Packet::new();
+        packet.header.code = MessageClass::Request(RequestType::Get);
+        packet.set_token(vec![0x01]);
+        packet.add_option(CoapOption::UriPath, b"a".to_vec());
+
+        let bytes = encode(&packet).unwrap();
+        assert_eq!(bytes, [0x21, 0x01, 0x01, 0xB1, 0x61]);
+        assert_eq!(encoded_len(&packet), bytes.len());
+        assert_eq!(frame_len(&bytes), Ok(Some(bytes.len())));
+
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(decoded, packet);
+    }
+
+    #[test]
+    fn extended_lengths() {
+        for payload_length in [11, 12, 267, 268, 65803, 65804, 70000] {
+            let mut packet = Packet::new();
+            packet.header.code = MessageClass::Response(ResponseType::Content);
+            packet.set_token(vec![1, 2, 3, 4]);
+            packet.payload = vec![0x42; payload_length];
+
+            let bytes = encode(&packet).unwrap();
+            assert_eq!(encoded_len(&packet), bytes.len());
+
+            let mut buf = vec![0u8; bytes.len()];
+            assert_eq!(encode_into(&packet, &mut buf), Ok(bytes.len()));
+            assert_eq!(buf, bytes);
+
+            let decoded = decode_ref(&bytes).unwrap();
+            assert_eq!(decoded.get_token(), &[1, 2, 3, 4]);
+            assert_eq!(decoded.payload, &packet.payload[..]);
+        }
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert_eq!(decode(&[]), Err(MessageError::InvalidHeader));
+        assert_eq!(
+            decode(&[0x09, 0x01]),
+            Err(MessageError::InvalidTokenLength)
+        );
+        assert_eq!(
+            decode(&[0x20, 0x01, 0xB1]),
+            Err(MessageError::InvalidPacketLength)
+        );
+        assert_eq!(
+            decode(&[0x20, 0x01, 0xF0, 0x00]),
+            Err(MessageError::InvalidOptionDelta)
+        );
+        assert_eq!(frame_len(&[0xD0]), Ok(None));
+        assert_eq!(frame_len(&[0xD0, 0x00, 0x01]), Ok(Some(16)));
+    }
+
+    #[test]
+    fn encode_into_buffer_too_small() {
+        let mut packet = Packet::new();
+        packet.payload = b"Hello".to_vec();
+        let mut buf = [0u8; 4];
+        assert_eq!(
+            encode_into(&packet, &mut buf),
+            Err(MessageError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn frame_decoder_chunks() {
+        let mut first = Packet::new();
+        first.set_token(vec![0xAA]);
+        first.add_option(CoapOption::UriPath, b"sensors".to_vec());
+        let mut second = Packet::new();
+        second.header.code = MessageClass::Response(ResponseType::Content);
+        second.payload = vec![0x55; 300];
+
+        let mut stream = encode(&first).unwrap();
+        stream.extend(encode(&second).unwrap());
+
+        let mut decoder = FrameDecoder::new();
+        let mut decoded = Vec::new();
+        for byte in stream.iter() {
+            decoder.push(&[*byte]);
+            while let Some(packet) = decoder.next_packet().unwrap() {
+                decoded.push(packet);
+            }
+        }
+        assert_eq!(decoded, [first.clone(), second.clone()]);
+        assert_eq!(decoder.buffered_len(), 0);
+
+        decoder.push(&stream);
+        assert_eq!(decoder.next_packet(), Ok(Some(first)));
+        assert_eq!(decoder.next_packet(), Ok(Some(second)));
+        assert_eq!(decoder.next_packet(), Ok(None));
+    }
+
+    #[cfg(feature = "extended-tokens")]
+    #[test]
+    fn extended_tokens() {
+        let mut packet = Packet::new();
+        packet.header.code = MessageClass::Request(RequestType::Get);
+        packet.set_token(vec![0xAB; 13]);
+        let bytes = encode(&packet).unwrap();
+        assert_eq!(bytes[..4], [0x0D, 0x01, 0x00, 0xAB]);
+
+        for token_length in [13, 300] {
+            let mut packet = Packet::new();
+            packet.set_token(vec![0xAB; token_length]);
+            packet.payload = vec![0x55; 20];
+            let bytes = encode(&packet).unwrap();
+            assert_eq!(encoded_len(&packet), bytes.len());
+            assert_eq!(decode(&bytes).unwrap(), packet);
+
+            let mut decoder = FrameDecoder::with_max_message_size(512);
+            for byte in bytes.iter() {
+                assert_eq!(decoder.next_packet(), Ok(None));
+                decoder.push(&[*byte]);
+            }
+            assert_eq!(decoder.next_packet(), Ok(Some(packet)));
+        }
+    }
+
+    #[test]
+    fn frame_decoder_max_message_size() {
+        let mut packet = Packet::new();
+        packet.payload = vec![0; 100];
+
+        let mut decoder = FrameDecoder::with_max_message_size(64);
+        decoder.push(&encode(&packet).unwrap()[..3]);
+        assert_eq!(
+            decoder.next_packet(),
+            Err(MessageError::InvalidPacketLength)
+        );
+    }
+}
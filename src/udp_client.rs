@@ -0,0 +1,690 @@
+//! A blocking client for CoAP over UDP.
+//!
+//! [`UdpClient`] combines [`crate::ReliabilityLayer`],
+//! [`crate::ClientExchanges`], [`crate::MessageIdAllocator`] and
+//! [`crate::TokenGenerator`] on top of [`std::net::UdpSocket`], and
+//! transparently performs block-wise transfers (RFC 7959) for payloads that
+//! don't fit into a single message.
+
+use std::{
+    io::ErrorKind,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use rand_core::{OsRng, RngCore};
+
+use crate::{
+    block_handler::BlockValue, error::ClientError, generator::TOKEN_LENGTH,
+    ClientExchanges, CoapOption, CoapResponse, CoapUri, ExchangeMatch,
+    MessageClass, MessageIdAllocator, MessageType, Packet, PacketBuilder,
+    ReliabilityEvent, ReliabilityLayer, RequestType, ResponseType,
+    TokenGenerator, TransmissionParameters, UriScheme,
+};
+
+/// Default size of the blocks request payloads are split into.
+const DEFAULT_BLOCK_SIZE: usize = 1024;
+
+/// Size of the buffer for incoming datagrams, which is shared with the async
+/// client and server.
+pub(crate) const RECEIVE_BUFFER_SIZE: usize = 2048;
+
+/// The configuration for [`UdpClient`].
+pub struct UdpClientConfig {
+    /// Length of time to wait for the response to each message exchanged
+    /// with the server, including retransmissions and, for separate
+    /// responses, the time after the request was acknowledged.
+    pub timeout: Duration,
+
+    /// The parameters for retransmitting Confirmable requests.
+    pub transmission: TransmissionParameters,
+
+    /// Size of the blocks request payloads are split into with Block1 if
+    /// they are larger.  Must be a power of two from 16 to 1024.  The
+    /// server may ask for smaller blocks, and chooses the size of the blocks
+    /// of responses.
+    pub block_size: usize,
+}
+
+impl Default for UdpClientConfig {
+    fn default() -> Self {
+        let transmission = TransmissionParameters::default();
+        Self {
+            timeout: transmission.max_transmit_wait(),
+            transmission,
+            block_size: DEFAULT_BLOCK_SIZE,
+        }
+    }
+}
+
+/// A blocking CoAP client that sends Confirmable requests over UDP.
+///
+/// Requests are retransmitted until they are acknowledged, responses may be
+/// piggybacked or separate, and large payloads are transferred block-wise in
+/// both directions. Requests to multicast groups are sent with
+/// [`UdpClient::multicast`] instead. The returned response carries the
+/// complete payload, and its code has to be checked by the caller, since
+/// error responses are returned like any other.
+pub struct UdpClient {
+    socket: UdpSocket,
+    config: UdpClientConfig,
+    reliability: ReliabilityLayer<SocketAddr>,
+    exchanges: ClientExchanges<SocketAddr>,
+    message_ids: MessageIdAllocator<SocketAddr, OsRng>,
+    tokens: TokenGenerator<OsRng>,
+    /// The point in time all durations passed to the components refer to.
+    start: Instant,
+}
+
+impl UdpClient {
+    /// Creates a client sending from a socket bound to the given local
+    /// address, e.g. `0.0.0.0:0` for an arbitrary port.
+    pub fn bind<A: ToSocketAddrs>(
+        address: A,
+        config: UdpClientConfig,
+    ) -> Result<Self, ClientError> {
+        Ok(Self::from_socket(UdpSocket::bind(address)?, config))
+    }
+
+    /// Creates a client sending from an existing socket.
+    pub fn from_socket(socket: UdpSocket, config: UdpClientConfig) -> Self {
+        let exchange_lifetime = config.transmission.exchange_lifetime();
+        Self {
+            socket,
+            reliability: ReliabilityLayer::new(
+                config.transmission,
+                OsRng.next_u32(),
+            ),
+            exchanges: ClientExchanges::new(exchange_lifetime),
+            message_ids: MessageIdAllocator::new(OsRng, exchange_lifetime),
+            tokens: TokenGenerator::random(OsRng, TOKEN_LENGTH),
+            config,
+            start: Instant::now(),
+        }
+    }
+
+    /// Returns the socket of the client.
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Sends a GET request and returns the response.
+    pub fn get(&mut self, uri: &str) -> Result<Packet, ClientError> {
+        self.request(RequestType::Get, uri, &[])
+    }
+
+    /// Sends a PUT request with a payload and returns the response.
+    pub fn put(
+        &mut self,
+        uri: &str,
+        payload: &[u8],
+    ) -> Result<Packet, ClientError> {
+        self.request(RequestType::Put, uri, payload)
+    }
+
+    /// Sends a POST request with a payload and returns the response.
+    pub fn post(
+        &mut self,
+        uri: &str,
+        payload: &[u8],
+    ) -> Result<Packet, ClientError> {
+        self.request(RequestType::Post, uri, payload)
+    }
+
+    /// Sends a DELETE request and returns the response.
+    pub fn delete(&mut self, uri: &str) -> Result<Packet, ClientError> {
+        self.request(RequestType::Delete, uri, &[])
+    }
+
+    /// Sends a FETCH request with a payload and returns the response.
+    pub fn fetch(
+        &mut self,
+        uri: &str,
+        payload: &[u8],
+    ) -> Result<Packet, ClientError> {
+        self.request(RequestType::Fetch, uri, payload)
+    }
+
+    /// Sends a request with a `coap` URI and a payload, and returns the
+    /// response.
+    pub fn request(
+        &mut self,
+        method: RequestType,
+        uri: &str,
+        payload: &[u8],
+    ) -> Result<Packet, ClientError> {
+        let destination = resolve(uri)?;
+        let request = PacketBuilder::request(method).uri(uri).build()?;
+        let mut transfer =
+            BlockwiseTransfer::new(request, payload, self.config.block_size);
+        let mut message = transfer.first_message()?;
+        loop {
+            let response = self.exchange(destination, message)?;
+            match transfer.handle_response(response)? {
+                BlockwiseStep::Send(next) => message = next,
+                BlockwiseStep::Done(response) => return Ok(response),
+            }
+        }
+    }
+
+    /// Sends a Non-confirmable request to a multicast group, e.g.
+    /// `coap://[ff02::fd]/.well-known/core`, and collects the responses of
+    /// all servers that arrive within `wait`, along with their source.
+    ///
+    /// Servers delay their responses to multicast requests by a random time
+    /// within their Leisure period (see
+    /// [`TransmissionParameters::default_leisure`]), so `wait` should be
+    /// at least that long. Payloads aren't transferred block-wise, so they
+    /// have to fit into a single message.
+    pub fn multicast(
+        &mut self,
+        method: RequestType,
+        uri: &str,
+        payload: &[u8],
+        wait: Duration,
+    ) -> Result<Vec<(SocketAddr, Packet)>, ClientError> {
+        let destination = resolve(uri)?;
+        let now = self.start.elapsed();
+        let mut request = PacketBuilder::request(method)
+            .uri(uri)
+            .message_type(MessageType::NonConfirmable)
+            .payload(payload)
+            .build()?;
+        request.header.message_id = self
+            .message_ids
+            .allocate(&destination, now)
+            .ok_or(ClientError::TimedOut)?;
+        request.set_token(self.tokens.next_token());
+
+        let token = request.get_token().to_vec();
+        self.socket.send_to(&request.to_bytes()?, destination)?;
+        self.exchanges.register_multicast(request);
+
+        let result = self.collect_group_responses(now + wait);
+        self.exchanges.cancel_multicast(&token);
+        result
+    }
+
+    /// Sends a single message with a fresh message ID and token, and waits
+    /// for its response.
+    fn exchange(
+        &mut self,
+        destination: SocketAddr,
+        mut request: Packet,
+    ) -> Result<Packet, ClientError> {
+        let now = self.start.elapsed();
+        request.header.set_type(MessageType::Confirmable);
+        request.header.message_id = self
+            .message_ids
+            .allocate(&destination, now)
+            .ok_or(ClientError::TimedOut)?;
+        request.set_token(self.tokens.next_token());
+        request.to_bytes()?;
+
+        let message_id = request.header.message_id;
+        let token = request.get_token().to_vec();
+        self.exchanges.register(destination, request.clone());
+        self.reliability.send(destination, request, now);
+
+        let result = self.await_response(now + self.config.timeout);
+        self.reliability.cancel(&destination, message_id);
+        self.exchanges.cancel(&destination, &token);
+        result
+    }
+
+    /// Transmits queued messages and processes incoming ones until the
+    /// response of the outstanding request arrives.
+    fn await_response(
+        &mut self,
+        deadline: Duration,
+    ) -> Result<Packet, ClientError> {
+        loop {
+            let now = self.start.elapsed();
+            while let Some(event) = self.reliability.poll(now) {
+                match event {
+                    ReliabilityEvent::Transmit(endpoint, packet) => {
+                        self.socket.send_to(&packet.to_bytes()?, endpoint)?;
+                    }
+                    ReliabilityEvent::TimedOut(_, _) => {
+                        return Err(ClientError::TimedOut);
+                    }
+                    _ => {}
+                }
+            }
+
+            let wake = self
+                .reliability
+                .poll_timeout()
+                .map_or(deadline, |timeout| timeout.min(deadline));
+            if now >= deadline {
+                return Err(ClientError::TimedOut);
+            }
+            if wake <= now {
+                continue;
+            }
+
+            let (source, packet) = match self.receive(wake - now)? {
+                Some(received) => received,
+                None => continue,
+            };
+
+            // Resets are reported below as well
+            self.reliability.handle_incoming(&source, &packet);
+            let now = self.start.elapsed();
+            match self.exchanges.handle_incoming(&source, &packet, now) {
+                ExchangeMatch::Response(_)
+                | ExchangeMatch::Notification(_) => {
+                    self.acknowledge(source, &packet)?;
+                    return Ok(packet);
+                }
+                ExchangeMatch::Duplicate => {
+                    self.acknowledge(source, &packet)?
+                }
+                ExchangeMatch::Unexpected(reset) => {
+                    self.socket.send_to(&reset.to_bytes()?, source)?;
+                }
+                ExchangeMatch::Reset(_) => return Err(ClientError::Reset),
+                _ => {}
+            }
+        }
+    }
+
+    /// Processes incoming messages until the deadline, collecting the
+    /// responses to the outstanding multicast request.
+    fn collect_group_responses(
+        &mut self,
+        deadline: Duration,
+    ) -> Result<Vec<(SocketAddr, Packet)>, ClientError> {
+        let mut responses = Vec::new();
+        loop {
+            let now = self.start.elapsed();
+            if now >= deadline {
+                return Ok(responses);
+            }
+            let (source, packet) = match self.receive(deadline - now)? {
+                Some(received) => received,
+                None => continue,
+            };
+
+            let now = self.start.elapsed();
+            match self.exchanges.handle_incoming(&source, &packet, now) {
+                ExchangeMatch::GroupResponse(_) => {
+                    self.acknowledge(source, &packet)?;
+                    responses.push((source, packet));
+                }
+                ExchangeMatch::Duplicate => {
+                    self.acknowledge(source, &packet)?
+                }
+                ExchangeMatch::Unexpected(reset) => {
+                    self.socket.send_to(&reset.to_bytes()?, source)?;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Waits up to a timeout for a datagram and decodes it, returning
+    /// `None` if nothing or something invalid was received.
+    fn receive(
+        &self,
+        timeout: Duration,
+    ) -> Result<Option<(SocketAddr, Packet)>, ClientError> {
+        let mut buf = [0; RECEIVE_BUFFER_SIZE];
+        self.socket.set_read_timeout(Some(timeout))?;
+        let (size, source) = match self.socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Packet::from_bytes(&buf[..size])
+            .ok()
+            .map(|packet| (source, packet)))
+    }
+
+    /// Acknowledges a packet if it is Confirmable.
+    fn acknowledge(
+        &self,
+        source: SocketAddr,
+        packet: &Packet,
+    ) -> Result<(), ClientError> {
+        if let Some(ack) = CoapResponse::empty_ack(packet) {
+            self.socket.send_to(&ack.to_bytes()?, source)?;
+        }
+        Ok(())
+    }
+}
+
+/// Resolves the host and port of a `coap` URI to a socket address.
+fn resolve(uri: &str) -> Result<SocketAddr, ClientError> {
+    coap_authority(uri)?
+        .to_socket_addrs()
+        .map_err(|_| ClientError::InvalidUri)?
+        .next()
+        .ok_or(ClientError::InvalidUri)
+}
+
+/// Returns the host and port of a `coap` URI.
+pub(crate) fn coap_authority(uri: &str) -> Result<(String, u16), ClientError> {
+    let parsed = CoapUri::parse(uri).map_err(|_| ClientError::InvalidUri)?;
+    if parsed.scheme != UriScheme::Coap {
+        return Err(ClientError::InvalidUri);
+    }
+    Ok((parsed.host, parsed.port))
+}
+
+/// What to do next in a [`BlockwiseTransfer`].
+pub(crate) enum BlockwiseStep {
+    /// The message should be exchanged with the server next.
+    Send(Packet),
+    /// The transfer is complete with the response.
+    Done(Packet),
+}
+
+/// Splits the payload of a request into Block1 blocks and reassembles the
+/// Block2 blocks of its response, independent of how the messages are
+/// exchanged with the server.
+pub(crate) struct BlockwiseTransfer<'a> {
+    request: Packet,
+    payload: &'a [u8],
+    /// The size of the Block1 blocks, if the payload is sent block-wise.
+    block_size: Option<usize>,
+    /// The length of the part of the payload that has been sent.
+    sent: usize,
+    /// The part of the response payload that has been received.
+    body: Vec<u8>,
+}
+
+impl<'a> BlockwiseTransfer<'a> {
+    /// Creates a transfer of a request, which is sent block-wise if the
+    /// payload is larger than the block size.
+    pub(crate) fn new(
+        request: Packet,
+        payload: &'a [u8],
+        block_size: usize,
+    ) -> Self {
+        Self {
+            request,
+            block_size: (payload.len() > block_size).then_some(block_size),
+            payload,
+            sent: 0,
+            body: Vec::new(),
+        }
+    }
+
+    /// Returns the first message to send.
+    pub(crate) fn first_message(&mut self) -> Result<Packet, ClientError> {
+        match self.block_size {
+            Some(size) => self.block1_message(size),
+            None => {
+                let mut message = self.request.clone();
+                message.payload = self.payload.to_vec();
+                self.sent = self.payload.len();
+                Ok(message)
+            }
+        }
+    }
+
+    /// Handles the response to the latest message.
+    pub(crate) fn handle_response(
+        &mut self,
+        mut response: Packet,
+    ) -> Result<BlockwiseStep, ClientError> {
+        if let Some(size) = self.block_size.filter(|_| self.sent_partly()) {
+            if response.header.code
+                != MessageClass::Response(ResponseType::Continue)
+            {
+                return Ok(BlockwiseStep::Done(response));
+            }
+
+            // The server may ask for smaller blocks from now on
+            let size = match response
+                .get_first_option_as::<BlockValue>(CoapOption::Block1)
+            {
+                Some(Ok(acknowledged)) => size.min(acknowledged.size()),
+                _ => size,
+            };
+            self.block_size = Some(size);
+            return self.block1_message(size).map(BlockwiseStep::Send);
+        }
+
+        let block = match response
+            .get_first_option_as::<BlockValue>(CoapOption::Block2)
+        {
+            Some(block) => block.map_err(|_| ClientError::InvalidBlock)?,
+            None if self.body.is_empty() => {
+                return Ok(BlockwiseStep::Done(response))
+            }
+            None => return Err(ClientError::InvalidBlock),
+        };
+        if usize::from(block.num) * block.size() != self.body.len() {
+            return Err(ClientError::InvalidBlock);
+        }
+        self.body.extend_from_slice(&response.payload);
+
+        if !block.more {
+            response.remove_option(CoapOption::Block2);
+            response.payload = core::mem::take(&mut self.body);
+            return Ok(BlockwiseStep::Done(response));
+        }
+
+        let next =
+            BlockValue::new(usize::from(block.num) + 1, false, block.size())
+                .map_err(|_| ClientError::InvalidBlock)?;
+        let mut message = self.request.clone();
+        message.add_option_as(CoapOption::Block2, next);
+        // FETCH requests are identified by their payload, so it has to be
+        // repeated for every block
+        if self.request.header.code
+            == MessageClass::Request(RequestType::Fetch)
+        {
+            message.payload = self.payload.to_vec();
+        }
+        Ok(BlockwiseStep::Send(message))
+    }
+
+    /// Returns whether only part of the payload has been sent.
+    fn sent_partly(&self) -> bool {
+        self.sent < self.payload.len()
+    }
+
+    /// Returns the message carrying the next block of the payload.
+    fn block1_message(&mut self, size: usize) -> Result<Packet, ClientError> {
+        let end = self.payload.len().min(self.sent + size);
+        let more = end < self.payload.len();
+        let block = BlockValue::new(self.sent / size, more, size)
+            .map_err(|_| ClientError::InvalidBlock)?;
+
+        let mut message = self.request.clone();
+        message.add_option_as(CoapOption::Block1, block);
+        message.payload = self.payload[self.sent..end].to_vec();
+        self.sent = end;
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{BlockHandler, BlockHandlerConfig, CoapRequest};
+    use std::{sync::mpsc, thread};
+
+    /// Runs a server on the loopback interface that passes every request it
+    /// receives to the handler, until it is idle for a while.
+    fn serve<F>(mut handler: F) -> SocketAddr
+    where
+        F: FnMut(&UdpSocket, SocketAddr, Packet) + Send + 'static,
+    {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; RECEIVE_BUFFER_SIZE];
+            while let Ok((size, source)) = socket.recv_from(&mut buf) {
+                let packet = Packet::from_bytes(&buf[..size]).unwrap();
+                handler(&socket, source, packet);
+            }
+        });
+        address
+    }
+
+    fn send(socket: &UdpSocket, destination: SocketAddr, packet: &Packet) {
+        socket
+            .send_to(&packet.to_bytes().unwrap(), destination)
+            .unwrap();
+    }
+
+    fn fast() -> UdpClientConfig {
+        let transmission = TransmissionParameters {
+            ack_timeout: Duration::from_millis(50),
+            max_retransmit: 2,
+            ..Default::default()
+        };
+        UdpClientConfig {
+            timeout: Duration::from_secs(1),
+            transmission,
+            block_size: 256,
+        }
+    }
+
+    #[test]
+    fn piggybacked_and_block_wise() {
+        let mut blocks = BlockHandler::new(BlockHandlerConfig::default());
+        let server = serve(move |socket, source, packet| {
+            let mut request = CoapRequest::from_packet(packet, source);
+            if !blocks.intercept_request(&mut request).unwrap() {
+                let payload = match request.get_path().as_str() {
+                    "hello" => b"world".to_vec(),
+                    "big" => (0..3000).map(|i| i as u8).collect(),
+                    "upload" | "search" => request.message.payload.clone(),
+                    _ => Vec::new(),
+                };
+                let response = request.response.as_mut().unwrap();
+                response.message.payload = payload;
+                blocks.intercept_response(&mut request).unwrap();
+            }
+            send(socket, source, &request.response.unwrap().message);
+        });
+        let base = format!("coap://{}", server);
+        let mut client = UdpClient::bind("127.0.0.1:0", fast()).unwrap();
+
+        let response = client.get(&format!("{}/hello", base)).unwrap();
+        assert_eq!(response.header.get_type(), MessageType::Acknowledgement);
+        assert_eq!(
+            response.header.code,
+            MessageClass::Response(ResponseType::Content)
+        );
+        assert_eq!(response.payload, b"world");
+
+        let big: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        let response = client.get(&format!("{}/big", base)).unwrap();
+        assert_eq!(response.payload, big);
+        assert!(response.get_option(CoapOption::Block2).is_none());
+
+        let response = client
+            .put(&format!("{}/upload", base), &big[..1000])
+            .unwrap();
+        assert_eq!(response.payload, &big[..1000]);
+
+        let response =
+            client.fetch(&format!("{}/search", base), b"query").unwrap();
+        assert_eq!(response.payload, b"query");
+    }
+
+    #[test]
+    fn retransmissions_and_separate_responses() {
+        let (acks, received_acks) = mpsc::channel();
+        let mut ignored_first = false;
+        let server = serve(move |socket, source, packet| {
+            if packet.header.get_type() == MessageType::Acknowledgement {
+                acks.send(packet.header.message_id).unwrap();
+                return;
+            }
+            let mut request = CoapRequest::from_packet(packet, source);
+            let mut response = request.response.take().unwrap();
+            match request.get_path().as_str() {
+                "flaky" if !ignored_first => ignored_first = true,
+                "flaky" => send(socket, source, &response.message),
+                _ => {
+                    let ack = CoapResponse::empty_ack(&request.message);
+                    send(socket, source, &ack.unwrap());
+                    response.make_separate(true, 0x4321);
+                    response.message.payload = b"later".to_vec();
+                    send(socket, source, &response.message);
+                }
+            }
+        });
+        let base = format!("coap://{}", server);
+        let mut client = UdpClient::bind("127.0.0.1:0", fast()).unwrap();
+
+        let response = client.get(&format!("{}/flaky", base)).unwrap();
+        assert_eq!(response.header.get_type(), MessageType::Acknowledgement);
+
+        let response = client.post(&format!("{}/slow", base), b"").unwrap();
+        assert_eq!(response.header.get_type(), MessageType::Confirmable);
+        assert_eq!(response.payload, b"later");
+        assert_eq!(
+            received_acks.recv_timeout(Duration::from_secs(1)),
+            Ok(0x4321)
+        );
+    }
+
+    #[test]
+    fn multicast_responses() {
+        let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let other_address = other.local_addr().unwrap();
+        let group = serve(move |socket, source, packet| {
+            let mut request = CoapRequest::from_packet(packet, source);
+            let mut response = request.response.take().unwrap();
+            response.message.payload = b"first".to_vec();
+            send(socket, source, &response.message);
+            send(socket, source, &response.message);
+            response.message.header.message_id += 1;
+            response.message.payload = b"second".to_vec();
+            send(&other, source, &response.message);
+        });
+        let mut client = UdpClient::bind("127.0.0.1:0", fast()).unwrap();
+
+        let uri = format!("coap://{}/.well-known/core", group);
+        let responses = client
+            .multicast(RequestType::Get, &uri, b"", Duration::from_millis(300))
+            .unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].0, group);
+        assert_eq!(responses[0].1.payload, b"first");
+        assert_eq!(
+            responses[0].1.header.get_type(),
+            MessageType::NonConfirmable
+        );
+        assert_eq!(responses[1].0, other_address);
+        assert_eq!(responses[1].1.payload, b"second");
+        assert!(client.exchanges.is_empty());
+    }
+
+    #[test]
+    fn failures() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client = UdpClient::bind("127.0.0.1:0", fast()).unwrap();
+        let uri = format!("coap://{}/", silent.local_addr().unwrap());
+        assert!(matches!(client.get(&uri), Err(ClientError::TimedOut)));
+
+        let server = serve(|socket, source, packet| {
+            send(socket, source, &Packet::reset(packet.header.message_id));
+        });
+        let uri = format!("coap://{}/", server);
+        assert!(matches!(client.delete(&uri), Err(ClientError::Reset)));
+
+        assert!(matches!(
+            client.get("coaps://127.0.0.1/"),
+            Err(ClientError::InvalidUri)
+        ));
+    }
+}
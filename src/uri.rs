@@ -0,0 +1,503 @@
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    fmt::{self, Write},
+    net::{Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use crate::{
+    error::InvalidUri,
+    option_value::{OptionValueString, OptionValueU16},
+    packet::{CoapOption, Packet},
+};
+
+/// The URI schemes of CoAP over the different transports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UriScheme {
+    /// CoAP over UDP (RFC 7252).
+    Coap,
+    /// CoAP over DTLS (RFC 7252).
+    Coaps,
+    /// CoAP over TCP (RFC 8323).
+    CoapTcp,
+    /// CoAP over TLS (RFC 8323).
+    CoapsTcp,
+    /// CoAP over WebSockets (RFC 8323).
+    CoapWs,
+    /// CoAP over secure WebSockets (RFC 8323).
+    CoapsWs,
+}
+
+impl UriScheme {
+    /// Returns the name of the scheme as it appears in a URI.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UriScheme::Coap => "coap",
+            UriScheme::Coaps => "coaps",
+            UriScheme::CoapTcp => "coap+tcp",
+            UriScheme::CoapsTcp => "coaps+tcp",
+            UriScheme::CoapWs => "coap+ws",
+            UriScheme::CoapsWs => "coaps+ws",
+        }
+    }
+
+    /// Returns the port used when a URI doesn't specify one.
+    pub fn default_port(&self) -> u16 {
+        match self {
+            UriScheme::Coap | UriScheme::CoapTcp => 5683,
+            UriScheme::Coaps | UriScheme::CoapsTcp => 5684,
+            UriScheme::CoapWs => 80,
+            UriScheme::CoapsWs => 443,
+        }
+    }
+}
+
+impl FromStr for UriScheme {
+    type Err = InvalidUri;
+
+    fn from_str(scheme: &str) -> Result<Self, Self::Err> {
+        [
+            UriScheme::Coap,
+            UriScheme::Coaps,
+            UriScheme::CoapTcp,
+            UriScheme::CoapsTcp,
+            UriScheme::CoapWs,
+            UriScheme::CoapsWs,
+        ]
+        .into_iter()
+        .find(|candidate| candidate.as_str().eq_ignore_ascii_case(scheme))
+        .ok_or(InvalidUri)
+    }
+}
+
+impl fmt::Display for UriScheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A CoAP URI, decomposed into the parts that are carried in options.
+///
+/// Parsing normalizes the URI: the scheme and host are lowercased, percent
+/// encodings are decoded, dot segments are removed and a missing port is
+/// replaced by the default port of the scheme. Formatting it reverses this,
+/// percent-encoding where necessary and leaving out the default port, so
+/// equivalent URIs compare equal and format identically.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CoapUri {
+    pub scheme: UriScheme,
+    /// The host name or IP address, without brackets for IPv6 addresses.
+    pub host: String,
+    pub port: u16,
+    /// The segments of the path, as carried in Uri-Path options.
+    pub path: Vec<String>,
+    /// The arguments of the query, as carried in Uri-Query options.
+    pub query: Vec<String>,
+}
+
+impl CoapUri {
+    /// Parses an absolute CoAP URI.
+    pub fn parse(uri: &str) -> Result<CoapUri, InvalidUri> {
+        let (scheme, rest) = uri.split_once("://").ok_or(InvalidUri)?;
+        let scheme = scheme.parse::<UriScheme>()?;
+        if rest.contains('#') {
+            return Err(InvalidUri);
+        }
+
+        let authority_end = rest.find(['/', '?']).unwrap_or(rest.len());
+        let (authority, path_and_query) = rest.split_at(authority_end);
+        if authority.contains('@') {
+            return Err(InvalidUri);
+        }
+
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(literal) => {
+                let (address, port) =
+                    literal.split_once(']').ok_or(InvalidUri)?;
+                address.parse::<Ipv6Addr>().map_err(|_| InvalidUri)?;
+                (address.to_ascii_lowercase(), port)
+            }
+            None => {
+                let host_end = authority.find(':').unwrap_or(authority.len());
+                let (host, port) = authority.split_at(host_end);
+                (percent_decode(host)?.to_ascii_lowercase(), port)
+            }
+        };
+        if host.is_empty() {
+            return Err(InvalidUri);
+        }
+        let port = match port {
+            "" | ":" => scheme.default_port(),
+            _ => port
+                .strip_prefix(':')
+                .filter(|port| port.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|port| port.parse().ok())
+                .ok_or(InvalidUri)?,
+        };
+
+        let (path, query) = parse_path_and_query(path_and_query)?;
+        Ok(CoapUri {
+            scheme,
+            host,
+            port,
+            path,
+            query,
+        })
+    }
+
+    /// Returns whether the host is an IP address rather than a host name.
+    pub fn has_ip_host(&self) -> bool {
+        self.host.parse::<Ipv4Addr>().is_ok()
+            || self.host.parse::<Ipv6Addr>().is_ok()
+    }
+
+    /// Sets the Uri-Host, Uri-Path and Uri-Query options of a request that
+    /// is sent to the host and port of this URI, as described in RFC 7252,
+    /// Section 6.4.
+    ///
+    /// Uri-Host is left out for IP addresses and Uri-Port altogether, since
+    /// they are implied by the destination of the request.
+    pub fn apply_to(&self, packet: &mut Packet) {
+        for option in [
+            CoapOption::UriHost,
+            CoapOption::UriPort,
+            CoapOption::UriPath,
+            CoapOption::UriQuery,
+        ] {
+            packet.remove_option(option);
+        }
+        if !self.has_ip_host() {
+            packet.add_option(CoapOption::UriHost, self.host.clone().into());
+        }
+        for segment in &self.path {
+            packet.add_option(CoapOption::UriPath, segment.clone().into());
+        }
+        for argument in &self.query {
+            packet.add_option(CoapOption::UriQuery, argument.clone().into());
+        }
+    }
+
+    /// Constructs the URI of a request from its options and the host and
+    /// port it was sent to, as described in RFC 7252, Section 6.5.
+    pub fn from_request(
+        scheme: UriScheme,
+        host: &str,
+        port: u16,
+        packet: &Packet,
+    ) -> Result<CoapUri, InvalidUri> {
+        let strings = |tp| {
+            packet
+                .get_options_as::<OptionValueString>(tp)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|value| value.map(|value| value.0))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| InvalidUri)
+        };
+
+        let host = match packet
+            .get_first_option_as::<OptionValueString>(CoapOption::UriHost)
+        {
+            Some(value) => value.map_err(|_| InvalidUri)?.0,
+            None => host.to_string(),
+        };
+        let port = match packet
+            .get_first_option_as::<OptionValueU16>(CoapOption::UriPort)
+        {
+            Some(value) => value.map_err(|_| InvalidUri)?.0,
+            None => port,
+        };
+        if host.is_empty() {
+            return Err(InvalidUri);
+        }
+
+        Ok(CoapUri {
+            scheme,
+            host: host.to_ascii_lowercase(),
+            port,
+            path: strings(CoapOption::UriPath)?,
+            query: strings(CoapOption::UriQuery)?,
+        })
+    }
+}
+
+impl FromStr for CoapUri {
+    type Err = InvalidUri;
+
+    fn from_str(uri: &str) -> Result<Self, Self::Err> {
+        CoapUri::parse(uri)
+    }
+}
+
+impl fmt::Display for CoapUri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}://", self.scheme)?;
+        if self.host.parse::<Ipv6Addr>().is_ok() {
+            write!(f, "[{}]", self.host)?;
+        } else {
+            percent_encode(f, &self.host, is_host_char)?;
+        }
+        if self.port != self.scheme.default_port() {
+            write!(f, ":{}", self.port)?;
+        }
+
+        // An empty first segment would turn the path into "//", which looks
+        // like the start of an authority, so it's prefixed with "/." instead
+        if self.path.first().is_some_and(|segment| segment.is_empty()) {
+            f.write_str("/.")?;
+        }
+        if self.path.is_empty() {
+            f.write_char('/')?;
+        }
+        for segment in &self.path {
+            f.write_char('/')?;
+            percent_encode(f, segment, is_pchar)?;
+        }
+
+        for (idx, argument) in self.query.iter().enumerate() {
+            f.write_char(if idx == 0 { '?' } else { '&' })?;
+            percent_encode(f, argument, |c| {
+                (is_pchar(c) || c == b'/' || c == b'?') && c != b'&'
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits the path and query of a URI into the values of the Uri-Path and
+/// Uri-Query options, percent-decoding them and removing dot segments.
+pub(crate) fn parse_path_and_query(
+    path_and_query: &str,
+) -> Result<(Vec<String>, Vec<String>), InvalidUri> {
+    let (path, query) = path_and_query
+        .split_once('?')
+        .unwrap_or((path_and_query, ""));
+
+    let mut segments = Vec::new();
+    let path = path.strip_prefix('/').unwrap_or(path);
+    if !path.is_empty() {
+        let mut raw_segments = path.split('/').peekable();
+        while let Some(segment) = raw_segments.next() {
+            let is_last = raw_segments.peek().is_none();
+            match segment {
+                "." | ".." => {
+                    if segment == ".." {
+                        segments.pop();
+                    }
+                    // A trailing dot segment still denotes a directory
+                    if is_last {
+                        segments.push(String::new());
+                    }
+                }
+                _ => segments.push(percent_decode(segment)?),
+            }
+        }
+    }
+
+    let query = if query.is_empty() {
+        Vec::new()
+    } else {
+        query
+            .split('&')
+            .map(percent_decode)
+            .collect::<Result<_, _>>()?
+    };
+
+    Ok((segments, query))
+}
+
+/// Decodes the percent-encoded octets in a URI component.
+pub(crate) fn percent_decode(encoded: &str) -> Result<String, InvalidUri> {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut rest = encoded.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        match (byte, tail) {
+            (b'%', [high, low, tail @ ..]) => {
+                let digit = |c: u8| (c as char).to_digit(16).ok_or(InvalidUri);
+                bytes.push((digit(*high)? << 4 | digit(*low)?) as u8);
+                rest = tail;
+            }
+            (b'%', _) => return Err(InvalidUri),
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| InvalidUri)
+}
+
+fn percent_encode(
+    f: &mut fmt::Formatter,
+    decoded: &str,
+    is_allowed: impl Fn(u8) -> bool,
+) -> fmt::Result {
+    for byte in decoded.bytes() {
+        if is_allowed(byte) {
+            f.write_char(byte as char)?;
+        } else {
+            write!(f, "%{:02X}", byte)?;
+        }
+    }
+    Ok(())
+}
+
+fn is_unreserved(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, b'-' | b'.' | b'_' | b'~')
+}
+
+fn is_sub_delim(c: u8) -> bool {
+    matches!(
+        c,
+        b'!' | b'$'
+            | b'&'
+            | b'\''
+            | b'('
+            | b')'
+            | b'*'
+            | b'+'
+            | b','
+            | b';'
+            | b'='
+    )
+}
+
+fn is_host_char(c: u8) -> bool {
+    is_unreserved(c) || is_sub_delim(c)
+}
+
+fn is_pchar(c: u8) -> bool {
+    is_unreserved(c) || is_sub_delim(c) || matches!(c, b':' | b'@')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn uri(
+        scheme: UriScheme,
+        host: &str,
+        port: u16,
+        path: &[&str],
+        query: &[&str],
+    ) -> CoapUri {
+        CoapUri {
+            scheme,
+            host: host.into(),
+            port,
+            path: path.iter().map(|s| s.to_string()).collect(),
+            query: query.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            CoapUri::parse("coap://[fe80::1]:5683/a%2Fb?x=1"),
+            Ok(uri(UriScheme::Coap, "fe80::1", 5683, &["a/b"], &["x=1"]))
+        );
+        assert_eq!(
+            CoapUri::parse("COAPS+TCP://Example.COM/"),
+            Ok(uri(UriScheme::CoapsTcp, "example.com", 5684, &[], &[]))
+        );
+        assert_eq!(
+            CoapUri::parse("coap+ws://h:8080/a/./b/../c/?q&r=%C3%A4"),
+            Ok(uri(
+                UriScheme::CoapWs,
+                "h",
+                8080,
+                &["a", "c", ""],
+                &["q", "r=ä"]
+            ))
+        );
+        assert_eq!(
+            "coaps+ws://10.0.0.1".parse(),
+            Ok(uri(UriScheme::CoapsWs, "10.0.0.1", 443, &[], &[]))
+        );
+
+        for invalid in [
+            "http://example.com/",
+            "coap:/example.com",
+            "/relative",
+            "coap:///path",
+            "coap://user@host/",
+            "coap://host/#fragment",
+            "coap://host:99999/",
+            "coap://host:-1/",
+            "coap://[::g]/",
+            "coap://host/%zz",
+            "coap://host/%ff",
+        ] {
+            assert_eq!(
+                CoapUri::parse(invalid),
+                Err(InvalidUri),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn format() {
+        for (input, output) in [
+            (
+                "coap://[FE80::1]:5683/a%2Fb?x=1",
+                "coap://[fe80::1]/a%2Fb?x=1",
+            ),
+            ("coap://example.com", "coap://example.com/"),
+            (
+                "coaps://example.com:5683/%7e%41",
+                "coaps://example.com:5683/~A",
+            ),
+            ("coap://h/a?b=%26&c=/?", "coap://h/a?b=%26&c=/?"),
+            ("coap://h/a%20b", "coap://h/a%20b"),
+            ("coap://h//x", "coap://h/.//x"),
+        ] {
+            let parsed = CoapUri::parse(input).unwrap();
+            assert_eq!(parsed.to_string(), output);
+            assert_eq!(CoapUri::parse(output), Ok(parsed));
+        }
+    }
+
+    #[test]
+    fn options() {
+        let mut packet = Packet::new();
+        let parsed =
+            CoapUri::parse("coap://Example.com:61616/a/b?x=1&y").unwrap();
+        parsed.apply_to(&mut packet);
+        assert_eq!(
+            packet.get_first_option(CoapOption::UriHost),
+            Some(&b"example.com"[..])
+        );
+        assert_eq!(packet.get_option(CoapOption::UriPort), None);
+        assert_eq!(packet.get_option(CoapOption::UriPath).unwrap().len(), 2);
+        assert_eq!(packet.get_option(CoapOption::UriQuery).unwrap().len(), 2);
+
+        let rebuilt = CoapUri::from_request(
+            UriScheme::Coap,
+            "192.0.2.1",
+            61616,
+            &packet,
+        )
+        .unwrap();
+        assert_eq!(rebuilt, parsed);
+
+        let mut packet = Packet::new();
+        CoapUri::parse("coap://[2001:db8::1]/")
+            .unwrap()
+            .apply_to(&mut packet);
+        assert_eq!(packet.options().len(), 0);
+        packet.add_option(CoapOption::UriPort, vec![0x16, 0x34]);
+        let rebuilt = CoapUri::from_request(
+            UriScheme::Coap,
+            "2001:db8::1",
+            5683,
+            &packet,
+        )
+        .unwrap();
+        assert_eq!(rebuilt.to_string(), "coap://[2001:db8::1]:5684/");
+    }
+}